@@ -0,0 +1,112 @@
+//! Prompt caching breakpoints.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Which parts of a request to mark with `cache_control`, so the API caches
+/// the prompt up to and including them. Later requests that start with the
+/// same prefix read it from the cache at a tenth of the input price.
+///
+/// Prefixes shorter than the model's minimum (1024 tokens for most models)
+/// are sent as usual and not cached.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheBreakpoints {
+    pub system: bool,
+    pub tools: bool,
+    /// The conversation up to the latest message, which the next turn
+    /// repeats as its prefix.
+    pub messages: bool,
+}
+
+impl CacheBreakpoints {
+    pub fn all() -> Self {
+        Self {
+            system: true,
+            tools: true,
+            messages: true,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !(self.system || self.tools || self.messages)
+    }
+}
+
+/// Adds `cache_control` to the serialized request `body` at `breakpoints`.
+pub(crate) fn mark(body: &mut Value, breakpoints: CacheBreakpoints) {
+    let mark_last = |list: Option<&mut Value>| {
+        if let Some(last) = list
+            .and_then(Value::as_array_mut)
+            .and_then(|l| l.last_mut())
+        {
+            last["cache_control"] = json!({"type": "ephemeral"});
+        }
+    };
+
+    if breakpoints.system {
+        // Only blocks take cache_control
+        if let Some(text) = body.get("system").and_then(Value::as_str) {
+            body["system"] = json!([{"type": "text", "text": text}]);
+        }
+        mark_last(body.get_mut("system"));
+    }
+    if breakpoints.tools {
+        mark_last(body.get_mut("tools"));
+    }
+    if breakpoints.messages {
+        let last = body
+            .get_mut("messages")
+            .and_then(Value::as_array_mut)
+            .and_then(|messages| messages.last_mut());
+        mark_last(last.and_then(|message| message.get_mut("content")));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ClaudeModel, Message, MessageRequest, Tool};
+
+    #[test]
+    fn test_mark_breakpoints() {
+        let tool = Tool {
+            name: "weather".into(),
+            description: None,
+            input_schema: json!({"type": "object"}),
+        };
+        let request = MessageRequest::new(
+            ClaudeModel::Claude35Sonnet,
+            1024,
+            vec![
+                Message::user("Hi"),
+                Message::assistant("Hello"),
+                Message::user("Weather?"),
+            ],
+        )
+        .system("Project notes")
+        .tools(vec![tool.clone(), tool]);
+        let mut body = serde_json::to_value(&request).unwrap();
+        mark(&mut body, CacheBreakpoints::all());
+
+        let ephemeral = json!({"type": "ephemeral"});
+        assert_eq!(
+            body["system"],
+            json!([{"type": "text", "text": "Project notes", "cache_control": ephemeral}])
+        );
+        assert!(body["tools"][0].get("cache_control").is_none());
+        assert_eq!(body["tools"][1]["cache_control"], ephemeral);
+        assert!(body["messages"][1]["content"][0]
+            .get("cache_control")
+            .is_none());
+        assert_eq!(
+            body["messages"][2]["content"][0]["cache_control"],
+            ephemeral
+        );
+
+        // Nothing changes without breakpoints
+        let mut plain = serde_json::to_value(&request).unwrap();
+        mark(&mut plain, CacheBreakpoints::default());
+        assert_eq!(plain, serde_json::to_value(&request).unwrap());
+    }
+}
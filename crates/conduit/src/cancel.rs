@@ -0,0 +1,101 @@
+use crate::ConduitError;
+use futures_util::{Stream, StreamExt};
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Cancels in-flight requests and streams from another task, e.g. a stop
+/// button. Clones share the same state.
+#[derive(Clone, Debug)]
+pub struct CancelToken {
+    state: Arc<watch::Sender<bool>>,
+}
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(watch::channel(false).0),
+        }
+    }
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.state.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.state.borrow()
+    }
+
+    /// Resolves once [`CancelToken::cancel`] has been called.
+    pub async fn cancelled(&self) {
+        let mut state = self.state.subscribe();
+        // The sender lives in `self`, so this only fails if it is dropped
+        let _ = state.wait_for(|cancelled| *cancelled).await;
+    }
+
+    /// Runs `future` unless cancelled first.
+    pub async fn run<T, F>(&self, future: F) -> Result<T, ConduitError>
+    where
+        F: Future<Output = Result<T, ConduitError>>,
+    {
+        tokio::select! {
+            biased;
+            _ = self.cancelled() => Err(ConduitError::Cancelled),
+            result = future => result,
+        }
+    }
+
+    /// Passes `stream` through until cancelled, then yields
+    /// [`ConduitError::Cancelled`] and ends.
+    pub fn wrap<T, S>(&self, stream: S) -> impl Stream<Item = Result<T, ConduitError>> + Send
+    where
+        T: Send,
+        S: Stream<Item = Result<T, ConduitError>> + Send,
+    {
+        let stream = Box::pin(stream);
+        futures_util::stream::unfold(
+            (stream, Some(self.clone())),
+            |(mut stream, token)| async move {
+                let token = token?;
+                tokio::select! {
+                    biased;
+                    _ = token.cancelled() => Some((Err(ConduitError::Cancelled), (stream, None))),
+                    item = stream.next() => item.map(|item| (item, (stream, Some(token)))),
+                }
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+
+    #[tokio::test]
+    async fn test_cancel_stream() {
+        let token = CancelToken::new();
+        let events = stream::iter([Ok(1), Ok(2)]).chain(stream::pending());
+        let mut events = Box::pin(token.wrap(events));
+
+        assert_eq!(events.next().await.unwrap().unwrap(), 1);
+        assert_eq!(events.next().await.unwrap().unwrap(), 2);
+        let cancel = token.clone();
+        tokio::spawn(async move { cancel.cancel() });
+        assert!(matches!(
+            events.next().await,
+            Some(Err(ConduitError::Cancelled))
+        ));
+        assert!(events.next().await.is_none());
+        assert!(matches!(
+            token.run(async { Ok(()) }).await,
+            Err(ConduitError::Cancelled)
+        ));
+    }
+}
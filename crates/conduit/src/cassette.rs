@@ -0,0 +1,442 @@
+//! Recorded API exchanges for tests, so the whole HTTP and streaming path
+//! runs offline and deterministically.
+//!
+//! A cassette is a JSON file under `fixtures/` holding each request and the
+//! response it got. Response bodies are kept as the chunks that arrived and
+//! the delay before each, so streams replay with their original pacing.
+//!
+//! Tests replay cassettes from a local server by default. Run them with
+//! `CONDUIT_RECORD=1` and `ANTHROPIC_API_KEY` set to forward requests to the
+//! real API instead and rewrite the cassettes. API keys never reach the
+//! files.
+//!
+//! The checked-in cassettes were written by hand in the recorder's format,
+//! so they show the API's shape rather than a real exchange. Re-record them
+//! with `CONDUIT_RECORD=1` to replace them with captured traffic.
+
+use crate::{Conduit, DEFAULT_BASE_URL};
+use futures_util::StreamExt;
+use hyperax::{
+    BodyExt, RedirectPolicy, Request, RequestBody, Response, ResponseBody, Server, ServerResponse,
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Headers holding credentials, replaced with [`REDACTED`].
+const SECRET_HEADERS: &[&str] = &["x-api-key", "authorization", "cookie", "set-cookie"];
+
+/// Headers that describe one particular transfer rather than the exchange.
+const TRANSFER_HEADERS: &[&str] = &[
+    "host",
+    "content-length",
+    "content-encoding",
+    "accept-encoding",
+    "transfer-encoding",
+    "connection",
+    "keep-alive",
+    "date",
+];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: BTreeMap<String, String>,
+    /// Parsed when the body is JSON, a string otherwise.
+    pub body: Value,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct RecordedResponse {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    pub chunks: Vec<Chunk>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Chunk {
+    /// Since the previous chunk, or since the response head for the first.
+    pub delay_ms: u64,
+    pub data: String,
+}
+
+/// Whether `CONDUIT_RECORD` asks for recording. Other values, such as `0`
+/// or an empty string, replay.
+fn recording() -> bool {
+    std::env::var("CONDUIT_RECORD").is_ok_and(|value| matches!(value.as_str(), "1" | "true"))
+}
+
+/// Path of the cassette called `name`.
+pub(crate) fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures")
+        .join(format!("{}.json", name))
+}
+
+fn scrub_headers(map: &hyperax::HeaderMap, secret: &str) -> BTreeMap<String, String> {
+    map.iter()
+        .filter(|(name, _)| !TRANSFER_HEADERS.contains(&name.as_str()))
+        .map(|(name, value)| {
+            let value = match SECRET_HEADERS.contains(&name.as_str()) {
+                true => REDACTED.to_string(),
+                false => scrub(&String::from_utf8_lossy(value.as_bytes()), secret),
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+fn scrub(text: &str, secret: &str) -> String {
+    match secret.is_empty() {
+        true => text.to_string(),
+        false => text.replace(secret, REDACTED),
+    }
+}
+
+/// Splits `buf` after its last complete UTF-8 character, so a character cut
+/// across network chunks is recorded whole in the later one.
+fn take_utf8(buf: &mut Vec<u8>) -> String {
+    let valid = match std::str::from_utf8(buf) {
+        Ok(text) => text.len(),
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        Err(_) => buf.len(),
+    };
+    let rest = buf.split_off(valid);
+    let text = String::from_utf8_lossy(buf).into_owned();
+    *buf = rest;
+    text
+}
+
+/// A local stand-in for the API, replaying or recording a cassette. The
+/// server stops when this is dropped.
+pub(crate) struct Cassette {
+    base_url: String,
+    state: Arc<State>,
+    _stop: watch::Sender<bool>,
+}
+
+struct State {
+    /// Responses still to replay, in order.
+    queue: Mutex<VecDeque<Interaction>>,
+    /// Every exchange so far, as received or as recorded.
+    log: Mutex<Vec<Interaction>>,
+    /// Where recordings are written, and the API they are made against.
+    record: Option<(PathBuf, String)>,
+    api_key: String,
+}
+
+impl Cassette {
+    /// Replays the cassette `name`, or records it when `CONDUIT_RECORD` is
+    /// `1` or `true`.
+    pub async fn open(name: &str) -> Self {
+        let path = fixture(name);
+        if recording() {
+            let api_key = std::env::var("ANTHROPIC_API_KEY")
+                .expect("ANTHROPIC_API_KEY must be set to record");
+            Self::record(path, DEFAULT_BASE_URL, api_key).await
+        } else {
+            Self::replay(Self::load(&path)).await
+        }
+    }
+
+    pub fn load(path: &Path) -> Vec<Interaction> {
+        let json = std::fs::read(path)
+            .unwrap_or_else(|e| panic!("Failed to read cassette {}: {}", path.display(), e));
+        serde_json::from_slice(&json)
+            .unwrap_or_else(|e| panic!("Invalid cassette {}: {}", path.display(), e))
+    }
+
+    /// Serves `interactions` in order, whatever the requests ask for.
+    pub async fn replay(interactions: Vec<Interaction>) -> Self {
+        Self::start(State {
+            queue: Mutex::new(interactions.into()),
+            log: Mutex::new(Vec::new()),
+            record: None,
+            api_key: "test-key".to_string(),
+        })
+        .await
+    }
+
+    /// Forwards requests to `upstream` and writes the exchanges to `path`
+    /// as they complete.
+    pub async fn record(path: PathBuf, upstream: &str, api_key: String) -> Self {
+        Self::start(State {
+            queue: Mutex::new(VecDeque::new()),
+            log: Mutex::new(Vec::new()),
+            record: Some((path, upstream.trim_end_matches('/').to_string())),
+            api_key,
+        })
+        .await
+    }
+
+    async fn start(state: State) -> Self {
+        let state = Arc::new(state);
+        let mut server = Server::new(([127, 0, 0, 1], 0).into());
+        let addr = server.bind().await.expect("Failed to bind cassette server");
+        let (stop, shutdown) = watch::channel(false);
+        let handler_state = Arc::clone(&state);
+        let handler = move |req: Request<RequestBody>| {
+            let state = Arc::clone(&handler_state);
+            async move { Ok::<_, Infallible>(state.handle(req).await) }
+        };
+        tokio::spawn(async move { server.run(handler, shutdown).await });
+        Self {
+            base_url: format!("http://{}", addr),
+            state,
+            _stop: stop,
+        }
+    }
+
+    /// A client for the cassette's server, without retries so every
+    /// recorded response is seen.
+    pub fn conduit(&self) -> Conduit {
+        Conduit::new(self.state.api_key.clone())
+            .with_base_url(&self.base_url)
+            .with_retry(crate::RetryPolicy::none())
+    }
+
+    /// Exchanges so far. In replay, requests are as received, with keys
+    /// scrubbed.
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.state.log.lock().unwrap().clone()
+    }
+
+    /// Panics if recorded responses were left unused.
+    pub fn assert_done(&self) {
+        let left = self.state.queue.lock().unwrap().len();
+        assert_eq!(left, 0, "{} recorded responses were never requested", left);
+    }
+}
+
+impl State {
+    async fn handle(self: Arc<Self>, req: Request<RequestBody>) -> Response<ResponseBody> {
+        let (parts, body) = req.into_parts();
+        let body = match body.collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(e) => return ServerResponse::new(StatusCode::BAD_REQUEST).text(e.to_string()),
+        };
+        let request = RecordedRequest {
+            method: parts.method.to_string(),
+            path: parts.uri.path().to_string(),
+            headers: scrub_headers(&parts.headers, &self.api_key),
+            body: serde_json::from_slice(&body)
+                .unwrap_or_else(|_| scrub(&String::from_utf8_lossy(&body), &self.api_key).into()),
+        };
+        match &self.record {
+            Some((_, upstream)) => {
+                let upstream = format!("{}{}", upstream, parts.uri);
+                let mut forward = Request::builder().method(parts.method).uri(upstream);
+                for (name, value) in &parts.headers {
+                    if !TRANSFER_HEADERS.contains(&name.as_str()) {
+                        forward = forward.header(name, value);
+                    }
+                }
+                match forward.body(body.to_vec()) {
+                    Ok(forward) => self.forward(request, forward).await,
+                    Err(e) => ServerResponse::new(StatusCode::BAD_REQUEST).text(e.to_string()),
+                }
+            }
+            None => self.replay(request),
+        }
+    }
+
+    fn replay(&self, request: RecordedRequest) -> Response<ResponseBody> {
+        let next = self.queue.lock().unwrap().pop_front();
+        let Some(recorded) = next else {
+            return ServerResponse::new(StatusCode::INTERNAL_SERVER_ERROR).text(format!(
+                "cassette has no response left for {} {}",
+                request.method, request.path
+            ));
+        };
+        if (&recorded.request.method, &recorded.request.path) != (&request.method, &request.path) {
+            return ServerResponse::new(StatusCode::INTERNAL_SERVER_ERROR).text(format!(
+                "cassette expected {} {}, got {} {}",
+                recorded.request.method, recorded.request.path, request.method, request.path
+            ));
+        }
+        self.log.lock().unwrap().push(Interaction {
+            request,
+            response: recorded.response.clone(),
+        });
+
+        let response = recorded.response;
+        let mut head = ServerResponse::new(
+            StatusCode::from_u16(response.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+        );
+        for (name, value) in &response.headers {
+            head = head.header(name.as_str(), value.as_str());
+        }
+        let (tx, body) = hyperax::body::channel();
+        tokio::spawn(async move {
+            for chunk in response.chunks {
+                tokio::time::sleep(Duration::from_millis(chunk.delay_ms)).await;
+                if tx.send(chunk.data).await.is_err() {
+                    break;
+                }
+            }
+        });
+        head.streaming(body)
+    }
+
+    /// Sends `forward` upstream and relays the response while recording it.
+    async fn forward(
+        self: Arc<Self>,
+        request: RecordedRequest,
+        forward: Request<Vec<u8>>,
+    ) -> Response<ResponseBody> {
+        let client = hyperax::Client::builder()
+            .redirect(RedirectPolicy::none())
+            .no_timeout()
+            .build();
+        let (head, body) = match client.stream(forward).await {
+            Ok(response) => response.into_parts(),
+            // Error responses arrive buffered
+            Err(hyperax::Error::Status(status)) => {
+                let hyperax::StatusError {
+                    code,
+                    headers,
+                    body,
+                } = *status;
+                let chunks = vec![Chunk {
+                    delay_ms: 0,
+                    data: scrub(&String::from_utf8_lossy(&body), &self.api_key),
+                }];
+                let response = RecordedResponse {
+                    status: code.as_u16(),
+                    headers: scrub_headers(&headers, &self.api_key),
+                    chunks,
+                };
+                let relayed = self.relay_head(&response).body(body);
+                self.save(Interaction { request, response });
+                return relayed;
+            }
+            Err(e) => return ServerResponse::new(StatusCode::BAD_GATEWAY).text(e.to_string()),
+        };
+
+        let mut response = RecordedResponse {
+            status: head.status.as_u16(),
+            headers: scrub_headers(&head.headers, &self.api_key),
+            chunks: Vec::new(),
+        };
+        let relayed = self.relay_head(&response);
+        let (tx, relay) = hyperax::body::channel();
+        let mut body = body.into_data_stream();
+        tokio::spawn(async move {
+            let mut pending = Vec::new();
+            let mut last = Instant::now();
+            while let Some(Ok(data)) = body.next().await {
+                // Keep recording even if the test stopped reading
+                let _ = tx.send(data.clone()).await;
+                pending.extend_from_slice(&data);
+                let text = take_utf8(&mut pending);
+                if !text.is_empty() {
+                    response.chunks.push(Chunk {
+                        delay_ms: last.elapsed().as_millis() as u64,
+                        data: scrub(&text, &self.api_key),
+                    });
+                    last = Instant::now();
+                }
+            }
+            drop(tx);
+            self.save(Interaction { request, response });
+        });
+        relayed.streaming(relay)
+    }
+
+    fn relay_head(&self, response: &RecordedResponse) -> ServerResponse {
+        let mut head = ServerResponse::new(
+            StatusCode::from_u16(response.status).unwrap_or(StatusCode::BAD_GATEWAY),
+        );
+        for (name, value) in &response.headers {
+            if !SECRET_HEADERS.contains(&name.as_str()) {
+                head = head.header(name.as_str(), value.as_str());
+            }
+        }
+        head
+    }
+
+    /// Appends `interaction` and rewrites the cassette file.
+    fn save(&self, interaction: Interaction) {
+        let mut log = self.log.lock().unwrap();
+        log.push(interaction);
+        if let Some((path, _)) = &self.record {
+            let json = serde_json::to_vec_pretty(&*log).expect("Failed to serialize cassette");
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir).expect("Failed to create fixtures directory");
+            }
+            std::fs::write(path, json)
+                .unwrap_or_else(|e| panic!("Failed to write {}: {}", path.display(), e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClaudeModel, StreamEvent};
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        // Record through a second cassette standing in for the API
+        let recorded = Cassette::load(&fixture("stream_message"));
+        let upstream = Cassette::replay(recorded.clone()).await;
+        let path = std::env::temp_dir().join(format!("conduit-{}.json", std::process::id()));
+        let recorder = Cassette::record(path.clone(), &upstream.base_url, "sk-secret".into()).await;
+
+        let events: Vec<_> = recorder
+            .conduit()
+            .stream_message("Count from 1 to 5", ClaudeModel::Claude35Sonnet, 1024)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(
+            events.last().unwrap().as_ref().unwrap(),
+            &StreamEvent::MessageStop
+        );
+        upstream.assert_done();
+        // The recording is saved once the body has been relayed
+        while recorder.interactions().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let saved = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(!saved.contains("sk-secret"));
+        let saved: Vec<Interaction> = serde_json::from_str(&saved).unwrap();
+        assert_eq!(saved[0].request, recorded[0].request);
+
+        let response = &saved[0].response;
+        assert_eq!(response.status, 200);
+        assert_eq!(response.headers, recorded[0].response.headers);
+        let text = |chunks: &[Chunk]| chunks.iter().map(|c| c.data.as_str()).collect::<String>();
+        assert_eq!(text(&response.chunks), text(&recorded[0].response.chunks));
+        // Pauses in the stream are kept
+        let paused: u64 = response.chunks.iter().map(|c| c.delay_ms).sum();
+        assert!(paused >= 100, "{:?}", response.chunks);
+    }
+
+    #[test]
+    fn test_take_utf8() {
+        let mut buf = "héllo".as_bytes()[..2].to_vec();
+        assert_eq!(take_utf8(&mut buf), "h");
+        buf.extend_from_slice(&"héllo".as_bytes()[2..]);
+        assert_eq!(take_utf8(&mut buf), "éllo");
+        assert!(buf.is_empty());
+    }
+}
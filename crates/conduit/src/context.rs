@@ -0,0 +1,234 @@
+//! Token estimates and trimming history to fit a model's context window.
+
+use crate::types::{ClaudeModel, ContentBlock, Message, MessageRequest, Role, Source};
+use serde::{Deserialize, Serialize};
+
+/// Context window sizes by model id prefix. The longest match wins.
+const CONTEXT_WINDOWS: &[(&str, u32)] = &[
+    ("claude-", 200_000),
+    ("claude-2.0", 100_000),
+    ("claude-instant", 100_000),
+];
+
+/// Used for models missing from the table.
+pub const DEFAULT_CONTEXT_WINDOW: u32 = 200_000;
+
+/// Flat estimate for an image or PDF page, whose real cost depends on its
+/// dimensions.
+const ATTACHMENT_TOKENS: u32 = 1_600;
+
+/// Role markers and separators around each message.
+const MESSAGE_OVERHEAD: u32 = 4;
+
+impl ClaudeModel {
+    /// Input plus output tokens the model can attend to.
+    pub fn context_window(&self) -> u32 {
+        CONTEXT_WINDOWS
+            .iter()
+            .filter(|(prefix, _)| self.id().starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(DEFAULT_CONTEXT_WINDOW, |(_, tokens)| *tokens)
+    }
+}
+
+/// Rough token count for `text`. Claude's tokenizer averages a little over
+/// three characters per token on prose and less on code, so this errs high.
+pub fn estimate_tokens(text: &str) -> u32 {
+    (text.chars().count() as u32).div_ceil(3)
+}
+
+pub fn estimate_message(message: &Message) -> u32 {
+    MESSAGE_OVERHEAD + message.content.iter().map(estimate_block).sum::<u32>()
+}
+
+fn estimate_block(block: &ContentBlock) -> u32 {
+    match block {
+        ContentBlock::Text { text } => estimate_tokens(text),
+        ContentBlock::Document {
+            source: Source::Text { data, .. },
+            ..
+        } => estimate_tokens(data),
+        ContentBlock::Image { .. } | ContentBlock::Document { .. } => ATTACHMENT_TOKENS,
+        ContentBlock::ToolUse { name, input, .. } => {
+            estimate_tokens(name) + estimate_tokens(&input.to_string())
+        }
+        ContentBlock::ToolResult { content, .. } => content.iter().map(estimate_block).sum(),
+        ContentBlock::Thinking { thinking, .. } => estimate_tokens(thinking),
+        ContentBlock::RedactedThinking { data } => estimate_tokens(data),
+        ContentBlock::Unknown => 0,
+    }
+}
+
+/// Estimated input tokens for `request`: system prompt, tools and messages.
+pub fn estimate_request(request: &MessageRequest) -> u32 {
+    let system = request.system.as_deref().map_or(0, estimate_tokens);
+    let tools = match request.tools.is_empty() {
+        true => 0,
+        false => serde_json::to_string(&request.tools).map_or(0, |json| estimate_tokens(&json)),
+    };
+    system + tools + request.messages.iter().map(estimate_message).sum::<u32>()
+}
+
+/// Input tokens `request` may use and still leave room for `max_tokens` of
+/// output.
+pub fn input_budget(request: &MessageRequest) -> u32 {
+    request
+        .model
+        .context_window()
+        .saturating_sub(request.max_tokens)
+}
+
+/// How history is shortened once it no longer fits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrimStrategy {
+    /// Drop the oldest messages.
+    #[default]
+    DropOldest,
+    /// Drop the oldest messages that are not pinned.
+    KeepPinned,
+    /// Replace the oldest unpinned messages with a summary written by the
+    /// model.
+    Summarize,
+}
+
+/// Indices of the messages to drop, oldest first, for the request to fit
+/// its budget. The latest message is always kept. `pinned` runs parallel
+/// to `request.messages`; missing entries count as unpinned.
+pub(crate) fn overflow(
+    request: &MessageRequest,
+    pinned: &[bool],
+    keep_pinned: bool,
+    budget: u32,
+) -> Vec<usize> {
+    let mut used = estimate_request(request);
+    let is_pinned = |i: usize| keep_pinned && pinned.get(i).copied().unwrap_or(false);
+    let last = request.messages.len().saturating_sub(1);
+
+    let mut drops = Vec::new();
+    let mut candidates = (0..last).filter(|&i| !is_pinned(i)).peekable();
+    while used > budget {
+        let Some(i) = candidates.next() else { break };
+        used -= estimate_message(&request.messages[i]);
+        drops.push(i);
+    }
+    // History has to start with the user, so don't leave an assistant reply
+    // at the front
+    if let (Some(&i), Some(&next)) = (drops.last(), candidates.peek()) {
+        let starts_with_assistant = request.messages[next].role == Role::Assistant;
+        if next == i + 1 && starts_with_assistant && (0..next).all(|j| drops.contains(&j)) {
+            drops.push(next);
+        }
+    }
+    drops
+}
+
+/// Removes the messages at `indices`, returning them in order.
+pub(crate) fn remove(request: &mut MessageRequest, indices: &[usize]) -> Vec<Message> {
+    let mut removed = Vec::with_capacity(indices.len());
+    let mut kept = Vec::with_capacity(request.messages.len());
+    for (i, message) in request.messages.drain(..).enumerate() {
+        match indices.contains(&i) {
+            true => removed.push(message),
+            false => kept.push(message),
+        }
+    }
+    request.messages = kept;
+    removed
+}
+
+/// `messages` as plain text for the model to summarize.
+pub(crate) fn transcript(messages: &[Message]) -> String {
+    let turns = messages.iter().map(|message| {
+        let speaker = match message.role {
+            Role::User => "User",
+            Role::Assistant => "Assistant",
+        };
+        let text: String = message
+            .content
+            .iter()
+            .filter_map(ContentBlock::as_text)
+            .collect();
+        format!("{}: {}", speaker, text)
+    });
+    turns.collect::<Vec<_>>().join("\n\n")
+}
+
+/// Drops history from `request` until it fits, without calling the model.
+/// `Summarize` falls back to keeping pinned messages. Returns how many
+/// messages were dropped.
+pub fn trim(request: &mut MessageRequest, pinned: &[bool], strategy: TrimStrategy) -> usize {
+    trim_to(request, pinned, strategy, input_budget(request))
+}
+
+/// Like [`trim`], but against `budget` input tokens rather than the model's
+/// window, for when the API rejected a request the estimate let through.
+/// A budget of 0 keeps only the latest message and, unless dropping the
+/// oldest, the pinned ones.
+pub fn trim_to(
+    request: &mut MessageRequest,
+    pinned: &[bool],
+    strategy: TrimStrategy,
+    budget: u32,
+) -> usize {
+    let keep_pinned = strategy != TrimStrategy::DropOldest;
+    let drops = overflow(request, pinned, keep_pinned, budget);
+    remove(request, &drops).len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversation(turns: usize, words: usize) -> MessageRequest {
+        let messages = (0..turns * 2)
+            .map(|i| {
+                let text = format!("{} ", i).repeat(words);
+                match i % 2 {
+                    0 => Message::user(text),
+                    _ => Message::assistant(text),
+                }
+            })
+            .collect();
+        MessageRequest::new(ClaudeModel::Custom("claude-2.0".into()), 4_000, messages)
+    }
+
+    #[test]
+    fn test_context_windows() {
+        assert_eq!(ClaudeModel::Claude35Sonnet.context_window(), 200_000);
+        assert_eq!(
+            ClaudeModel::Custom("claude-2.0".into()).context_window(),
+            100_000
+        );
+        assert_eq!(estimate_tokens("abcdefg"), 3);
+    }
+
+    #[test]
+    fn test_trim_history() {
+        // 40 messages of ~5k tokens each against a 96k budget
+        let request = conversation(20, 7_500);
+        assert!(estimate_request(&request) > input_budget(&request));
+
+        let mut dropped = request.clone();
+        let count = trim(&mut dropped, &[], TrimStrategy::DropOldest);
+        assert!(estimate_request(&dropped) <= input_budget(&dropped));
+        assert_eq!(dropped.messages[0].role, Role::User);
+        assert_eq!(dropped.messages.last(), request.messages.last());
+        assert_eq!(count % 2, 0);
+
+        // The pinned first message survives
+        let mut pinned = request.clone();
+        trim(&mut pinned, &[true], TrimStrategy::KeepPinned);
+        assert_eq!(pinned.messages[0], request.messages[0]);
+        assert!(estimate_request(&pinned) <= input_budget(&pinned));
+
+        // Nothing to do when it fits
+        let mut small = conversation(2, 10);
+        assert_eq!(trim(&mut small, &[], TrimStrategy::DropOldest), 0);
+
+        // Unless the budget is forced down to the pins and the prompt
+        let pins = [false, false, true];
+        assert_eq!(trim_to(&mut small, &pins, TrimStrategy::KeepPinned, 0), 2);
+        assert_eq!(small.messages, conversation(2, 10).messages[2..]);
+    }
+}
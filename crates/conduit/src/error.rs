@@ -0,0 +1,345 @@
+use crate::types::{ApiError, ErrorKind, ErrorResponse};
+use hyperax::retry::STATUS_OVERLOADED;
+use hyperax::{HeaderMap, StatusCode};
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+/// Rate limit families reported in `anthropic-ratelimit-*` headers.
+const RATE_LIMITS: [&str; 4] = ["requests", "tokens", "input-tokens", "output-tokens"];
+
+#[derive(Debug)]
+pub enum ConduitError {
+    /// The API key is missing, malformed or revoked.
+    AuthenticationFailed {
+        message: String,
+    },
+    /// Too many requests or tokens. `reset_at` is when the exhausted limit
+    /// refills, from the `anthropic-ratelimit-*-reset` headers.
+    RateLimited {
+        message: String,
+        retry_after: Option<Duration>,
+        reset_at: Option<SystemTime>,
+    },
+    /// The API is temporarily overloaded.
+    Overloaded {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    /// The conversation does not fit the model's context window. The token
+    /// counts are filled in when the API reports them.
+    ContextTooLong {
+        message: String,
+        tokens: Option<u32>,
+        limit: Option<u32>,
+    },
+    /// The request was rejected. `field` is the offending parameter path,
+    /// such as `max_tokens` or `messages.0.content`, when the API names one.
+    InvalidRequest {
+        field: Option<String>,
+        message: String,
+    },
+    /// Any other API error, either as an HTTP error response or as an
+    /// `error` event in the middle of a stream (`status` is `None`).
+    Api {
+        status: Option<StatusCode>,
+        error: ApiError,
+    },
+    /// The connection failed or broke off.
+    Network(Box<hyperax::Error>),
+    /// No response, or no stream event, arrived in time.
+    Timeout,
+    /// The request was cancelled with a [`CancelToken`](crate::CancelToken).
+    Cancelled,
+    Json(serde_json::Error),
+    /// The event stream was malformed.
+    Stream(String),
+    EmptyResponse,
+}
+
+impl fmt::Display for ConduitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConduitError::AuthenticationFailed { message } => {
+                write!(f, "Authentication failed: {}", message)
+            }
+            ConduitError::RateLimited { message, .. } => write!(f, "Rate limited: {}", message),
+            ConduitError::Overloaded { message, .. } => write!(f, "API overloaded: {}", message),
+            ConduitError::ContextTooLong { message, .. } => {
+                write!(f, "Context too long: {}", message)
+            }
+            ConduitError::InvalidRequest { message, .. } => {
+                write!(f, "Invalid request: {}", message)
+            }
+            ConduitError::Api {
+                status: Some(status),
+                error,
+            } => write!(f, "API error ({}): {}", status.as_u16(), error.message),
+            ConduitError::Api { error, .. } => write!(f, "API error: {}", error.message),
+            ConduitError::Network(e) => write!(f, "Network error: {}", e),
+            ConduitError::Timeout => write!(f, "Timed out waiting for the API"),
+            ConduitError::Cancelled => write!(f, "Cancelled"),
+            ConduitError::Json(e) => write!(f, "Invalid API response: {}", e),
+            ConduitError::Stream(e) => write!(f, "Stream error: {}", e),
+            ConduitError::EmptyResponse => write!(f, "Empty response from API"),
+        }
+    }
+}
+
+impl Error for ConduitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConduitError::Network(e) => Some(e),
+            ConduitError::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl ConduitError {
+    /// Classifies an API error. `headers` are those of the HTTP response,
+    /// when there was one.
+    pub(crate) fn from_api(
+        status: Option<StatusCode>,
+        error: ApiError,
+        headers: Option<&HeaderMap>,
+    ) -> Self {
+        let retry_after = headers.and_then(hyperax::retry::retry_after);
+        let status_is = |code: u16| status.is_some_and(|s| s.as_u16() == code);
+        match error.kind {
+            ErrorKind::AuthenticationError => ConduitError::AuthenticationFailed {
+                message: error.message,
+            },
+            ErrorKind::RateLimitError => ConduitError::RateLimited {
+                message: error.message,
+                retry_after,
+                reset_at: headers.and_then(rate_limit_reset),
+            },
+            ErrorKind::OverloadedError => ConduitError::Overloaded {
+                message: error.message,
+                retry_after,
+            },
+            ErrorKind::RequestTooLarge => context_too_long(error.message),
+            ErrorKind::InvalidRequestError if is_context_overflow(&error.message) => {
+                context_too_long(error.message)
+            }
+            ErrorKind::InvalidRequestError => ConduitError::InvalidRequest {
+                field: field(&error.message),
+                message: error.message,
+            },
+            // Proxies and gateways answer without an Anthropic error body
+            _ if status_is(401) => ConduitError::AuthenticationFailed {
+                message: error.message,
+            },
+            _ if status_is(429) => ConduitError::RateLimited {
+                message: error.message,
+                retry_after,
+                reset_at: headers.and_then(rate_limit_reset),
+            },
+            _ if status_is(STATUS_OVERLOADED) => ConduitError::Overloaded {
+                message: error.message,
+                retry_after,
+            },
+            _ => ConduitError::Api { status, error },
+        }
+    }
+
+    /// Whether the request may succeed if sent again later.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ConduitError::RateLimited { .. } | ConduitError::Overloaded { .. } => true,
+            ConduitError::Network(e) => e.is_transient(),
+            _ => false,
+        }
+    }
+
+    /// How long to wait before retrying, when the API said so: the
+    /// `Retry-After` delay, or else the time until the rate limit resets.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ConduitError::RateLimited {
+                retry_after,
+                reset_at,
+                ..
+            } => retry_after.or_else(|| {
+                reset_at.map(|at| {
+                    at.duration_since(SystemTime::now())
+                        .unwrap_or(Duration::ZERO)
+                })
+            }),
+            ConduitError::Overloaded { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl From<hyperax::Error> for ConduitError {
+    fn from(error: hyperax::Error) -> Self {
+        let Some(status) = error.status() else {
+            return match error {
+                hyperax::Error::Http(e) if e.is_timeout() => ConduitError::Timeout,
+                error => ConduitError::Network(Box::new(error)),
+            };
+        };
+        let api_error = error
+            .json_body::<ErrorResponse>()
+            .map(|body| body.error)
+            .unwrap_or_else(|| ApiError {
+                kind: ErrorKind::Other,
+                message: status.canonical_reason().unwrap_or("unknown").to_string(),
+            });
+        let headers = match &error {
+            hyperax::Error::Status(status) => Some(&status.headers),
+            _ => None,
+        };
+        ConduitError::from_api(Some(status), api_error, headers)
+    }
+}
+
+impl From<serde_json::Error> for ConduitError {
+    fn from(error: serde_json::Error) -> Self {
+        ConduitError::Json(error)
+    }
+}
+
+fn context_too_long(message: String) -> ConduitError {
+    // "prompt is too long: 210000 tokens > 200000 maximum"
+    let mut numbers = message
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|n| n.parse().ok());
+    let (tokens, limit) = match (numbers.next(), numbers.next()) {
+        (Some(tokens), Some(limit)) if tokens > limit => (Some(tokens), Some(limit)),
+        _ => (None, None),
+    };
+    ConduitError::ContextTooLong {
+        message,
+        tokens,
+        limit,
+    }
+}
+
+fn is_context_overflow(message: &str) -> bool {
+    let message = message.to_lowercase();
+    ["prompt is too long", "context window", "context length"]
+        .iter()
+        .any(|needle| message.contains(needle))
+}
+
+/// The parameter path a validation message starts with, as in
+/// `messages.1.content: Field required`.
+fn field(message: &str) -> Option<String> {
+    let (path, _) = message.split_once(": ")?;
+    let is_path = !path.is_empty()
+        && path
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '[' | ']'));
+    is_path.then(|| path.to_string())
+}
+
+/// The latest reset time among the rate limits that are used up.
+fn rate_limit_reset(headers: &HeaderMap) -> Option<SystemTime> {
+    RATE_LIMITS
+        .iter()
+        .filter(|limit| {
+            let remaining = format!("anthropic-ratelimit-{}-remaining", limit);
+            headers
+                .get(remaining.as_str())
+                .and_then(|v| v.to_str().ok())
+                == Some("0")
+        })
+        .filter_map(|limit| {
+            let reset = format!("anthropic-ratelimit-{}-reset", limit);
+            let value = headers.get(reset.as_str())?.to_str().ok()?;
+            humantime::parse_rfc3339_weak(value).ok()
+        })
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api(kind: ErrorKind, message: &str) -> ApiError {
+        ApiError {
+            kind,
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_classify_api_errors() {
+        let error = ConduitError::from_api(
+            Some(StatusCode::BAD_REQUEST),
+            api(
+                ErrorKind::InvalidRequestError,
+                "prompt is too long: 210000 tokens > 200000 maximum",
+            ),
+            None,
+        );
+        assert!(matches!(
+            error,
+            ConduitError::ContextTooLong {
+                tokens: Some(210000),
+                limit: Some(200000),
+                ..
+            }
+        ));
+
+        let error = ConduitError::from_api(
+            Some(StatusCode::BAD_REQUEST),
+            api(
+                ErrorKind::InvalidRequestError,
+                "max_tokens: Input should be greater than or equal to 1",
+            ),
+            None,
+        );
+        match error {
+            ConduitError::InvalidRequest { field, .. } => {
+                assert_eq!(field.as_deref(), Some("max_tokens"))
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+
+        // A gateway's 401 without an Anthropic body
+        let error = ConduitError::from_api(
+            Some(StatusCode::UNAUTHORIZED),
+            api(ErrorKind::Other, "Unauthorized"),
+            None,
+        );
+        assert!(matches!(error, ConduitError::AuthenticationFailed { .. }));
+
+        // Overloaded mid-stream
+        let error = ConduitError::from_api(None, api(ErrorKind::OverloadedError, "Busy"), None);
+        assert!(error.is_retryable());
+    }
+
+    #[test]
+    fn test_rate_limit_reset() {
+        let reset = SystemTime::now() + Duration::from_secs(30);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "anthropic-ratelimit-requests-remaining",
+            "12".parse().unwrap(),
+        );
+        headers.insert(
+            "anthropic-ratelimit-requests-reset",
+            "2000-01-01T00:00:00Z".parse().unwrap(),
+        );
+        headers.insert("anthropic-ratelimit-tokens-remaining", "0".parse().unwrap());
+        headers.insert(
+            "anthropic-ratelimit-tokens-reset",
+            humantime::format_rfc3339_seconds(reset)
+                .to_string()
+                .parse()
+                .unwrap(),
+        );
+
+        let error = ConduitError::from_api(
+            Some(StatusCode::TOO_MANY_REQUESTS),
+            api(ErrorKind::RateLimitError, "Too many tokens"),
+            Some(&headers),
+        );
+        let wait = error.retry_after().unwrap();
+        assert!(wait > Duration::from_secs(25) && wait <= Duration::from_secs(30));
+    }
+}
@@ -1,65 +1,227 @@
-// Re-export types from mesh that we use publicly
-use futures_util::StreamExt;
-pub use mesh::anthropic::{
-    client::Client,
-    completion::{
-        message::{Content, ContentType, Message, MessageRequest, Role},
-        stream::StreamEvent,
-    },
-    config::Config,
-    error::AnthropicError,
-    models::claude::ClaudeModel,
+//! A native client for the Anthropic Messages API, built on hyperax.
+
+mod cache;
+mod cancel;
+#[cfg(test)]
+mod cassette;
+mod context;
+mod error;
+mod sampling;
+mod stream;
+mod types;
+mod usage;
+
+pub use cache::CacheBreakpoints;
+pub use cancel::CancelToken;
+pub use context::{
+    estimate_message, estimate_request, estimate_tokens, input_budget, trim, trim_to, TrimStrategy,
+    DEFAULT_CONTEXT_WINDOW,
 };
-use std::error::Error;
+pub use error::ConduitError;
+pub use hyperax::RetryPolicy;
+pub use sampling::Sampling;
+pub use stream::{Delta, MessageDelta, StreamEvent};
+pub use types::{
+    ApiError, ClaudeModel, ContentBlock, ErrorKind, Message, MessageRequest, MessageResponse,
+    Metadata, Role, Source, StopReason, ThinkingConfig, Tool, ToolChoice,
+};
+pub use usage::{Pricing, Usage};
+
+use futures_util::Stream;
+use hyperax::{BodyExt, RedirectPolicy, Request};
 use std::fmt;
+use std::time::Duration;
+
+pub const DEFAULT_BASE_URL: &str = "https://api.anthropic.com";
+const API_VERSION: &str = "2023-06-01";
 
-#[derive(Debug)]
-pub enum ConduitError {
-    ApiError(AnthropicError),
-    EmptyResponse,
+/// Output budget for the summary written by [`TrimStrategy::Summarize`].
+const SUMMARY_TOKENS: u32 = 1024;
+
+#[derive(Clone)]
+pub struct Conduit {
+    client: hyperax::Client,
+    api_key: String,
+    base_url: String,
+    retry: RetryPolicy,
+    timeout: Duration,
 }
 
-impl std::fmt::Display for ConduitError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ConduitError::ApiError(e) => write!(f, "API error: {}", e),
-            ConduitError::EmptyResponse => write!(f, "Empty response from API"),
-        }
+impl fmt::Debug for Conduit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Conduit")
+            .field("base_url", &self.base_url)
+            .finish()
     }
 }
 
-impl Error for ConduitError {}
+impl Conduit {
+    /// Creates a new Conduit instance with the provided API key. Redirects
+    /// are not followed, so the key is never sent to another host.
+    pub fn new(api_key: impl Into<String>) -> Self {
+        Self {
+            client: hyperax::Client::builder()
+                .redirect(RedirectPolicy::none())
+                .build(),
+            api_key: api_key.into(),
+            base_url: DEFAULT_BASE_URL.to_string(),
+            retry: RetryPolicy::default(),
+            timeout: Duration::from_secs(600),
+        }
+    }
 
-impl From<AnthropicError> for ConduitError {
-    fn from(error: AnthropicError) -> Self {
-        ConduitError::ApiError(error)
+    /// Sends requests through `client`, e.g. one with a proxy or a mock
+    /// transport. Its redirect policy should not forward `x-api-key`.
+    pub fn with_client(mut self, client: hyperax::Client) -> Self {
+        self.client = client;
+        self
     }
-}
 
-pub struct Conduit {
-    client: Client,
-    config: Config,
-}
+    pub fn with_base_url(mut self, url: impl Into<String>) -> Self {
+        self.base_url = url.into().trim_end_matches('/').to_string();
+        self
+    }
 
-impl Clone for Conduit {
-    fn clone(&self) -> Self {
-        // Create a new instance with the same config
-        Self::new(self.config.api_key.to_string()).unwrap()
+    /// Replaces the retry policy used for rate limited and overloaded errors
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
     }
-}
 
-impl fmt::Debug for Conduit {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Conduit").finish()
+    /// How long to wait for a response, and between events of a stream,
+    /// before failing with [`ConduitError::Timeout`]. Ten minutes by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
     }
-}
 
-impl Conduit {
-    /// Creates a new Conduit instance with the provided API key
-    pub fn new(api_key: impl Into<String>) -> Result<Self, ConduitError> {
-        let config = Config::new(api_key.into());
-        let client = Client::new(config.clone()).map_err(ConduitError::from)?;
-        Ok(Self { client, config })
+    /// Returns how long to wait before retrying a failed request, or `None`
+    /// if the error is permanent or the attempt budget is spent.
+    ///
+    /// `attempt` is the zero based number of the attempt that failed.
+    pub fn retry_delay(&self, error: &ConduitError, attempt: u32) -> Option<Duration> {
+        if error.is_retryable() {
+            self.retry.next_delay(attempt, error.retry_after())
+        } else {
+            None
+        }
+    }
+
+    fn request(&self, body: &MessageRequest) -> Result<Request<Vec<u8>>, ConduitError> {
+        if self.api_key.trim().is_empty() {
+            return Err(ConduitError::AuthenticationFailed {
+                message: "no API key configured".to_string(),
+            });
+        }
+        body.sampling.validate()?;
+        let cache = body.cache;
+        let mut body = serde_json::to_value(body)?;
+        if !cache.is_empty() {
+            cache::mark(&mut body, cache);
+        }
+        let body = serde_json::to_vec(&body)?;
+        Request::post(format!("{}/v1/messages", self.base_url))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", API_VERSION)
+            .header("content-type", "application/json")
+            .body(body)
+            .map_err(|e| ConduitError::Network(Box::new(e.into())))
+    }
+
+    /// Sends `request` once and returns the complete response.
+    pub async fn create_message(
+        &self,
+        request: &MessageRequest,
+    ) -> Result<MessageResponse, ConduitError> {
+        let request = self.request(request)?;
+        let response = tokio::time::timeout(self.timeout, self.client.request(request))
+            .await
+            .map_err(|_| ConduitError::Timeout)??;
+        Ok(serde_json::from_slice(response.body())?)
+    }
+
+    /// Sends `request` with streaming enabled and returns its events as
+    /// they arrive.
+    pub async fn stream(
+        &self,
+        request: &MessageRequest,
+    ) -> Result<impl Stream<Item = Result<StreamEvent, ConduitError>> + Send, ConduitError> {
+        let request = MessageRequest {
+            stream: true,
+            ..request.clone()
+        };
+        let request = self.request(&request)?;
+        let response = tokio::time::timeout(self.timeout, self.client.stream(request))
+            .await
+            .map_err(|_| ConduitError::Timeout)??;
+        let events = stream::events(response.into_body().into_data_stream());
+        Ok(stream::idle_timeout(events, self.timeout))
+    }
+
+    /// Shortens `request.messages` until it fits the model's context window
+    /// with room for `max_tokens` of output, and returns how many messages
+    /// were removed along with the tokens spent doing so. `pinned` runs
+    /// parallel to the messages.
+    ///
+    /// [`TrimStrategy::Summarize`] asks the model to summarize the messages
+    /// it removes and puts the summary at the start of the history.
+    pub async fn fit_context(
+        &self,
+        request: &mut MessageRequest,
+        pinned: &[bool],
+        strategy: TrimStrategy,
+    ) -> Result<(usize, Usage), ConduitError> {
+        let budget = input_budget(request);
+        self.fit_context_to(request, pinned, strategy, budget).await
+    }
+
+    /// Like [`Conduit::fit_context`], but against `budget` input tokens; see
+    /// [`trim_to`].
+    pub async fn fit_context_to(
+        &self,
+        request: &mut MessageRequest,
+        pinned: &[bool],
+        strategy: TrimStrategy,
+        budget: u32,
+    ) -> Result<(usize, Usage), ConduitError> {
+        if strategy != TrimStrategy::Summarize {
+            let dropped = context::trim_to(request, pinned, strategy, budget);
+            return Ok((dropped, Usage::default()));
+        }
+        let drops = context::overflow(request, pinned, true, budget);
+        if drops.is_empty() {
+            return Ok((0, Usage::default()));
+        }
+        let mut pinned: Vec<bool> = (0..request.messages.len())
+            .filter(|i| !drops.contains(i))
+            .map(|i| pinned.get(i).copied().unwrap_or(false))
+            .collect();
+        let removed = context::remove(request, &drops);
+
+        let prompt = format!(
+            "Summarize this conversation so it can continue without the original. \
+             Keep facts, decisions, names and open questions; leave out pleasantries.\n\n{}",
+            context::transcript(&removed)
+        );
+        let summary = MessageRequest::new(
+            request.model.clone(),
+            SUMMARY_TOKENS,
+            vec![Message::user(prompt)],
+        );
+        let response = self.create_message(&summary).await?;
+        let summary = response.text();
+        request.messages.insert(
+            0,
+            Message::user(format!(
+                "Summary of the earlier conversation:\n\n{}",
+                summary
+            )),
+        );
+        pinned.insert(0, true);
+
+        // In case the summary itself doesn't fit
+        let dropped = context::trim(request, &pinned, TrimStrategy::KeepPinned);
+        Ok((removed.len() + dropped, response.usage))
     }
 
     /// Sends a message to Claude and returns the response
@@ -69,33 +231,22 @@ impl Conduit {
         model: ClaudeModel,
         max_tokens: u32,
     ) -> Result<String, ConduitError> {
-        unsafe {
-            // Create a safe message structure
-            let content = Content {
-                content_type: ContentType::Text,
-                text: prompt.into(),
-            };
-
-            let message = Message {
-                role: Role::User,
-                content: vec![content],
-            };
-
-            // Create a safe request structure
-            let mut request = MessageRequest::default();
-            request.model = model;
-            request.max_tokens = max_tokens;
-            request.messages = vec![message];
-
-            // Send the request and handle the response
-            let response = self.client.create_message(request).await?;
-
-            // Safely extract the response text
-            response
-                .content
-                .get(0)
-                .ok_or(ConduitError::EmptyResponse)
-                .map(|c| c.text.clone())
+        let request = MessageRequest::new(model, max_tokens, vec![Message::user(prompt)]);
+        let mut attempt = 0;
+        loop {
+            match self.create_message(&request).await {
+                Err(e) => match self.retry_delay(&e, attempt) {
+                    Some(delay) => {
+                        tokio::time::sleep(delay).await;
+                        attempt += 1;
+                    }
+                    None => return Err(e),
+                },
+                Ok(response) if response.content.is_empty() => {
+                    return Err(ConduitError::EmptyResponse)
+                }
+                Ok(response) => return Ok(response.text()),
+            }
         }
     }
 
@@ -105,83 +256,163 @@ impl Conduit {
         prompt: impl Into<String>,
         model: ClaudeModel,
         max_tokens: u32,
-    ) -> Result<impl StreamExt<Item = Result<StreamEvent, AnthropicError>>, ConduitError> {
-        unsafe {
-            // Create message structure
-            let content = Content {
-                content_type: ContentType::Text,
-                text: prompt.into(),
-            };
-
-            let message = Message {
-                role: Role::User,
-                content: vec![content],
-            };
-
-            // Create request structure
-            let mut request = MessageRequest::default();
-            request.model = model;
-            request.max_tokens = max_tokens;
-            request.stream = true;
-            request.messages = vec![message];
-
-            eprintln!(
-                "Conduit - Sending stream request with prompt: {:?}",
-                request
-            );
-
-            // Send the streaming request
-            eprintln!("Conduit - About to send request to API");
-            let raw_stream = self.client.stream_message(request).await?;
-            eprintln!("Conduit - Got raw stream from API");
-
-            // Convert the raw stream into a mapped stream with explicit event handling
-            use futures_util::{Stream, StreamExt, TryStreamExt};
-            use std::pin::Pin;
-            use std::task::{Context, Poll};
-
-            struct TrackedStream<S> {
-                inner: S,
-            }
-
-            impl<S: Stream + Unpin> Stream for TrackedStream<S> {
-                type Item = S::Item;
-
-                fn poll_next(
-                    mut self: Pin<&mut Self>,
-                    cx: &mut Context<'_>,
-                ) -> Poll<Option<Self::Item>> {
-                    match self.inner.poll_next_unpin(cx) {
-                        Poll::Ready(Some(item)) => {
-                            eprintln!("Conduit - Got stream item");
-                            Poll::Ready(Some(item))
-                        }
-                        Poll::Ready(None) => {
-                            eprintln!("Conduit - Stream ended");
-                            Poll::Ready(None)
-                        }
-                        Poll::Pending => {
-                            eprintln!("Conduit - Stream pending");
-                            Poll::Pending
-                        }
-                    }
-                }
-            }
-
-            let tracked = TrackedStream { inner: raw_stream };
-            Ok(Box::pin(tracked))
-        }
+    ) -> Result<impl Stream<Item = Result<StreamEvent, ConduitError>> + Send, ConduitError> {
+        let request = MessageRequest::new(model, max_tokens, vec![Message::user(prompt)]);
+        self.stream(&request).await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use cassette::Cassette;
+    use futures_util::StreamExt;
+    use hyperax::transport::MockTransport;
+    use hyperax::{Method, Response};
+
+    fn mock_conduit(mock: &MockTransport) -> Conduit {
+        let client = hyperax::Client::builder().transport(mock.clone()).build();
+        Conduit::new("test-key")
+            .with_client(client)
+            .with_retry(RetryPolicy::none())
+    }
+
+    #[tokio::test]
+    async fn test_create_message_mock() {
+        let mock = MockTransport::new();
+        mock.expect(Method::POST, "/v1/messages").respond(
+            200,
+            r#"{"id":"msg_1","type":"message","role":"assistant","model":"claude-3-5-sonnet-latest",
+                "content":[{"type":"text","text":"Hello!"}],"stop_reason":"end_turn",
+                "usage":{"input_tokens":3,"output_tokens":2}}"#,
+        );
+        let conduit = mock_conduit(&mock);
+
+        let reply = conduit
+            .send_message("Say hello", ClaudeModel::Claude35Sonnet, 64)
+            .await
+            .unwrap();
+        assert_eq!(reply, "Hello!");
+
+        let sent = &mock.requests()[0];
+        assert_eq!(sent.headers()["x-api-key"], "test-key");
+        assert_eq!(sent.headers()["anthropic-version"], API_VERSION);
+        let body: serde_json::Value = serde_json::from_slice(sent.body()).unwrap();
+        assert_eq!(body["model"], "claude-3-5-sonnet-latest");
+        assert!(body.get("stream").is_none());
+        mock.assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_api_errors_mock() {
+        let mock = MockTransport::new();
+        mock.expect(Method::POST, "/v1/messages").respond_with(
+            Response::builder()
+                .status(429)
+                .header("retry-after", "20")
+                .body(
+                    r#"{"type":"error","error":{"type":"rate_limit_error","message":"Slow down"}}"#
+                        .into(),
+                )
+                .unwrap(),
+        );
+        mock.expect(Method::POST, "/v1/messages").respond(
+            400,
+            r#"{"type":"error","error":{"type":"invalid_request_error","message":"max_tokens: required"}}"#,
+        );
+        let conduit = mock_conduit(&mock);
+        let request = MessageRequest::new(ClaudeModel::Claude35Haiku, 0, vec![Message::user("x")]);
+
+        let error = conduit.create_message(&request).await.unwrap_err();
+        assert!(error.is_retryable());
+        assert_eq!(error.retry_after(), Some(Duration::from_secs(20)));
+
+        let error = conduit.create_message(&request).await.unwrap_err();
+        assert!(!error.is_retryable());
+        match error {
+            ConduitError::InvalidRequest { field, .. } => {
+                assert_eq!(field.as_deref(), Some("max_tokens"))
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+
+        // A missing key fails before anything is sent
+        let error = Conduit::new("").create_message(&request).await.unwrap_err();
+        assert!(matches!(error, ConduitError::AuthenticationFailed { .. }));
+        mock.assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_stream_mock() {
+        let mock = MockTransport::new();
+        mock.expect(Method::POST, "/v1/messages").respond(
+            200,
+            concat!(
+                "event: content_block_delta\n",
+                "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"1, 2\"}}\n\n",
+                "event: message_stop\n",
+                "data: {\"type\":\"message_stop\"}\n\n",
+            ),
+        );
+        let conduit = mock_conduit(&mock);
+
+        let events: Vec<_> = conduit
+            .stream_message("Count", ClaudeModel::Claude35Sonnet, 64)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].as_ref().unwrap(), &StreamEvent::MessageStop);
+
+        let body: serde_json::Value = serde_json::from_slice(mock.requests()[0].body()).unwrap();
+        assert_eq!(body["stream"], true);
+    }
+
+    #[tokio::test]
+    async fn test_fit_context_mock() {
+        let mock = MockTransport::new();
+        mock.expect(Method::POST, "/v1/messages").respond(
+            200,
+            r#"{"id":"msg_1","type":"message","role":"assistant","model":"claude-2.0",
+                "content":[{"type":"text","text":"They talked about numbers."}],
+                "stop_reason":"end_turn","usage":{"input_tokens":90000,"output_tokens":6}}"#,
+        );
+        let conduit = mock_conduit(&mock);
+        let messages = (0..40)
+            .map(|i| match i % 2 {
+                0 => Message::user(format!("{} ", i).repeat(7_500)),
+                _ => Message::assistant(format!("{} ", i).repeat(7_500)),
+            })
+            .collect();
+        let mut request =
+            MessageRequest::new(ClaudeModel::Custom("claude-2.0".into()), 4_000, messages);
+        let last = request.messages.last().cloned();
+
+        let (removed, usage) = conduit
+            .fit_context(&mut request, &[], TrimStrategy::Summarize)
+            .await
+            .unwrap();
+        assert!(removed > 0);
+        assert_eq!(usage.input_tokens, 90_000);
+        assert_eq!(usage.output_tokens, 6);
+        assert!(estimate_request(&request) <= input_budget(&request));
+        assert_eq!(
+            request.messages[0],
+            Message::user("Summary of the earlier conversation:\n\nThey talked about numbers.")
+        );
+        assert_eq!(request.messages.last().cloned(), last);
+
+        let body: serde_json::Value = serde_json::from_slice(mock.requests()[0].body()).unwrap();
+        let prompt = body["messages"][0]["content"][0]["text"].as_str().unwrap();
+        assert!(prompt.contains("User: 0 0 0"));
+        mock.assert_done();
+    }
 
     #[tokio::test]
     async fn test_send_message() {
-        let api_key = std::env::var("ANTHROPIC_API_KEY").expect("ANTHROPIC_API_KEY must be set");
-        let conduit = Conduit::new(api_key).expect("Failed to create Conduit instance");
+        let cassette = Cassette::open("send_message").await;
+        let conduit = cassette.conduit();
 
         let prompt = "Say hello";
         let result = conduit
@@ -190,36 +421,43 @@ mod tests {
             .expect("Failed to send message");
 
         assert!(!result.is_empty());
+        cassette.assert_done();
     }
 
     #[tokio::test]
     async fn test_stream_message() {
-        let api_key = std::env::var("ANTHROPIC_API_KEY").expect("ANTHROPIC_API_KEY must be set");
-        let conduit = Conduit::new(api_key).expect("Failed to create Conduit instance");
+        let cassette = Cassette::open("stream_message").await;
+        let conduit = cassette.conduit();
 
         let mut total_text = String::new();
         let prompt = "Count from 1 to 5";
-        let mut stream = conduit
-            .stream_message(prompt, ClaudeModel::Claude35Sonnet, 1024)
-            .await
-            .expect("Failed to create stream");
+        let mut stream = Box::pin(
+            conduit
+                .stream_message(prompt, ClaudeModel::Claude35Sonnet, 1024)
+                .await
+                .expect("Failed to create stream"),
+        );
 
         while let Some(event) = stream.next().await {
             match event {
-                Ok(StreamEvent::ContentBlockDelta(content)) => {
-                    total_text.push_str(&content.delta.text);
-                }
+                Ok(StreamEvent::ContentBlockDelta {
+                    delta: Delta::TextDelta { text },
+                    ..
+                }) => total_text.push_str(&text),
                 Ok(StreamEvent::MessageStop) => break,
-                Ok(_) => {} // Handle other successful events gracefully
-                Err(e) => {
-                    println!("Stream event error: {}", e);
-                    continue; // Skip invalid events and continue streaming
-                }
+                Ok(_) => {}
+                Err(e) => panic!("Stream event error: {}", e),
             }
         }
 
-        assert!(!total_text.is_empty());
         assert!(total_text.contains("1"));
         assert!(total_text.contains("5"));
+
+        // Sent what was recorded
+        let recorded = Cassette::load(&cassette::fixture("stream_message"));
+        let sent = cassette.interactions();
+        assert_eq!(sent[0].request.body, recorded[0].request.body);
+        assert_eq!(sent[0].request.headers["x-api-key"], "[REDACTED]");
+        cassette.assert_done();
     }
 }
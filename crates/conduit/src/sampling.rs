@@ -0,0 +1,97 @@
+//! Sampling parameters shared by configuration, requests and overrides.
+
+use crate::ConduitError;
+use serde::{Deserialize, Serialize};
+
+/// How the model picks tokens. Unset fields use the API's defaults.
+///
+/// Flattened into [`MessageRequest`](crate::MessageRequest), so the fields
+/// serialize under their API names.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Sampling {
+    /// Randomness from 0.0 to 1.0. Lower is more deterministic.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// Nucleus sampling: only consider tokens within this much probability
+    /// mass, from 0.0 to 1.0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    /// Only consider the `top_k` most likely tokens.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    /// Text that ends the response when generated.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop_sequences: Vec<String>,
+}
+
+impl Sampling {
+    /// `self` with every parameter set in `overrides` replaced.
+    pub fn merge(&self, overrides: &Sampling) -> Sampling {
+        Sampling {
+            temperature: overrides.temperature.or(self.temperature),
+            top_p: overrides.top_p.or(self.top_p),
+            top_k: overrides.top_k.or(self.top_k),
+            stop_sequences: match overrides.stop_sequences.is_empty() {
+                true => self.stop_sequences.clone(),
+                false => overrides.stop_sequences.clone(),
+            },
+        }
+    }
+
+    /// Checks ranges before anything is sent.
+    pub fn validate(&self) -> Result<(), ConduitError> {
+        let invalid = |field: &str, message: &str| ConduitError::InvalidRequest {
+            field: Some(field.to_string()),
+            message: message.to_string(),
+        };
+        if self.temperature.is_some_and(|t| !(0.0..=1.0).contains(&t)) {
+            return Err(invalid("temperature", "must be between 0 and 1"));
+        }
+        if self.top_p.is_some_and(|p| !(0.0..=1.0).contains(&p)) {
+            return Err(invalid("top_p", "must be between 0 and 1"));
+        }
+        if self.top_k == Some(0) {
+            return Err(invalid("top_k", "must be at least 1"));
+        }
+        if self.stop_sequences.iter().any(|s| s.trim().is_empty()) {
+            return Err(invalid("stop_sequences", "must not be blank"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_and_validate() {
+        let defaults = Sampling {
+            temperature: Some(1.0),
+            top_k: Some(40),
+            stop_sequences: vec!["END".into()],
+            ..Sampling::default()
+        };
+        let review = Sampling {
+            temperature: Some(0.0),
+            ..Sampling::default()
+        };
+        let merged = defaults.merge(&review);
+        assert_eq!(merged.temperature, Some(0.0));
+        assert_eq!(merged.top_k, Some(40));
+        assert_eq!(merged.stop_sequences, ["END"]);
+        assert!(merged.validate().is_ok());
+
+        let error = Sampling {
+            temperature: Some(1.5),
+            ..Sampling::default()
+        }
+        .validate()
+        .unwrap_err();
+        assert!(matches!(
+            error,
+            ConduitError::InvalidRequest { field: Some(field), .. } if field == "temperature"
+        ));
+    }
+}
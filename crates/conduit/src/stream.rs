@@ -0,0 +1,229 @@
+//! Server-sent events of a streamed `POST /v1/messages`.
+
+use crate::types::{ApiError, ContentBlock, MessageResponse, StopReason};
+use crate::usage::Usage;
+use crate::ConduitError;
+use futures_util::{Stream, StreamExt};
+use hyperax::sse;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamEvent {
+    MessageStart {
+        message: MessageResponse,
+    },
+    ContentBlockStart {
+        index: usize,
+        content_block: ContentBlock,
+    },
+    ContentBlockDelta {
+        index: usize,
+        delta: Delta,
+    },
+    ContentBlockStop {
+        index: usize,
+    },
+    /// Final stop reason and the cumulative output token count.
+    MessageDelta {
+        delta: MessageDelta,
+        #[serde(default)]
+        usage: Usage,
+    },
+    MessageStop,
+    Ping,
+    Error {
+        error: ApiError,
+    },
+    /// An event type added to the API after this version.
+    #[serde(other)]
+    Unknown,
+}
+
+impl StreamEvent {
+    /// Answer text carried by this event.
+    pub fn text(&self) -> Option<&str> {
+        let text = match self {
+            StreamEvent::ContentBlockStart {
+                content_block: ContentBlock::Text { text },
+                ..
+            } => text,
+            StreamEvent::ContentBlockDelta {
+                delta: Delta::TextDelta { text },
+                ..
+            } => text,
+            _ => return None,
+        };
+        (!text.is_empty()).then_some(text.as_str())
+    }
+
+    /// Reasoning carried by this event, from a `thinking` block or its
+    /// deltas. Kept apart from [`StreamEvent::text`] so it can be shown
+    /// separately from the answer.
+    pub fn thinking(&self) -> Option<&str> {
+        let thinking = match self {
+            StreamEvent::ContentBlockStart {
+                content_block: ContentBlock::Thinking { thinking, .. },
+                ..
+            } => thinking,
+            StreamEvent::ContentBlockDelta {
+                delta: Delta::ThinkingDelta { thinking },
+                ..
+            } => thinking,
+            _ => return None,
+        };
+        (!thinking.is_empty()).then_some(thinking.as_str())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Delta {
+    TextDelta {
+        text: String,
+    },
+    /// A fragment of a `tool_use` block's JSON input.
+    InputJsonDelta {
+        partial_json: String,
+    },
+    ThinkingDelta {
+        thinking: String,
+    },
+    SignatureDelta {
+        signature: String,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageDelta {
+    #[serde(default)]
+    pub stop_reason: Option<StopReason>,
+    #[serde(default)]
+    pub stop_sequence: Option<String>,
+}
+
+/// Parses a `text/event-stream` body into events. An `error` event is
+/// classified like an error response.
+pub(crate) fn events<S, E>(body: S) -> impl Stream<Item = Result<StreamEvent, ConduitError>>
+where
+    S: Stream<Item = Result<hyperax::Bytes, E>> + Send + 'static,
+    E: Into<hyperax::body::BoxError> + 'static,
+{
+    sse::decode(body).map(|event| {
+        let event = event.map_err(|e| match e.downcast::<hyperax::Error>() {
+            Ok(e) => ConduitError::Network(e),
+            Err(e) => ConduitError::Stream(e.to_string()),
+        })?;
+        match serde_json::from_str(&event.data)? {
+            StreamEvent::Error { error } => Err(ConduitError::from_api(None, error, None)),
+            event => Ok(event),
+        }
+    })
+}
+
+/// Fails with [`ConduitError::Timeout`] when no event arrives for
+/// `timeout`. The API sends `ping` events, so a quiet stream is a dead one.
+pub(crate) fn idle_timeout<S>(
+    events: S,
+    timeout: Duration,
+) -> impl Stream<Item = Result<StreamEvent, ConduitError>> + Send
+where
+    S: Stream<Item = Result<StreamEvent, ConduitError>> + Send,
+{
+    let events = Box::pin(events);
+    futures_util::stream::unfold(Some(events), move |events| async move {
+        let mut events = events?;
+        match tokio::time::timeout(timeout, events.next()).await {
+            Ok(item) => item.map(|item| (item, Some(events))),
+            Err(_) => Some((Err(ConduitError::Timeout), None)),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+    use std::convert::Infallible;
+
+    #[tokio::test]
+    async fn test_parse_events() {
+        let body = concat!(
+            "event: message_start\n",
+            "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"type\":\"message\",\"role\":\"assistant\",\"model\":\"claude-3-5-sonnet-latest\",\"content\":[],\"stop_reason\":null,\"usage\":{\"input_tokens\":10,\"output_tokens\":1}}}\n\n",
+            "event: content_block_start\n",
+            "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+            "event: ping\n",
+            "data: {\"type\":\"ping\"}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hi\"}}\n\n",
+            "event: message_delta\n",
+            "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\",\"stop_sequence\":null},\"usage\":{\"output_tokens\":5}}\n\n",
+            "event: error\n",
+            "data: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n",
+        );
+        // Split mid-event to exercise the incremental decoder
+        let (a, b) = body.split_at(100);
+        let chunks = stream::iter([a, b].map(|s| Ok::<_, Infallible>(hyperax::Bytes::from(s))));
+        let events: Vec<_> = events(chunks).collect().await;
+
+        assert_eq!(events.len(), 6);
+        assert!(matches!(events[0], Ok(StreamEvent::MessageStart { .. })));
+        assert_eq!(events[2].as_ref().unwrap(), &StreamEvent::Ping);
+        assert_eq!(
+            events[3].as_ref().unwrap(),
+            &StreamEvent::ContentBlockDelta {
+                index: 0,
+                delta: Delta::TextDelta { text: "Hi".into() }
+            }
+        );
+        match &events[4] {
+            Ok(StreamEvent::MessageDelta { delta, usage }) => {
+                assert_eq!(delta.stop_reason, Some(StopReason::EndTurn));
+                assert_eq!(usage.output_tokens, 5);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(events[5].as_ref().unwrap_err().is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_parse_unknown_event() {
+        let body = "data: {\"type\":\"message_annotation\",\"note\":\"new\"}\n\n";
+        let chunks = stream::iter([Ok::<_, Infallible>(hyperax::Bytes::from(body))]);
+        let events: Vec<_> = events(chunks).collect().await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].as_ref().unwrap(), &StreamEvent::Unknown);
+    }
+
+    #[tokio::test]
+    async fn test_parse_thinking() {
+        let body = concat!(
+            "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"thinking\",\"thinking\":\"\"}}\n\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"thinking_delta\",\"thinking\":\"Two plus two\"}}\n\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"signature_delta\",\"signature\":\"sig\"}}\n\n",
+            "data: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+            "data: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"text_delta\",\"text\":\"4\"}}\n\n",
+        );
+        let chunks = stream::iter([Ok::<_, Infallible>(hyperax::Bytes::from(body))]);
+        let events: Vec<_> = events(chunks).map(|event| event.unwrap()).collect().await;
+
+        let thinking: Vec<_> = events.iter().filter_map(StreamEvent::thinking).collect();
+        let text: Vec<_> = events.iter().filter_map(StreamEvent::text).collect();
+        assert_eq!(thinking, ["Two plus two"]);
+        assert_eq!(text, ["4"]);
+        assert_eq!(
+            events[2],
+            StreamEvent::ContentBlockDelta {
+                index: 0,
+                delta: Delta::SignatureDelta {
+                    signature: "sig".into()
+                }
+            }
+        );
+    }
+}
@@ -0,0 +1,416 @@
+//! Request and response types for the Anthropic Messages API.
+
+use crate::cache::CacheBreakpoints;
+use crate::sampling::Sampling;
+use crate::usage::Usage;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::fmt;
+use std::str::FromStr;
+
+/// A Claude model. Known models map to their API aliases; anything else is
+/// sent as written.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ClaudeModel {
+    Claude3Opus,
+    Claude35Haiku,
+    Claude35Sonnet,
+    Claude37Sonnet,
+    ClaudeSonnet4,
+    ClaudeOpus4,
+    Custom(String),
+}
+
+impl ClaudeModel {
+    /// Every model with an alias, oldest first.
+    pub const KNOWN: &'static [ClaudeModel] = &[
+        ClaudeModel::Claude3Opus,
+        ClaudeModel::Claude35Haiku,
+        ClaudeModel::Claude35Sonnet,
+        ClaudeModel::Claude37Sonnet,
+        ClaudeModel::ClaudeSonnet4,
+        ClaudeModel::ClaudeOpus4,
+    ];
+
+    pub fn id(&self) -> &str {
+        match self {
+            ClaudeModel::Claude3Opus => "claude-3-opus-latest",
+            ClaudeModel::Claude35Haiku => "claude-3-5-haiku-latest",
+            ClaudeModel::Claude35Sonnet => "claude-3-5-sonnet-latest",
+            ClaudeModel::Claude37Sonnet => "claude-3-7-sonnet-latest",
+            ClaudeModel::ClaudeSonnet4 => "claude-sonnet-4-0",
+            ClaudeModel::ClaudeOpus4 => "claude-opus-4-0",
+            ClaudeModel::Custom(id) => id,
+        }
+    }
+}
+
+impl fmt::Display for ClaudeModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+impl FromStr for ClaudeModel {
+    type Err = std::convert::Infallible;
+
+    /// Accepts API aliases and short names like `claude-3.5-sonnet`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().replace('.', "-");
+        let name = name.strip_suffix("-latest").unwrap_or(&name);
+        Ok(match name {
+            "claude-3-opus" => ClaudeModel::Claude3Opus,
+            "claude-3-5-haiku" => ClaudeModel::Claude35Haiku,
+            "claude-3-5-sonnet" => ClaudeModel::Claude35Sonnet,
+            "claude-3-7-sonnet" => ClaudeModel::Claude37Sonnet,
+            "claude-sonnet-4" | "claude-sonnet-4-0" => ClaudeModel::ClaudeSonnet4,
+            "claude-opus-4" | "claude-opus-4-0" => ClaudeModel::ClaudeOpus4,
+            _ => ClaudeModel::Custom(s.trim().to_string()),
+        })
+    }
+}
+
+impl Serialize for ClaudeModel {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.id())
+    }
+}
+
+impl<'de> Deserialize<'de> for ClaudeModel {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let id = String::deserialize(deserializer)?;
+        Ok(id.parse().unwrap_or_else(|never| match never {}))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    pub role: Role,
+    pub content: Vec<ContentBlock>,
+}
+
+impl Message {
+    pub fn user(text: impl Into<String>) -> Self {
+        Self {
+            role: Role::User,
+            content: vec![ContentBlock::text(text)],
+        }
+    }
+
+    pub fn assistant(text: impl Into<String>) -> Self {
+        Self {
+            role: Role::Assistant,
+            content: vec![ContentBlock::text(text)],
+        }
+    }
+}
+
+/// One block of message content.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBlock {
+    Text {
+        text: String,
+    },
+    Image {
+        source: Source,
+    },
+    Document {
+        source: Source,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        content: Vec<ContentBlock>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        is_error: bool,
+    },
+    Thinking {
+        thinking: String,
+        #[serde(default)]
+        signature: String,
+    },
+    RedactedThinking {
+        data: String,
+    },
+    /// A block type this version does not know about.
+    #[serde(other)]
+    Unknown,
+}
+
+impl ContentBlock {
+    pub fn text(text: impl Into<String>) -> Self {
+        ContentBlock::Text { text: text.into() }
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            ContentBlock::Text { text } => Some(text),
+            _ => None,
+        }
+    }
+}
+
+/// Where image and document content comes from.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Source {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+    Text { media_type: String, data: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Tool {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub input_schema: Value,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ToolChoice {
+    Auto,
+    Any,
+    Tool { name: String },
+    None,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+}
+
+/// Extended thinking, where the model reasons in `thinking` blocks before
+/// answering.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ThinkingConfig {
+    /// `budget_tokens` must be at least 1024 and less than `max_tokens`,
+    /// which counts thinking as output.
+    Enabled {
+        budget_tokens: u32,
+    },
+    Disabled,
+}
+
+/// Body of `POST /v1/messages`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MessageRequest {
+    pub model: ClaudeModel,
+    pub max_tokens: u32,
+    pub messages: Vec<Message>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<Tool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ToolChoice>,
+    #[serde(flatten)]
+    pub sampling: Sampling,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<ThinkingConfig>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stream: bool,
+    /// Prompt caching breakpoints, marked on the body when it is sent.
+    #[serde(skip)]
+    pub cache: CacheBreakpoints,
+}
+
+impl MessageRequest {
+    pub fn new(model: ClaudeModel, max_tokens: u32, messages: Vec<Message>) -> Self {
+        Self {
+            model,
+            max_tokens,
+            messages,
+            system: None,
+            tools: Vec::new(),
+            tool_choice: None,
+            sampling: Sampling::default(),
+            metadata: None,
+            thinking: None,
+            stream: false,
+            cache: CacheBreakpoints::default(),
+        }
+    }
+
+    pub fn system(mut self, system: impl Into<String>) -> Self {
+        self.system = Some(system.into());
+        self
+    }
+
+    pub fn tools(mut self, tools: Vec<Tool>) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    pub fn sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    /// Enables extended thinking with up to `budget_tokens` of reasoning.
+    pub fn thinking(mut self, budget_tokens: u32) -> Self {
+        self.thinking = Some(ThinkingConfig::Enabled { budget_tokens });
+        self
+    }
+
+    pub fn cache(mut self, breakpoints: CacheBreakpoints) -> Self {
+        self.cache = breakpoints;
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    EndTurn,
+    MaxTokens,
+    StopSequence,
+    ToolUse,
+    PauseTurn,
+    Refusal,
+    #[serde(other)]
+    Other,
+}
+
+/// Body of a successful `POST /v1/messages`, and the `message` of a
+/// `message_start` event.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MessageResponse {
+    pub id: String,
+    pub model: ClaudeModel,
+    pub role: Role,
+    pub content: Vec<ContentBlock>,
+    pub stop_reason: Option<StopReason>,
+    #[serde(default)]
+    pub stop_sequence: Option<String>,
+    #[serde(default)]
+    pub usage: Usage,
+}
+
+impl MessageResponse {
+    /// All text blocks joined together.
+    pub fn text(&self) -> String {
+        self.content
+            .iter()
+            .filter_map(ContentBlock::as_text)
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    InvalidRequestError,
+    AuthenticationError,
+    PermissionError,
+    NotFoundError,
+    RequestTooLarge,
+    RateLimitError,
+    ApiError,
+    OverloadedError,
+    #[serde(other)]
+    Other,
+}
+
+/// The `error` object of an API error response or `error` event.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiError {
+    #[serde(rename = "type")]
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.kind, self.message)
+    }
+}
+
+/// `{"type": "error", "error": {...}}`
+#[derive(Debug, Deserialize)]
+pub(crate) struct ErrorResponse {
+    pub(crate) error: ApiError,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_request_serialization() {
+        let request = MessageRequest::new(
+            ClaudeModel::Claude35Sonnet,
+            1024,
+            vec![Message::user("Hello")],
+        )
+        .system("Be brief")
+        .sampling(Sampling {
+            temperature: Some(0.0),
+            ..Sampling::default()
+        });
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            json!({
+                "model": "claude-3-5-sonnet-latest",
+                "max_tokens": 1024,
+                "messages": [{"role": "user", "content": [{"type": "text", "text": "Hello"}]}],
+                "system": "Be brief",
+                "temperature": 0.0,
+            })
+        );
+        assert_eq!(
+            "claude-3.5-sonnet".parse::<ClaudeModel>().unwrap(),
+            ClaudeModel::Claude35Sonnet
+        );
+    }
+
+    #[test]
+    fn test_response_deserialization() {
+        let response: MessageResponse = serde_json::from_value(json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-3-5-sonnet-20241022",
+            "content": [
+                {"type": "thinking", "thinking": "hmm", "signature": "sig"},
+                {"type": "text", "text": "Let me check. "},
+                {"type": "tool_use", "id": "tu_1", "name": "weather", "input": {"city": "Oslo"}},
+                {"type": "server_tool_use", "id": "x"}
+            ],
+            "stop_reason": "tool_use",
+            "stop_sequence": null,
+            "usage": {"input_tokens": 12, "output_tokens": 30, "cache_read_input_tokens": 4}
+        }))
+        .unwrap();
+        assert_eq!(response.text(), "Let me check. ");
+        assert_eq!(response.stop_reason, Some(StopReason::ToolUse));
+        assert_eq!(response.usage.cache_read_input_tokens, 4);
+        assert_eq!(
+            response.model,
+            ClaudeModel::Custom("claude-3-5-sonnet-20241022".into())
+        );
+        assert!(matches!(response.content[2], ContentBlock::ToolUse { .. }));
+        assert_eq!(response.content[3], ContentBlock::Unknown);
+    }
+}
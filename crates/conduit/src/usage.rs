@@ -0,0 +1,205 @@
+use crate::stream::StreamEvent;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign};
+
+/// Token counts. `message_delta` events only carry `output_tokens`, so
+/// every field defaults to zero.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Usage {
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    pub cache_creation_input_tokens: u32,
+    pub cache_read_input_tokens: u32,
+}
+
+impl Usage {
+    /// Every token billed, cached or not.
+    pub fn total_tokens(&self) -> u64 {
+        self.input_tokens as u64
+            + self.output_tokens as u64
+            + self.cache_creation_input_tokens as u64
+            + self.cache_read_input_tokens as u64
+    }
+
+    /// Folds the usage carried by a stream event into the running count for
+    /// that message. `message_start` sets the input side and `message_delta`
+    /// reports cumulative totals, so later counts replace earlier ones.
+    pub fn record(&mut self, event: &StreamEvent) {
+        match event {
+            StreamEvent::MessageStart { message } => *self = message.usage,
+            StreamEvent::MessageDelta { usage, .. } => {
+                self.output_tokens = usage.output_tokens;
+                let replace = |current: &mut u32, reported: u32| {
+                    if reported > 0 {
+                        *current = reported;
+                    }
+                };
+                replace(&mut self.input_tokens, usage.input_tokens);
+                replace(
+                    &mut self.cache_creation_input_tokens,
+                    usage.cache_creation_input_tokens,
+                );
+                replace(
+                    &mut self.cache_read_input_tokens,
+                    usage.cache_read_input_tokens,
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `12 in · 80 out`, plus cache reads and writes when there are any.
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} in · {} out", self.input_tokens, self.output_tokens)?;
+        if self.cache_read_input_tokens > 0 {
+            write!(f, " · {} from cache", self.cache_read_input_tokens)?;
+        }
+        if self.cache_creation_input_tokens > 0 {
+            write!(
+                f,
+                " · {} written to cache",
+                self.cache_creation_input_tokens
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Add for Usage {
+    type Output = Usage;
+
+    fn add(mut self, other: Usage) -> Usage {
+        self += other;
+        self
+    }
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Usage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cache_creation_input_tokens += other.cache_creation_input_tokens;
+        self.cache_read_input_tokens += other.cache_read_input_tokens;
+    }
+}
+
+impl Sum for Usage {
+    fn sum<I: Iterator<Item = Usage>>(iter: I) -> Usage {
+        iter.fold(Usage::default(), Add::add)
+    }
+}
+
+/// What a model charges, in US dollars per million tokens. Cache prices
+/// left out of a config are derived from `input` as in [`Pricing::new`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "PricingConfig")]
+pub struct Pricing {
+    pub input: f64,
+    pub output: f64,
+    /// Writing a prompt prefix to the cache.
+    pub cache_write: f64,
+    pub cache_read: f64,
+}
+
+impl Pricing {
+    /// Prices with the usual cache multipliers: writes cost 1.25 times the
+    /// input price and reads a tenth of it.
+    pub fn new(input: f64, output: f64) -> Self {
+        Self {
+            input,
+            output,
+            cache_write: input * 1.25,
+            cache_read: input * 0.1,
+        }
+    }
+
+    /// Cost of `usage` in US dollars.
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        let per_token = |tokens: u32, price: f64| tokens as f64 * price / 1_000_000.0;
+        per_token(usage.input_tokens, self.input)
+            + per_token(usage.output_tokens, self.output)
+            + per_token(usage.cache_creation_input_tokens, self.cache_write)
+            + per_token(usage.cache_read_input_tokens, self.cache_read)
+    }
+}
+
+/// [`Pricing`] as written in a config, with optional cache prices.
+#[derive(Deserialize)]
+struct PricingConfig {
+    input: f64,
+    output: f64,
+    cache_write: Option<f64>,
+    cache_read: Option<f64>,
+}
+
+impl From<PricingConfig> for Pricing {
+    fn from(config: PricingConfig) -> Self {
+        let defaults = Pricing::new(config.input, config.output);
+        Self {
+            cache_write: config.cache_write.unwrap_or(defaults.cache_write),
+            cache_read: config.cache_read.unwrap_or(defaults.cache_read),
+            ..defaults
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::MessageDelta;
+
+    #[test]
+    fn test_usage_from_stream() {
+        let start: StreamEvent = serde_json::from_str(
+            r#"{"type":"message_start","message":{"id":"msg_1","role":"assistant",
+                "model":"claude-3-5-sonnet-latest","content":[],"stop_reason":null,
+                "usage":{"input_tokens":1000,"output_tokens":1,"cache_read_input_tokens":4000}}}"#,
+        )
+        .unwrap();
+        let delta = StreamEvent::MessageDelta {
+            delta: MessageDelta::default(),
+            usage: Usage {
+                output_tokens: 500,
+                ..Usage::default()
+            },
+        };
+
+        let mut usage = Usage::default();
+        usage.record(&start);
+        usage.record(&delta);
+        assert_eq!(
+            usage,
+            Usage {
+                input_tokens: 1000,
+                output_tokens: 500,
+                cache_creation_input_tokens: 0,
+                cache_read_input_tokens: 4000,
+            }
+        );
+        assert_eq!(
+            [usage, usage].into_iter().sum::<Usage>().output_tokens,
+            1000
+        );
+        assert_eq!(usage.to_string(), "1000 in · 500 out · 4000 from cache");
+
+        // $3 in, $15 out, $0.30 cache reads per million
+        let cost = Pricing::new(3.0, 15.0).cost(&usage);
+        assert!((cost - (0.003 + 0.0075 + 0.0012)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_pricing_cache_defaults() {
+        let pricing: Pricing = serde_json::from_str(r#"{"input":3.0,"output":15.0}"#).unwrap();
+        assert_eq!(pricing, Pricing::new(3.0, 15.0));
+
+        let pricing: Pricing =
+            serde_json::from_str(r#"{"input":3.0,"output":15.0,"cache_read":0.5}"#).unwrap();
+        assert_eq!(pricing.cache_read, 0.5);
+        assert_eq!(pricing.cache_write, 3.75);
+    }
+}
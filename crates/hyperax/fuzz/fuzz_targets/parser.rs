@@ -0,0 +1,24 @@
+#![no_main]
+
+use bytes::Bytes;
+use hyperax::common::parser::{Parser, Status};
+use libfuzzer_sys::fuzz_target;
+
+// The first byte picks where the input is split so partial reads are
+// exercised too.
+fuzz_target!(|data: &[u8]| {
+    let Some((&split, data)) = data.split_first() else {
+        return;
+    };
+    let split = (split as usize).min(data.len());
+
+    let mut parser = Parser::default();
+    parser.feed(&data[..split]);
+    let _ = parser.parse_request();
+    parser.feed(&data[split..]);
+    while let Ok(Status::Complete(..)) = parser.parse_request() {}
+
+    let mut parser = Parser::new(Bytes::copy_from_slice(data));
+    parser.eof();
+    while let Ok(Status::Complete(..)) = parser.parse_response() {}
+});
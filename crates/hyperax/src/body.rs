@@ -0,0 +1,266 @@
+use futures_util::Stream;
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, Empty, Full, StreamBody};
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use std::fmt;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Request body handed to handlers and middleware, and sent by client
+/// transports.
+pub type RequestBody = UnsyncBoxBody<Bytes, BoxError>;
+
+/// Response body produced by handlers and middleware. Either a buffered
+/// payload or a stream that is written to the connection as it arrives.
+pub type ResponseBody = UnsyncBoxBody<Bytes, BoxError>;
+
+/// Chunks buffered between a [`Sender`] and the connection before `send`
+/// starts waiting.
+const CHANNEL_CAPACITY: usize = 16;
+
+pub fn full(data: impl Into<Bytes>) -> ResponseBody {
+    Full::new(data.into())
+        .map_err(BoxError::from)
+        .boxed_unsync()
+}
+
+pub fn empty() -> ResponseBody {
+    Empty::new().map_err(BoxError::from).boxed_unsync()
+}
+
+/// Boxes any body whose errors convert into [`BoxError`].
+pub fn boxed<B>(body: B) -> ResponseBody
+where
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    body.map_err(Into::into).boxed_unsync()
+}
+
+/// Streams every chunk produced by `stream`. An `Err` item aborts the
+/// response mid-flight.
+pub fn stream<S, E>(stream: S) -> ResponseBody
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<BoxError> + 'static,
+{
+    use futures_util::TryStreamExt;
+
+    StreamBody::new(stream.map_ok(Frame::data).map_err(Into::into)).boxed_unsync()
+}
+
+/// A body fed from another task. The response ends when the [`Sender`] is
+/// dropped.
+pub fn channel() -> (Sender, ResponseBody) {
+    let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+    (Sender { tx }, ChannelBody { rx }.boxed_unsync())
+}
+
+/// Body of an outgoing client request.
+///
+/// Buffered payloads are cheap to clone. Streamed payloads are produced on
+/// demand, once per attempt, so a request can still be retried or
+/// redirected without holding the whole body in memory.
+#[derive(Clone)]
+pub struct Payload(PayloadKind);
+
+#[derive(Clone)]
+enum PayloadKind {
+    Bytes(Bytes),
+    Stream {
+        open: Arc<dyn Fn() -> RequestBody + Send + Sync>,
+        length: Option<u64>,
+    },
+}
+
+impl Payload {
+    pub fn empty() -> Self {
+        Self(PayloadKind::Bytes(Bytes::new()))
+    }
+
+    /// A payload streamed from the body `open` returns. `open` is called for
+    /// every attempt at sending the request.
+    pub fn streaming<F>(open: F) -> Self
+    where
+        F: Fn() -> RequestBody + Send + Sync + 'static,
+    {
+        Self(PayloadKind::Stream {
+            open: Arc::new(open),
+            length: None,
+        })
+    }
+
+    /// Declares the total size of a streamed payload so it is sent with
+    /// `Content-Length` instead of chunked.
+    pub fn with_length(mut self, length: u64) -> Self {
+        if let PayloadKind::Stream { length: len, .. } = &mut self.0 {
+            *len = Some(length);
+        }
+        self
+    }
+
+    /// Size in bytes, if known up front.
+    pub fn length(&self) -> Option<u64> {
+        match &self.0 {
+            PayloadKind::Bytes(bytes) => Some(bytes.len() as u64),
+            PayloadKind::Stream { length, .. } => *length,
+        }
+    }
+
+    /// A fresh body to send.
+    pub fn open(&self) -> RequestBody {
+        match &self.0 {
+            PayloadKind::Bytes(bytes) => full(bytes.clone()),
+            PayloadKind::Stream { open, .. } => open(),
+        }
+    }
+}
+
+impl Default for Payload {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl fmt::Debug for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            PayloadKind::Bytes(bytes) => f.debug_tuple("Payload").field(bytes).finish(),
+            PayloadKind::Stream { length, .. } => f
+                .debug_struct("Payload")
+                .field("length", length)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+impl From<Bytes> for Payload {
+    fn from(bytes: Bytes) -> Self {
+        Self(PayloadKind::Bytes(bytes))
+    }
+}
+
+impl From<Vec<u8>> for Payload {
+    fn from(data: Vec<u8>) -> Self {
+        Bytes::from(data).into()
+    }
+}
+
+impl From<String> for Payload {
+    fn from(text: String) -> Self {
+        Bytes::from(text).into()
+    }
+}
+
+impl From<&'static str> for Payload {
+    fn from(text: &'static str) -> Self {
+        Bytes::from_static(text.as_bytes()).into()
+    }
+}
+
+impl From<&'static [u8]> for Payload {
+    fn from(data: &'static [u8]) -> Self {
+        Bytes::from_static(data).into()
+    }
+}
+
+impl From<Full<Bytes>> for Payload {
+    fn from(body: Full<Bytes>) -> Self {
+        let length = body.size_hint().exact();
+        let payload = Self::streaming(move || boxed(body.clone()));
+        match length {
+            Some(length) => payload.with_length(length),
+            None => payload,
+        }
+    }
+}
+
+impl From<crate::common::Body> for Payload {
+    fn from(body: crate::common::Body) -> Self {
+        body.into_bytes().into()
+    }
+}
+
+/// The client went away before the body was fully sent.
+#[derive(Debug, thiserror::Error)]
+#[error("response body receiver dropped")]
+pub struct Closed;
+
+/// Writing half of [`channel`].
+#[derive(Clone, Debug)]
+pub struct Sender {
+    tx: mpsc::Sender<Result<Frame<Bytes>, BoxError>>,
+}
+
+impl Sender {
+    /// Queues a chunk, waiting while the connection is behind.
+    pub async fn send(&self, data: impl Into<Bytes>) -> Result<(), Closed> {
+        self.tx
+            .send(Ok(Frame::data(data.into())))
+            .await
+            .map_err(|_| Closed)
+    }
+
+    /// Ends the response with trailers.
+    pub async fn send_trailers(self, trailers: hyper::HeaderMap) -> Result<(), Closed> {
+        self.tx
+            .send(Ok(Frame::trailers(trailers)))
+            .await
+            .map_err(|_| Closed)
+    }
+
+    /// Aborts the response. The connection is reset instead of ending the
+    /// body cleanly, so the client can tell the payload is truncated.
+    pub async fn abort(self, error: impl Into<BoxError>) {
+        let _ = self.tx.send(Err(error.into())).await;
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+}
+
+struct ChannelBody {
+    rx: mpsc::Receiver<Result<Frame<Bytes>, BoxError>>,
+}
+
+impl Body for ChannelBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        self.rx.poll_recv(cx)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_channel_body() {
+        let (tx, body) = channel();
+        tokio::spawn(async move {
+            tx.send("hello ").await.unwrap();
+            tx.send("world").await.unwrap();
+        });
+        let bytes = body.collect().await.unwrap().to_bytes();
+        assert_eq!(bytes, Bytes::from("hello world"));
+
+        let (tx, body) = channel();
+        tx.send("partial").await.unwrap();
+        tx.abort("boom").await;
+        assert!(body.collect().await.is_err());
+    }
+}
@@ -1,13 +1,26 @@
+use crate::body::Payload;
+use crate::common;
+use crate::encoding;
+use crate::multipart;
+use crate::redirect::{self, RedirectPolicy};
+use crate::retry::{self, RetryPolicy};
+use crate::transport::{HttpTransport, HttpVersion, Proxy, ResponseBody, Transport};
+use crate::websocket::{self, WebSocket};
 use http_body_util::{BodyExt, Full};
 use hyper::body::Bytes;
-use hyper::header::{HeaderMap, HeaderName, HeaderValue};
-use hyper::service::Service;
-use hyper::{Request, Response};
+use hyper::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT, ACCEPT_ENCODING, CONNECTION, CONTENT_LENGTH,
+    CONTENT_TYPE, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_VERSION, UPGRADE,
+};
+use hyper::http::request::Parts;
+use hyper::http::uri::Scheme;
+use hyper::{Method, Request, Response, StatusCode, Uri};
 use hyper_util::rt::TokioIo;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use std::future::Future;
-use std::pin::Pin;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::net::TcpStream;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -17,43 +30,65 @@ pub enum Error {
     Request(#[from] hyper::http::Error),
     #[error("Connect error: {0}")]
     Connect(#[from] std::io::Error),
+    #[error("HTTP status {}", .0.code)]
+    Status(Box<StatusError>),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Unexpected content type: {0}")]
+    ContentType(String),
+    #[error("Decode error: {0}")]
+    Decode(std::io::Error),
+    #[error("Too many redirects (stopped after {0})")]
+    TooManyRedirects(usize),
+    #[error("WebSocket error: {0}")]
+    WebSocket(#[from] websocket::WebSocketError),
     #[error("{0}")]
     Mock(String),
 }
 
-struct HttpConnector {
-    timeout: Option<Duration>,
+/// A non-2xx response, buffered. Boxed in [`Error::Status`] to keep
+/// `Result<_, Error>` small.
+#[derive(Debug)]
+pub struct StatusError {
+    pub code: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
 }
 
-impl HttpConnector {
-    fn new() -> Self {
-        Self {
-            timeout: Some(Duration::from_secs(60)),
+impl Error {
+    fn status_error(code: StatusCode, headers: HeaderMap, body: Bytes) -> Self {
+        Error::Status(Box::new(StatusError {
+            code,
+            headers,
+            body,
+        }))
+    }
+
+    /// Whether the failure happened in transit and the request may succeed
+    /// if sent again.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Connect(_) => true,
+            Error::Http(e) => e.is_closed() || e.is_incomplete_message() || e.is_timeout(),
+            _ => false,
         }
     }
-}
 
-impl hyper::service::Service<hyper::Uri> for HttpConnector {
-    type Response = TokioIo<TcpStream>;
-    type Error = std::io::Error;
-    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
-
-    fn call(&self, uri: hyper::Uri) -> Self::Future {
-        let timeout = self.timeout;
-        Box::pin(async move {
-            let addr = uri
-                .authority()
-                .ok_or_else(|| {
-                    std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid uri")
-                })?
-                .as_str();
-
-            let stream = TcpStream::connect(addr).await?;
-            if let Some(_) = timeout {
-                stream.set_nodelay(true)?;
-            }
-            Ok(TokioIo::new(stream))
-        })
+    /// The response status for `Error::Status`.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::Status(status) => Some(status.code),
+            _ => None,
+        }
+    }
+
+    /// Decodes the body of an `Error::Status` as a typed JSON error, such as
+    /// an API's `{"error": {...}}` envelope.
+    pub fn json_body<E: DeserializeOwned>(&self) -> Option<E> {
+        match self {
+            Error::Status(status) => serde_json::from_slice(&status.body).ok(),
+            _ => None,
+        }
     }
 }
 
@@ -62,72 +97,187 @@ pub struct Client {
     timeout: Option<Duration>,
     base_url: Option<String>,
     headers: HeaderMap,
+    retry: RetryPolicy,
+    redirect: RedirectPolicy,
+    decompress: bool,
+    transport: Arc<dyn Transport>,
 }
-#[cfg(test)]
-thread_local! {
-    static MOCK_RESPONSE: std::cell::RefCell<Option<Response<Bytes>>> = std::cell::RefCell::new(None);
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::builder().build()
+    }
 }
 
 impl Client {
     pub fn new() -> Self {
-        Self {
-            timeout: Some(Duration::from_secs(60)),
-            base_url: None,
-            headers: HeaderMap::new(),
-        }
-    }
-
-    #[cfg(test)]
-    pub fn mock_response(response: Response<Bytes>) {
-        MOCK_RESPONSE.with(|r| *r.borrow_mut() = Some(response));
+        Self::default()
     }
 
     pub fn builder() -> ClientBuilder {
         ClientBuilder::new()
     }
 
-    pub async fn request<T>(&self, mut req: Request<T>) -> Result<Response<Bytes>, Error>
+    /// Sends a [`common::Request`], for callers that keep away from hyper
+    /// types. Behaves like [`Client::request`].
+    pub async fn execute(&self, req: common::Request) -> Result<common::Response, Error> {
+        let req = Request::<Full<Bytes>>::try_from(req)?;
+        self.request(req).await.map(common::Response::from)
+    }
+
+    pub async fn request<T>(&self, req: Request<T>) -> Result<Response<Bytes>, Error>
     where
-        T: Into<Full<Bytes>>,
+        T: Into<Payload>,
     {
-        #[cfg(test)]
-        {
-            return MOCK_RESPONSE.with(|r| {
-                r.borrow()
-                    .clone()
-                    .ok_or_else(|| Error::Mock("No mock response set".to_string()))
-            });
+        let (parts, body) = self.prepare(req)?;
+        self.retrying(&parts, || {
+            self.send(Request::from_parts(parts.clone(), body.clone()))
+        })
+        .await
+        .and_then(check_status)
+    }
+
+    /// Like [`Client::request`], but returns once the response head arrives
+    /// and streams the (decoded) body. Error responses are still buffered
+    /// into [`Error::Status`].
+    pub async fn stream<T>(&self, req: Request<T>) -> Result<Response<ResponseBody>, Error>
+    where
+        T: Into<Payload>,
+    {
+        let (parts, body) = self.prepare(req)?;
+        let resp = self
+            .retrying(&parts, || {
+                self.follow(Request::from_parts(parts.clone(), body.clone()))
+            })
+            .await?;
+        if resp.status().is_success() {
+            return Ok(resp);
         }
+        let (head, body) = resp.into_parts();
+        Err(Error::status_error(
+            head.status,
+            head.headers,
+            body.collect().await?.to_bytes(),
+        ))
+    }
 
-        #[cfg(not(test))]
+    /// Applies the base url and default headers.
+    fn prepare<T>(&self, req: Request<T>) -> Result<(Parts, Payload), hyper::http::Error>
+    where
+        T: Into<Payload>,
+    {
+        let (mut parts, body) = req.into_parts();
+        if let Some(base) = &self.base_url {
+            let uri = format!("{}{}", base, parts.uri);
+            parts.uri = uri.parse()?;
+        }
+        for (k, v) in self.headers.iter() {
+            parts.headers.insert(k, v.clone());
+        }
+        if self.decompress && !parts.headers.contains_key(ACCEPT_ENCODING) {
+            parts.headers.insert(
+                ACCEPT_ENCODING,
+                HeaderValue::from_static(encoding::ACCEPT_ENCODING),
+            );
+        }
+        let body: Payload = body.into();
+        if let Some(length) = body
+            .length()
+            .filter(|_| !parts.headers.contains_key(CONTENT_LENGTH))
         {
-            if let Some(base) = &self.base_url {
-                let uri = format!("{}{}", base, req.uri());
-                *req.uri_mut() = uri
-                    .parse()
-                    .map_err(|e| Error::Request(hyper::http::Error::from(e)))?;
-            }
-            for (k, v) in self.headers.iter() {
-                req.headers_mut().insert(k, v.clone());
-            }
-
-            let (parts, body) = req.into_parts();
-            let req = Request::from_parts(parts, body.into());
+            // Lets streamed payloads of known size go out without chunking
+            parts.headers.insert(CONTENT_LENGTH, length.into());
+        }
+        Ok((parts, body))
+    }
 
-            let connector = HttpConnector::new();
-            let io = connector.call(req.uri().clone()).await?;
+    /// Calls `send` until it succeeds or the retry policy gives up.
+    async fn retrying<B, F, Fut>(&self, parts: &Parts, send: F) -> Result<Response<B>, Error>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<Response<B>, Error>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let result = send().await;
+
+            let delay = match &result {
+                Ok(resp)
+                    if self.retry.should_retry_status(
+                        &parts.method,
+                        &parts.headers,
+                        resp.status(),
+                    ) =>
+                {
+                    self.retry
+                        .next_delay(attempt, retry::retry_after(resp.headers()))
+                }
+                Err(e)
+                    if e.is_transient()
+                        && self.retry.should_retry_error(&parts.method, &parts.headers) =>
+                {
+                    self.retry.next_delay(attempt, None)
+                }
+                _ => None,
+            };
 
-            let (mut sender, conn) = hyper::client::conn::http1::handshake(io).await?;
-            tokio::task::spawn(async move {
-                if let Err(err) = conn.await {
-                    eprintln!("Connection failed: {:?}", err);
+            match delay {
+                Some(delay) => {
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
                 }
-            });
+                None => return result,
+            }
+        }
+    }
+
+    async fn send(&self, req: Request<Payload>) -> Result<Response<Bytes>, Error> {
+        let resp = self.follow(req).await?;
+        let (parts, body) = resp.into_parts();
+        let bytes = body.collect().await?.to_bytes();
+        Ok(Response::from_parts(parts, bytes))
+    }
 
-            let resp = sender.send_request(req).await?;
-            let (parts, body) = resp.into_parts();
-            let bytes = body.collect().await?.to_bytes();
-            Ok(Response::from_parts(parts, bytes))
+    /// Sends `req`, following redirects, and decodes the response body.
+    async fn follow(&self, req: Request<Payload>) -> Result<Response<ResponseBody>, Error> {
+        let (mut parts, mut body) = req.into_parts();
+        let mut hops = 0;
+        let resp = loop {
+            let send = self
+                .transport
+                .send(Request::from_parts(parts.clone(), body.open()));
+            let resp = match self.timeout {
+                Some(timeout) => tokio::time::timeout(timeout, send).await.map_err(|_| {
+                    std::io::Error::new(std::io::ErrorKind::TimedOut, "request timed out")
+                })??,
+                None => send.await?,
+            };
+            let location = redirect::location(resp.headers());
+            let Some(next) = self.redirect.next(&parts, resp.status(), location) else {
+                break resp;
+            };
+            if !self.redirect.allows(hops) {
+                if hops == 0 {
+                    // Following is disabled; hand back the redirect itself
+                    break resp;
+                }
+                return Err(Error::TooManyRedirects(hops));
+            }
+            hops += 1;
+            parts = next.parts;
+            if next.drops_body {
+                body = Payload::empty();
+            }
+        };
+
+        let skip = parts.method == Method::HEAD
+            || matches!(
+                resp.status(),
+                StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED
+            );
+        match self.decompress && !skip {
+            true => Ok(encoding::decode(resp)),
+            false => Ok(resp),
         }
     }
 
@@ -135,13 +285,13 @@ impl Client {
         let req = Request::builder()
             .method("GET")
             .uri(uri)
-            .body(Full::default())?;
+            .body(Payload::empty())?;
         self.request(req).await
     }
 
     pub async fn post<T>(&self, uri: &str, body: T) -> Result<Response<Bytes>, Error>
     where
-        T: Into<Full<Bytes>>,
+        T: Into<Payload>,
     {
         let req = Request::builder().method("POST").uri(uri).body(body)?;
         self.request(req).await
@@ -149,7 +299,7 @@ impl Client {
 
     pub async fn put<T>(&self, uri: &str, body: T) -> Result<Response<Bytes>, Error>
     where
-        T: Into<Full<Bytes>>,
+        T: Into<Payload>,
     {
         let req = Request::builder().method("PUT").uri(uri).body(body)?;
         self.request(req).await
@@ -159,31 +309,162 @@ impl Client {
         let req = Request::builder()
             .method("DELETE")
             .uri(uri)
-            .body(Full::default())?;
+            .body(Payload::empty())?;
         self.request(req).await
     }
 
     pub async fn patch<T>(&self, uri: &str, body: T) -> Result<Response<Bytes>, Error>
     where
-        T: Into<Full<Bytes>>,
+        T: Into<Payload>,
     {
         let req = Request::builder().method("PATCH").uri(uri).body(body)?;
         self.request(req).await
     }
 
+    /// GETs `uri` and decodes the JSON response body.
+    pub async fn get_json<T>(&self, uri: &str) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        let req = Request::builder()
+            .method("GET")
+            .uri(uri)
+            .header(ACCEPT, JSON)
+            .body(Payload::empty())?;
+        decode_json(self.request(req).await?)
+    }
+
+    /// POSTs `body` as JSON to `uri` and decodes the JSON response body.
+    pub async fn post_json<B, T>(&self, uri: &str, body: &B) -> Result<T, Error>
+    where
+        B: Serialize + ?Sized,
+        T: DeserializeOwned,
+    {
+        let req = Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header(CONTENT_TYPE, JSON)
+            .header(ACCEPT, JSON)
+            .body(Payload::from(serde_json::to_vec(body)?))?;
+        decode_json(self.request(req).await?)
+    }
+
+    /// POSTs a `multipart/form-data` body. File parts are streamed from
+    /// disk as the request is sent.
+    pub async fn post_multipart(
+        &self,
+        uri: &str,
+        form: multipart::Form,
+    ) -> Result<Response<Bytes>, Error> {
+        let req = Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header(CONTENT_TYPE, form.content_type())
+            .body(form.into_payload())?;
+        self.request(req).await
+    }
+
     pub async fn head(&self, uri: &str) -> Result<Response<Bytes>, Error> {
         let req = Request::builder()
             .method("HEAD")
             .uri(uri)
-            .body(Full::default())?;
+            .body(Payload::empty())?;
         self.request(req).await
     }
+
+    /// Opens a WebSocket. `uri` may use `ws`/`wss` or `http`/`https`.
+    /// The upgrade always goes over HTTP/1.1 and is neither retried nor
+    /// redirected.
+    pub async fn websocket(&self, uri: &str) -> Result<WebSocket, Error> {
+        let key = websocket::generate_key();
+        let req = Request::builder()
+            .method("GET")
+            .uri(uri)
+            .header(CONNECTION, "upgrade")
+            .header(UPGRADE, "websocket")
+            .header(SEC_WEBSOCKET_VERSION, "13")
+            .header(SEC_WEBSOCKET_KEY, &key)
+            .body(Payload::empty())?;
+        let (mut parts, body) = self.prepare(req)?;
+        let scheme = match parts.uri.scheme_str() {
+            Some("ws") => Some(Scheme::HTTP),
+            Some("wss") => Some(Scheme::HTTPS),
+            _ => None,
+        };
+        if let Some(scheme) = scheme {
+            let mut uri = parts.uri.into_parts();
+            uri.scheme = Some(scheme);
+            parts.uri = Uri::from_parts(uri).map_err(hyper::http::Error::from)?;
+        }
+
+        let resp = self
+            .transport
+            .send(Request::from_parts(parts, body.open()))
+            .await?;
+        if resp.status() != StatusCode::SWITCHING_PROTOCOLS {
+            let (head, body) = resp.into_parts();
+            return Err(Error::status_error(
+                head.status,
+                head.headers,
+                body.collect().await?.to_bytes(),
+            ));
+        }
+        websocket::check_response(&resp, &key)?;
+        let upgraded = hyper::upgrade::on(resp).await?;
+        Ok(WebSocket::from_raw(
+            TokioIo::new(upgraded),
+            websocket::Role::Client,
+        ))
+    }
+}
+
+const JSON: &str = "application/json";
+
+/// Turns non-2xx responses into `Error::Status`.
+fn check_status(resp: Response<Bytes>) -> Result<Response<Bytes>, Error> {
+    if resp.status().is_success() {
+        return Ok(resp);
+    }
+    let (parts, body) = resp.into_parts();
+    Err(Error::status_error(parts.status, parts.headers, body))
+}
+
+/// Whether a `Content-Type` value is JSON, including `+json` suffixes such
+/// as `application/problem+json`.
+pub(crate) fn is_json(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence == JSON || essence.ends_with("+json")
+}
+
+fn decode_json<T: DeserializeOwned>(resp: Response<Bytes>) -> Result<T, Error> {
+    if let Some(content_type) = resp.headers().get(CONTENT_TYPE) {
+        let content_type = content_type.to_str().unwrap_or_default();
+        if !is_json(content_type) {
+            return Err(Error::ContentType(content_type.to_string()));
+        }
+    }
+    // Treat an empty body as `null` so `()` and `Option<T>` decode a 204
+    let body = resp.body();
+    if body.is_empty() {
+        return Ok(serde_json::from_slice(b"null")?);
+    }
+    Ok(serde_json::from_slice(body)?)
 }
 
 pub struct ClientBuilder {
     timeout: Option<Duration>,
     base_url: Option<String>,
     headers: HeaderMap,
+    retry: RetryPolicy,
+    redirect: RedirectPolicy,
+    decompress: bool,
+    http: HttpTransport,
+    transport: Option<Arc<dyn Transport>>,
 }
 
 impl ClientBuilder {
@@ -192,14 +473,23 @@ impl ClientBuilder {
             timeout: Some(Duration::from_secs(60)),
             base_url: None,
             headers: HeaderMap::new(),
+            retry: RetryPolicy::none(),
+            redirect: RedirectPolicy::new(),
+            decompress: true,
+            http: HttpTransport::new(),
+            transport: None,
         }
     }
 
+    /// Limits how long each attempt may take to connect and receive the
+    /// response head; reading the body is not limited, so streams can run
+    /// longer. Defaults to 60 seconds.
     pub fn timeout(mut self, timeout: Duration) -> Self {
         self.timeout = Some(timeout);
         self
     }
 
+    /// Waits for connections and response heads as long as they take.
     pub fn no_timeout(mut self) -> Self {
         self.timeout = None;
         self
@@ -220,11 +510,74 @@ impl ClientBuilder {
         self
     }
 
+    /// Retry transient failures according to `policy`. Clients do not retry
+    /// by default.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// How redirects are followed. Up to 10 hops are followed by default.
+    pub fn redirect(mut self, policy: RedirectPolicy) -> Self {
+        self.redirect = policy;
+        self
+    }
+
+    /// Leaves compressed responses as they are and stops sending
+    /// `Accept-Encoding`.
+    pub fn no_decompression(mut self) -> Self {
+        self.decompress = false;
+        self
+    }
+
+    /// HTTP versions to use. Defaults to HTTP/2 where TLS negotiates it.
+    pub fn http_version(mut self, version: HttpVersion) -> Self {
+        self.http = self.http.version(version);
+        self
+    }
+
+    /// Shorthand for [`HttpVersion::Http2PriorKnowledge`], for talking h2c
+    /// to a local server.
+    pub fn http2_prior_knowledge(self) -> Self {
+        self.http_version(HttpVersion::Http2PriorKnowledge)
+    }
+
+    /// Sends requests through `proxy`. Without this, `HTTP_PROXY`,
+    /// `HTTPS_PROXY` and `NO_PROXY` from the environment apply.
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.http = self.http.proxy(proxy);
+        self
+    }
+
+    /// Ignores proxy settings from the environment.
+    pub fn no_proxy(mut self) -> Self {
+        self.http = self.http.no_proxy();
+        self
+    }
+
+    pub fn tls_config(mut self, config: crate::tls::rustls::ClientConfig) -> Self {
+        self.http = self.http.tls_config(config);
+        self
+    }
+
+    /// Sends requests through `transport` instead of opening HTTP
+    /// connections, e.g. a `MockTransport` in tests.
+    pub fn transport(mut self, transport: impl Transport) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
     pub fn build(self) -> Client {
         Client {
             timeout: self.timeout,
             base_url: self.base_url,
             headers: self.headers,
+            retry: self.retry,
+            redirect: self.redirect,
+            decompress: self.decompress,
+            transport: self
+                .transport
+                .unwrap_or_else(|| Arc::new(self.http.connect_timeout(self.timeout))),
         }
     }
 }
@@ -232,30 +585,29 @@ impl ClientBuilder {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::transport::MockTransport;
     use bytes::Bytes;
-    use http::Response;
-    use std::convert::Infallible;
+    use hyper::Method;
 
     #[tokio::test]
     async fn test_client_methods() {
-        let client = Client::new();
+        let mock = MockTransport::new();
+        mock.expect(Method::GET, "/").respond(200, "OK");
+        mock.expect(Method::POST, "/")
+            .body("test")
+            .respond(200, "OK");
+        mock.expect(Method::GET, "/path").respond(200, "OK");
 
-        // Mock response for testing
-        let mock_response = Response::builder()
-            .status(200)
-            .body(Bytes::from("OK"))
-            .unwrap();
-
-        Client::mock_response(mock_response);
+        let client = Client::builder().transport(mock.clone()).build();
 
         // Test GET
-        let response = client.get("http://test.com").await.unwrap();
+        let response = client.get("http://test.com/").await.unwrap();
         assert_eq!(response.status(), 200);
         assert_eq!(response.body(), &Bytes::from("OK"));
 
         // Test POST
         let response = client
-            .post("http://test.com", Full::new(Bytes::from("test")))
+            .post("http://test.com/", Full::new(Bytes::from("test")))
             .await
             .unwrap();
         assert_eq!(response.status(), 200);
@@ -264,10 +616,16 @@ mod tests {
         let client = Client::builder()
             .header("X-Test", "value")
             .base_url("http://test.com")
+            .transport(mock.clone())
             .build();
 
         let response = client.get("/path").await.unwrap();
         assert_eq!(response.status(), 200);
+
+        let requests = mock.requests();
+        assert_eq!(requests[2].uri(), "http://test.com/path");
+        assert_eq!(requests[2].headers()["x-test"], "value");
+        mock.assert_done();
     }
 
     #[tokio::test]
@@ -282,4 +640,229 @@ mod tests {
         assert_eq!(client.base_url.as_deref(), Some("http://test.com"));
         assert!(client.headers.contains_key("user-agent"));
     }
+
+    #[tokio::test]
+    async fn test_client_timeout() {
+        // Accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                held.push(stream);
+            }
+        });
+
+        let client = Client::builder()
+            .timeout(Duration::from_millis(100))
+            .no_proxy()
+            .build();
+        match client.get(&format!("http://{}/", addr)).await {
+            Err(Error::Connect(e)) => assert_eq!(e.kind(), std::io::ErrorKind::TimedOut),
+            other => panic!("expected a timeout, got {:?}", other.map(|r| r.status())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_retry_budget() {
+        let mock = MockTransport::new();
+        let limited = || {
+            Response::builder()
+                .status(429)
+                .header("retry-after", "0")
+                .body(Bytes::new())
+                .unwrap()
+        };
+        mock.expect(Method::POST, "/")
+            .respond_with(limited())
+            .respond_with(limited())
+            .respond_with(limited());
+
+        let client = Client::builder()
+            .retry(
+                RetryPolicy::new()
+                    .max_attempts(3)
+                    .base_delay(Duration::from_millis(1)),
+            )
+            .transport(mock.clone())
+            .build();
+
+        // Every attempt is rate limited, so the last status comes back once
+        // the budget is spent
+        let error = client
+            .post("http://test.com/", Bytes::new())
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), Some(StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(mock.requests().len(), 3);
+        mock.assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_client_retry_recovers() {
+        let mock = MockTransport::new();
+        mock.expect(Method::GET, "/")
+            .respond(503, "")
+            .respond(200, "OK");
+        // Not idempotent, so the 502 is not retried
+        mock.expect(Method::POST, "/")
+            .respond(502, "")
+            .respond(200, "OK");
+
+        let client = Client::builder()
+            .retry(RetryPolicy::new().base_delay(Duration::from_millis(1)))
+            .transport(mock.clone())
+            .build();
+
+        let response = client.get("http://test.com/").await.unwrap();
+        assert_eq!(response.status(), 200);
+
+        let error = client
+            .post("http://test.com/", Bytes::new())
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), Some(StatusCode::BAD_GATEWAY));
+    }
+
+    #[tokio::test]
+    async fn test_client_execute() {
+        let mock = MockTransport::new();
+        mock.expect(Method::POST, "/login")
+            .body("user=ada")
+            .respond(200, "welcome");
+        let client = Client::builder().transport(mock.clone()).build();
+
+        let req = common::Request::post("http://test.com/login")
+            .form(&[("user", "ada")])
+            .unwrap();
+        let res = client.execute(req).await.unwrap();
+        assert_eq!(res.body.text().unwrap(), "welcome");
+        let sent = &mock.requests()[0];
+        assert_eq!(
+            sent.headers()[CONTENT_TYPE],
+            "application/x-www-form-urlencoded"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_redirects() {
+        let redirect = |status: u16, location: &str| {
+            Response::builder()
+                .status(status)
+                .header("location", location)
+                .body(Bytes::new())
+                .unwrap()
+        };
+        let mock = MockTransport::new();
+        mock.expect(Method::POST, "/submit")
+            .respond_with(redirect(303, "/done"));
+        mock.expect(Method::GET, "/done").respond(200, "done");
+        mock.expect(Method::PUT, "/upload")
+            .respond_with(redirect(307, "http://other.com/upload"));
+        mock.expect(Method::PUT, "/upload").respond(200, "stored");
+        mock.expect(Method::GET, "/loop")
+            .respond_with(redirect(302, "loop"))
+            .respond_with(redirect(302, "loop"))
+            .respond_with(redirect(302, "loop"));
+
+        let client = Client::builder()
+            .base_url("http://test.com")
+            .header("authorization", "Bearer secret")
+            .redirect(RedirectPolicy::new().max_hops(2))
+            .transport(mock.clone())
+            .build();
+
+        // 303 turns into a GET without the body, same origin keeps auth
+        let response = client.post("/submit", "payload").await.unwrap();
+        assert_eq!(response.body(), &Bytes::from("done"));
+        let requests = mock.requests();
+        assert_eq!(requests[1].method(), Method::GET);
+        assert!(requests[1].body().is_empty());
+        assert_eq!(requests[1].headers()["authorization"], "Bearer secret");
+        assert_eq!(
+            requests[1].headers()[ACCEPT_ENCODING],
+            "gzip, deflate, br, zstd"
+        );
+
+        // 307 keeps method and body, crossing origins drops credentials
+        let response = client.put("/upload", "data").await.unwrap();
+        assert_eq!(response.body(), &Bytes::from("stored"));
+        let requests = mock.requests();
+        assert_eq!(requests[3].uri(), "http://other.com/upload");
+        assert_eq!(requests[3].body(), &Bytes::from("data"));
+        assert!(!requests[3].headers().contains_key("authorization"));
+
+        let error = client.get("/loop").await.unwrap_err();
+        assert!(matches!(error, Error::TooManyRedirects(2)));
+        mock.assert_done();
+
+        // Not following hands back the redirect as a status error
+        let mock = MockTransport::new();
+        mock.expect(Method::GET, "/moved")
+            .respond_with(redirect(301, "http://other.com/"));
+        let client = Client::builder()
+            .redirect(RedirectPolicy::none())
+            .transport(mock.clone())
+            .build();
+        let error = client.get("http://test.com/moved").await.unwrap_err();
+        assert_eq!(error.status(), Some(StatusCode::MOVED_PERMANENTLY));
+        mock.assert_done();
+    }
+
+    #[tokio::test]
+    async fn test_client_json() {
+        #[derive(serde::Serialize)]
+        struct Ask {
+            prompt: &'static str,
+        }
+
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Answer {
+            text: String,
+        }
+
+        #[derive(serde::Deserialize)]
+        struct ApiError {
+            error: String,
+        }
+
+        let mock = MockTransport::new();
+        mock.expect(Method::POST, "/ask")
+            .body(r#"{"prompt":"hi"}"#)
+            .respond_with(
+                Response::builder()
+                    .header(CONTENT_TYPE, "application/json; charset=utf-8")
+                    .body(Bytes::from(r#"{"text":"hello"}"#))
+                    .unwrap(),
+            );
+        mock.expect(Method::GET, "/missing")
+            .respond(404, r#"{"error":"not found"}"#);
+        mock.expect(Method::GET, "/page").respond_with(
+            Response::builder()
+                .header(CONTENT_TYPE, "text/html")
+                .body(Bytes::from("<html>"))
+                .unwrap(),
+        );
+
+        let client = Client::builder()
+            .base_url("http://test.com")
+            .transport(mock.clone())
+            .build();
+
+        let answer: Answer = client
+            .post_json("/ask", &Ask { prompt: "hi" })
+            .await
+            .unwrap();
+        assert_eq!(answer.text, "hello");
+        let request = &mock.requests()[0];
+        assert_eq!(request.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(request.headers()[ACCEPT], "application/json");
+
+        let error = client.get_json::<Answer>("/missing").await.unwrap_err();
+        assert_eq!(error.status(), Some(StatusCode::NOT_FOUND));
+        assert_eq!(error.json_body::<ApiError>().unwrap().error, "not found");
+
+        let error = client.get_json::<Answer>("/page").await.unwrap_err();
+        assert!(matches!(error, Error::ContentType(ct) if ct == "text/html"));
+    }
 }
@@ -0,0 +1,217 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    name: String,
+    value: String,
+}
+
+impl Header {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+/// Ordered header list. Lookups ignore case and repeated names keep every
+/// value.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Headers(Vec<Header>);
+
+impl Headers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// First value of `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .map(|h| h.value.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.0
+            .iter()
+            .filter(move |h| h.name.eq_ignore_ascii_case(name))
+            .map(|h| h.value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Sets `name`, replacing any earlier values.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.remove(&name);
+        self.0.push(Header::new(name, value));
+    }
+
+    /// Adds another value for `name`.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.0.push(Header::new(name, value));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.0.retain(|h| !h.name.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Header> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Media type without parameters, lowercased, e.g. `application/json`.
+    pub fn content_type(&self) -> Option<String> {
+        let value = self.get("content-type")?;
+        let media = value.split(';').next().unwrap_or_default().trim();
+        (!media.is_empty()).then(|| media.to_ascii_lowercase())
+    }
+
+    pub fn authorization(&self) -> Option<Authorization> {
+        self.get("authorization").map(Authorization::parse)
+    }
+
+    /// Delay requested by `Retry-After`, in seconds or as an HTTP date.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.get("retry-after")
+            .and_then(crate::retry::parse_retry_after)
+    }
+}
+
+impl From<&HeaderMap> for Headers {
+    /// Values that are not valid UTF-8 are dropped.
+    fn from(map: &HeaderMap) -> Self {
+        Self(
+            map.iter()
+                .filter_map(|(name, value)| Some(Header::new(name.as_str(), value.to_str().ok()?)))
+                .collect(),
+        )
+    }
+}
+
+impl TryFrom<&Headers> for HeaderMap {
+    type Error = hyper::http::Error;
+
+    fn try_from(headers: &Headers) -> Result<Self, Self::Error> {
+        let mut map = HeaderMap::with_capacity(headers.len());
+        for h in headers.iter() {
+            map.append(
+                HeaderName::from_bytes(h.name.as_bytes())?,
+                HeaderValue::from_str(&h.value)?,
+            );
+        }
+        Ok(map)
+    }
+}
+
+impl<N: Into<String>, V: Into<String>> FromIterator<(N, V)> for Headers {
+    fn from_iter<I: IntoIterator<Item = (N, V)>>(iter: I) -> Self {
+        Self(iter.into_iter().map(|(n, v)| Header::new(n, v)).collect())
+    }
+}
+
+impl<'a> IntoIterator for &'a Headers {
+    type Item = &'a Header;
+    type IntoIter = std::slice::Iter<'a, Header>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// Credentials from an `Authorization` header.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Authorization {
+    Bearer(String),
+    Basic {
+        username: String,
+        password: String,
+    },
+    /// Any other scheme, kept verbatim.
+    Other(String),
+}
+
+impl Authorization {
+    pub fn bearer(token: impl Into<String>) -> Self {
+        Authorization::Bearer(token.into())
+    }
+
+    pub fn basic(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Authorization::Basic {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
+    /// Parses a header value. Malformed basic credentials fall back to
+    /// [`Authorization::Other`].
+    pub fn parse(value: &str) -> Self {
+        let value = value.trim();
+        let (scheme, rest) = value.split_once(' ').unwrap_or((value, ""));
+        let rest = rest.trim();
+        if scheme.eq_ignore_ascii_case("bearer") {
+            return Authorization::Bearer(rest.to_string());
+        }
+        if scheme.eq_ignore_ascii_case("basic") {
+            let decoded = BASE64
+                .decode(rest)
+                .ok()
+                .and_then(|raw| String::from_utf8(raw).ok());
+            if let Some((username, password)) = decoded.as_deref().and_then(|d| d.split_once(':')) {
+                return Authorization::basic(username, password);
+            }
+        }
+        Authorization::Other(value.to_string())
+    }
+
+    pub fn to_header_value(&self) -> String {
+        match self {
+            Authorization::Bearer(token) => format!("Bearer {}", token),
+            Authorization::Basic { username, password } => {
+                format!(
+                    "Basic {}",
+                    BASE64.encode(format!("{}:{}", username, password))
+                )
+            }
+            Authorization::Other(value) => value.clone(),
+        }
+    }
+}
+
+/// Secrets are not printed.
+impl fmt::Debug for Authorization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Authorization::Bearer(_) => f.write_str("Bearer(..)"),
+            Authorization::Basic { username, .. } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .finish_non_exhaustive(),
+            Authorization::Other(_) => f.write_str("Other(..)"),
+        }
+    }
+}
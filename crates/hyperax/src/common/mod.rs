@@ -1,37 +1,224 @@
-mod parser;
+//! Plain request and response types for code that should not depend on
+//! hyper directly. They convert to and from hyper's types at the edges and
+//! [`Client::execute`](crate::Client::execute) sends them as is.
+//!
+//! ```
+//! use hyperax::common::{Authorization, Request};
+//!
+//! let req = Request::post("https://api.example.com/v1/items")
+//!     .bearer_auth("secret")
+//!     .json(&serde_json::json!({ "name": "widget" }))
+//!     .unwrap();
+//! assert_eq!(req.headers.content_type().as_deref(), Some("application/json"));
+//! assert_eq!(req.headers.authorization(), Some(Authorization::bearer("secret")));
+//! ```
+
+mod headers;
+pub mod parser;
+
+pub use headers::{Authorization, Header, Headers};
+pub use hyper::{Method, StatusCode};
 
 use http_body_util::Full;
 use hyper::body::Bytes;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+const JSON: &str = "application/json";
+const FORM: &str = "application/x-www-form-urlencoded";
+const TEXT: &str = "text/plain; charset=utf-8";
+
+#[derive(Debug, thiserror::Error)]
+pub enum BodyError {
+    #[error("body is not valid UTF-8")]
+    Utf8(#[from] std::str::Utf8Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("form error: {0}")]
+    FormEncode(#[from] serde_urlencoded::ser::Error),
+    #[error("form error: {0}")]
+    FormDecode(#[from] serde_urlencoded::de::Error),
+}
 
-pub struct Body(Vec<u8>);
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Body(Bytes);
 
 impl Body {
-    pub fn new(data: impl Into<Vec<u8>>) -> Self {
+    pub fn new(data: impl Into<Bytes>) -> Self {
         Self(data.into())
     }
 
     pub fn empty() -> Self {
-        Self(Vec::new())
+        Self(Bytes::new())
+    }
+
+    pub fn json<T: Serialize + ?Sized>(value: &T) -> Result<Self, BodyError> {
+        Ok(Self(serde_json::to_vec(value)?.into()))
+    }
+
+    /// `application/x-www-form-urlencoded` encoding of `value`.
+    pub fn form<T: Serialize + ?Sized>(value: &T) -> Result<Self, BodyError> {
+        Ok(Self(serde_urlencoded::to_string(value)?.into()))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn text(&self) -> Result<&str, BodyError> {
+        Ok(std::str::from_utf8(&self.0)?)
+    }
+
+    pub fn parse_json<T: DeserializeOwned>(&self) -> Result<T, BodyError> {
+        Ok(serde_json::from_slice(&self.0)?)
+    }
+
+    pub fn parse_form<T: DeserializeOwned>(&self) -> Result<T, BodyError> {
+        Ok(serde_urlencoded::from_bytes(&self.0)?)
     }
 }
 
 impl From<Body> for Full<Bytes> {
     fn from(body: Body) -> Self {
-        Full::new(Bytes::from(body.0))
+        Full::new(body.0)
+    }
+}
+
+impl From<Body> for Bytes {
+    fn from(body: Body) -> Self {
+        body.0
+    }
+}
+
+impl From<Bytes> for Body {
+    fn from(bytes: Bytes) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(data: Vec<u8>) -> Self {
+        Self(data.into())
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Self(text.into())
+    }
+}
+
+impl From<&'static str> for Body {
+    fn from(text: &'static str) -> Self {
+        Self(Bytes::from_static(text.as_bytes()))
     }
 }
 
 #[derive(Clone, Debug)]
-pub struct Header {
-    name: String,
-    value: String,
+pub struct Request {
+    pub method: Method,
+    pub uri: String,
+    pub headers: Headers,
+    pub body: Body,
 }
 
-impl Header {
-    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+impl Request {
+    pub fn new(method: Method, uri: impl Into<String>) -> Self {
         Self {
-            name: name.into(),
-            value: value.into(),
+            method,
+            uri: uri.into(),
+            headers: Headers::new(),
+            body: Body::empty(),
+        }
+    }
+
+    pub fn get(uri: impl Into<String>) -> Self {
+        Self::new(Method::GET, uri)
+    }
+
+    pub fn post(uri: impl Into<String>) -> Self {
+        Self::new(Method::POST, uri)
+    }
+
+    pub fn put(uri: impl Into<String>) -> Self {
+        Self::new(Method::PUT, uri)
+    }
+
+    pub fn patch(uri: impl Into<String>) -> Self {
+        Self::new(Method::PATCH, uri)
+    }
+
+    pub fn delete(uri: impl Into<String>) -> Self {
+        Self::new(Method::DELETE, uri)
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn authorization(self, auth: &Authorization) -> Self {
+        self.header("authorization", auth.to_header_value())
+    }
+
+    pub fn bearer_auth(self, token: impl Into<String>) -> Self {
+        self.authorization(&Authorization::bearer(token))
+    }
+
+    pub fn basic_auth(self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.authorization(&Authorization::basic(username, password))
+    }
+
+    pub fn body(mut self, body: impl Into<Body>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn text(self, text: impl Into<String>) -> Self {
+        self.header("content-type", TEXT).body(text.into())
+    }
+
+    pub fn json<T: Serialize + ?Sized>(self, value: &T) -> Result<Self, BodyError> {
+        Ok(self.header("content-type", JSON).body(Body::json(value)?))
+    }
+
+    pub fn form<T: Serialize + ?Sized>(self, value: &T) -> Result<Self, BodyError> {
+        Ok(self.header("content-type", FORM).body(Body::form(value)?))
+    }
+}
+
+impl TryFrom<Request> for hyper::Request<Full<Bytes>> {
+    type Error = hyper::http::Error;
+
+    fn try_from(req: Request) -> Result<Self, Self::Error> {
+        let mut builder = hyper::Request::builder().method(req.method).uri(req.uri);
+        if let Some(headers) = builder.headers_mut() {
+            *headers = (&req.headers).try_into()?;
+        }
+        builder.body(req.body.into())
+    }
+}
+
+impl<B: Into<Body>> From<hyper::Request<B>> for Request {
+    fn from(req: hyper::Request<B>) -> Self {
+        let (parts, body) = req.into_parts();
+        Self {
+            method: parts.method,
+            uri: parts.uri.to_string(),
+            headers: (&parts.headers).into(),
+            body: body.into(),
         }
     }
 }
@@ -39,6 +226,143 @@ impl Header {
 #[derive(Clone, Debug)]
 pub struct Response {
     pub status: u16,
-    pub headers: Vec<Header>,
-    pub body: Vec<u8>,
+    pub headers: Headers,
+    pub body: Body,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Headers::new(),
+            body: Body::empty(),
+        }
+    }
+
+    pub fn ok() -> Self {
+        Self::new(200)
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Body>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn text(self, text: impl Into<String>) -> Self {
+        self.header("content-type", TEXT).body(text.into())
+    }
+
+    pub fn json<T: Serialize + ?Sized>(self, value: &T) -> Result<Self, BodyError> {
+        Ok(self.header("content-type", JSON).body(Body::json(value)?))
+    }
+
+    pub fn form<T: Serialize + ?Sized>(self, value: &T) -> Result<Self, BodyError> {
+        Ok(self.header("content-type", FORM).body(Body::form(value)?))
+    }
+}
+
+impl TryFrom<Response> for hyper::Response<Full<Bytes>> {
+    type Error = hyper::http::Error;
+
+    fn try_from(res: Response) -> Result<Self, Self::Error> {
+        let mut builder = hyper::Response::builder().status(res.status);
+        if let Some(headers) = builder.headers_mut() {
+            *headers = (&res.headers).try_into()?;
+        }
+        builder.body(res.body.into())
+    }
+}
+
+impl<B: Into<Body>> From<hyper::Response<B>> for Response {
+    fn from(res: hyper::Response<B>) -> Self {
+        let (parts, body) = res.into_parts();
+        Self {
+            status: parts.status.as_u16(),
+            headers: (&parts.headers).into(),
+            body: body.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::time::Duration;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Login {
+        user: String,
+        remember: bool,
+    }
+
+    #[test]
+    fn test_body_helpers() {
+        let login = Login {
+            user: "ada lovelace".into(),
+            remember: true,
+        };
+        let body = Body::form(&login).unwrap();
+        assert_eq!(body.text().unwrap(), "user=ada+lovelace&remember=true");
+        assert_eq!(body.parse_form::<Login>().unwrap(), login);
+
+        let body = Body::json(&login).unwrap();
+        assert_eq!(body.parse_json::<Login>().unwrap(), login);
+        assert!(Body::new(vec![0xff]).text().is_err());
+    }
+
+    #[test]
+    fn test_typed_headers() {
+        let mut headers: Headers = [
+            ("Content-Type", "Application/JSON; charset=utf-8"),
+            ("Retry-After", "20"),
+            ("Set-Cookie", "a=1"),
+        ]
+        .into_iter()
+        .collect();
+        headers.append("set-cookie", "b=2");
+        assert_eq!(headers.content_type().as_deref(), Some("application/json"));
+        assert_eq!(headers.retry_after(), Some(Duration::from_secs(20)));
+        assert_eq!(headers.get_all("SET-COOKIE").count(), 2);
+
+        let basic = Authorization::basic("user", "p:ss");
+        headers.insert("Authorization", basic.to_header_value());
+        assert_eq!(headers.authorization(), Some(basic));
+        assert_eq!(
+            Authorization::parse("Digest abc"),
+            Authorization::Other("Digest abc".into())
+        );
+    }
+
+    #[test]
+    fn test_hyper_conversions() {
+        let req = Request::put("http://localhost/items/1")
+            .basic_auth("user", "pass")
+            .text("hello");
+        let hyper_req = hyper::Request::<Full<Bytes>>::try_from(req).unwrap();
+        assert_eq!(hyper_req.method(), Method::PUT);
+        assert_eq!(hyper_req.headers()["authorization"], "Basic dXNlcjpwYXNz");
+
+        let res = Response::new(404).header("x-bad\n", "v");
+        assert!(hyper::Response::<Full<Bytes>>::try_from(res).is_err());
+
+        let hyper_res = hyper::Response::builder()
+            .status(201)
+            .header("content-type", "text/plain")
+            .body(Bytes::from("made"))
+            .unwrap();
+        let res = Response::from(hyper_res);
+        assert!(res.is_success());
+        assert_eq!(res.headers.content_type().as_deref(), Some("text/plain"));
+        assert_eq!(res.body.text().unwrap(), "made");
+    }
 }
@@ -1,82 +1,653 @@
-use bytes::{Buf, Bytes};
-use hyper::{Request, Response, Version};
-use std::io::{self, Cursor};
+//! Incremental HTTP/1.x message parser.
+//!
+//! Input can arrive in any number of pieces: feed each one with
+//! [`Parser::feed`] and call `parse_request` or `parse_response` until it
+//! returns [`Status::Complete`]. Malformed input is reported as a
+//! [`ParseError`], never a panic.
 
+use bytes::{Buf, Bytes, BytesMut};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, TRANSFER_ENCODING};
+use hyper::{Method, Request, Response, StatusCode, Uri, Version};
+
+const DEFAULT_MAX_HEAD_SIZE: usize = 64 * 1024;
+const DEFAULT_MAX_HEADERS: usize = 100;
+/// Longest chunk size line, extensions included.
+const MAX_CHUNK_LINE: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ParseError {
+    #[error("malformed message head: {0}")]
+    Head(#[from] httparse::Error),
+    #[error("message head exceeds {0} bytes")]
+    HeadTooLarge(usize),
+    #[error("invalid method")]
+    Method,
+    #[error("invalid request target")]
+    Uri,
+    #[error("invalid status code {0}")]
+    Status(u16),
+    #[error("invalid header name")]
+    HeaderName,
+    #[error("invalid value for header {0}")]
+    HeaderValue(String),
+    #[error("invalid content-length")]
+    ContentLength,
+    #[error("unsupported transfer-encoding")]
+    TransferEncoding,
+    #[error("invalid chunk size")]
+    ChunkSize,
+    #[error("malformed chunked body")]
+    Chunk,
+    #[error("body exceeds {0} bytes")]
+    BodyTooLarge(usize),
+    #[error("input ended in the middle of a message")]
+    UnexpectedEof,
+    #[error("parser is in the middle of a {0}")]
+    WrongKind(&'static str),
+}
+
+/// Result of a parse attempt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status<T> {
+    /// A full message, and how many input bytes it took.
+    Complete(T, usize),
+    /// More input is needed; feed it and call the parser again.
+    Partial,
+}
+
+impl<T> Status<T> {
+    pub fn complete(self) -> Option<T> {
+        match self {
+            Status::Complete(message, _) => Some(message),
+            Status::Partial => None,
+        }
+    }
+}
+
+/// How the end of the body is found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    Length(u64),
+    Chunked(Chunk),
+    /// Responses without a length run until the connection closes.
+    UntilEof,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Chunk {
+    Size,
+    Data(u64),
+    DataEnd,
+    Trailers,
+}
+
+#[derive(Debug)]
+enum Head {
+    Request(hyper::http::request::Parts),
+    Response(hyper::http::response::Parts),
+}
+
+/// A message whose head has been parsed and whose body is still arriving.
+#[derive(Debug)]
+struct Pending {
+    head: Head,
+    framing: Framing,
+    body: BytesMut,
+    consumed: usize,
+}
+
+#[derive(Debug)]
 pub struct Parser {
-    buf: Cursor<Bytes>,
+    buf: BytesMut,
+    pending: Option<Pending>,
+    eof: bool,
+    max_head_size: usize,
+    max_headers: usize,
+    max_body_size: Option<usize>,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self {
+            buf: BytesMut::new(),
+            pending: None,
+            eof: false,
+            max_head_size: DEFAULT_MAX_HEAD_SIZE,
+            max_headers: DEFAULT_MAX_HEADERS,
+            max_body_size: None,
+        }
+    }
 }
 
 impl Parser {
+    /// A parser primed with `bytes`. More input can follow with `feed`.
     pub fn new(bytes: Bytes) -> Self {
-        Self {
-            buf: Cursor::new(bytes),
+        let mut parser = Self::default();
+        parser.feed(&bytes);
+        parser
+    }
+
+    pub fn max_head_size(mut self, bytes: usize) -> Self {
+        self.max_head_size = bytes;
+        self
+    }
+
+    pub fn max_headers(mut self, count: usize) -> Self {
+        self.max_headers = count;
+        self
+    }
+
+    pub fn max_body_size(mut self, bytes: usize) -> Self {
+        self.max_body_size = Some(bytes);
+        self
+    }
+
+    /// Appends input. Bytes past the end of a message are kept for the next
+    /// one, so pipelined messages parse one after another.
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Marks the end of input. A response without a length then completes
+    /// with whatever body arrived; anything else left half-parsed is an
+    /// [`ParseError::UnexpectedEof`].
+    pub fn eof(&mut self) {
+        self.eof = true;
+    }
+
+    /// Input not yet consumed by a complete message.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn parse_request(&mut self) -> Result<Status<Request<Bytes>>, ParseError> {
+        if self.pending.is_none() {
+            match self.request_head()? {
+                Some(pending) => self.pending = Some(pending),
+                None => return self.partial(),
+            }
         }
+        if let Some(Pending {
+            head: Head::Response(_),
+            ..
+        }) = self.pending
+        {
+            return Err(ParseError::WrongKind("response"));
+        }
+        Ok(match self.body()? {
+            Status::Complete((Head::Request(parts), body), n) => {
+                Status::Complete(Request::from_parts(parts, body), n)
+            }
+            _ => Status::Partial,
+        })
     }
 
-    pub fn parse_request(&mut self) -> io::Result<Request<()>> {
-        let mut headers = [httparse::EMPTY_HEADER; 64];
-        let mut req = httparse::Request::new(&mut headers);
+    pub fn parse_response(&mut self) -> Result<Status<Response<Bytes>>, ParseError> {
+        if self.pending.is_none() {
+            match self.response_head()? {
+                Some(pending) => self.pending = Some(pending),
+                None => return self.partial(),
+            }
+        }
+        if let Some(Pending {
+            head: Head::Request(_),
+            ..
+        }) = self.pending
+        {
+            return Err(ParseError::WrongKind("request"));
+        }
+        Ok(match self.body()? {
+            Status::Complete((Head::Response(parts), body), n) => {
+                Status::Complete(Response::from_parts(parts, body), n)
+            }
+            _ => Status::Partial,
+        })
+    }
 
-        let chunk = self.buf.chunk().to_vec();
-        let status = req.parse(&chunk).unwrap();
+    fn partial<T>(&self) -> Result<Status<T>, ParseError> {
+        if self.eof && !self.buf.is_empty() {
+            return Err(ParseError::UnexpectedEof);
+        }
+        if self.buf.len() > self.max_head_size {
+            return Err(ParseError::HeadTooLarge(self.max_head_size));
+        }
+        Ok(Status::Partial)
+    }
 
-        if !status.is_complete() {
-            return Err(io::Error::new(
-                io::ErrorKind::WouldBlock,
-                "incomplete HTTP request",
-            ));
+    fn request_head(&mut self) -> Result<Option<Pending>, ParseError> {
+        let mut headers = vec![httparse::EMPTY_HEADER; self.max_headers];
+        let mut req = httparse::Request::new(&mut headers);
+        let len = match req.parse(&self.buf)? {
+            httparse::Status::Complete(len) => len,
+            httparse::Status::Partial => return Ok(None),
+        };
+        if len > self.max_head_size {
+            return Err(ParseError::HeadTooLarge(self.max_head_size));
         }
 
+        let method = req.method.ok_or(ParseError::Method)?;
+        let method = Method::from_bytes(method.as_bytes()).map_err(|_| ParseError::Method)?;
+        let uri: Uri = req
+            .path
+            .ok_or(ParseError::Uri)?
+            .parse()
+            .map_err(|_| ParseError::Uri)?;
         let mut builder = Request::builder()
-            .method(req.method.unwrap())
-            .uri(req.path.unwrap())
-            .version(Version::HTTP_11);
-
-        if let Some(headers) = builder.headers_mut() {
-            for h in req.headers {
-                headers.insert(
-                    h.name.parse::<hyper::header::HeaderName>().unwrap(),
-                    String::from_utf8_lossy(h.value)
-                        .into_owned()
-                        .try_into()
-                        .unwrap(),
-                );
-            }
+            .method(method)
+            .uri(uri)
+            .version(version(req.version));
+        if let Some(map) = builder.headers_mut() {
+            append_headers(map, req.headers)?;
         }
+        let (parts, ()) = builder.body(()).map_err(|_| ParseError::Uri)?.into_parts();
 
-        Ok(builder.body(()).unwrap())
+        // Requests without a length have no body
+        let framing = framing(&parts.headers, Framing::Length(0), true)?;
+        self.buf.advance(len);
+        Ok(Some(Pending {
+            head: Head::Request(parts),
+            framing,
+            body: BytesMut::new(),
+            consumed: len,
+        }))
     }
 
-    pub fn parse_response(&mut self) -> io::Result<Response<()>> {
-        let mut headers = [httparse::EMPTY_HEADER; 64];
+    fn response_head(&mut self) -> Result<Option<Pending>, ParseError> {
+        let mut headers = vec![httparse::EMPTY_HEADER; self.max_headers];
         let mut res = httparse::Response::new(&mut headers);
+        let len = match res.parse(&self.buf)? {
+            httparse::Status::Complete(len) => len,
+            httparse::Status::Partial => return Ok(None),
+        };
+        if len > self.max_head_size {
+            return Err(ParseError::HeadTooLarge(self.max_head_size));
+        }
 
-        let chunk = self.buf.chunk().to_vec();
-        let status = res.parse(&chunk).unwrap();
+        let code = res.code.unwrap_or(0);
+        let status = StatusCode::from_u16(code).map_err(|_| ParseError::Status(code))?;
+        let mut builder = Response::builder()
+            .status(status)
+            .version(version(res.version));
+        if let Some(map) = builder.headers_mut() {
+            append_headers(map, res.headers)?;
+        }
+        let (parts, ()) = builder
+            .body(())
+            .map_err(|_| ParseError::Status(code))?
+            .into_parts();
 
-        if !status.is_complete() {
-            return Err(io::Error::new(
-                io::ErrorKind::WouldBlock,
-                "incomplete HTTP response",
-            ));
+        let framing = if status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+        {
+            Framing::Length(0)
+        } else {
+            framing(&parts.headers, Framing::UntilEof, false)?
+        };
+        self.buf.advance(len);
+        Ok(Some(Pending {
+            head: Head::Response(parts),
+            framing,
+            body: BytesMut::new(),
+            consumed: len,
+        }))
+    }
+
+    /// Moves body bytes out of the buffer until the pending message is done.
+    fn body(&mut self) -> Result<Status<(Head, Bytes)>, ParseError> {
+        let Some(pending) = self.pending.as_mut() else {
+            return Ok(Status::Partial);
+        };
+
+        loop {
+            match pending.framing {
+                Framing::Length(0) => break,
+                Framing::Length(remaining) => {
+                    let n = remaining.min(self.buf.len() as u64) as usize;
+                    if n == 0 {
+                        return self.body_partial();
+                    }
+                    pending.body.extend_from_slice(&self.buf.split_to(n));
+                    pending.consumed += n;
+                    pending.framing = Framing::Length(remaining - n as u64);
+                }
+                Framing::UntilEof => {
+                    let n = self.buf.len();
+                    pending.body.extend_from_slice(&self.buf.split_to(n));
+                    pending.consumed += n;
+                    if !self.eof {
+                        check_body_size(&pending.body, self.max_body_size)?;
+                        return Ok(Status::Partial);
+                    }
+                    break;
+                }
+                Framing::Chunked(Chunk::Size) => {
+                    let Some(line) = line(&self.buf) else {
+                        if self.buf.len() > MAX_CHUNK_LINE {
+                            return Err(ParseError::ChunkSize);
+                        }
+                        return self.body_partial();
+                    };
+                    let size = chunk_size(&self.buf[..line])?;
+                    self.buf.advance(line + 2);
+                    pending.consumed += line + 2;
+                    pending.framing = Framing::Chunked(match size {
+                        0 => Chunk::Trailers,
+                        n => Chunk::Data(n),
+                    });
+                }
+                Framing::Chunked(Chunk::Data(remaining)) => {
+                    let n = remaining.min(self.buf.len() as u64) as usize;
+                    if n == 0 {
+                        return self.body_partial();
+                    }
+                    pending.body.extend_from_slice(&self.buf.split_to(n));
+                    pending.consumed += n;
+                    pending.framing = Framing::Chunked(match remaining - n as u64 {
+                        0 => Chunk::DataEnd,
+                        left => Chunk::Data(left),
+                    });
+                }
+                Framing::Chunked(Chunk::DataEnd) => {
+                    if self.buf.len() < 2 {
+                        return self.body_partial();
+                    }
+                    if &self.buf[..2] != b"\r\n" {
+                        return Err(ParseError::Chunk);
+                    }
+                    self.buf.advance(2);
+                    pending.consumed += 2;
+                    pending.framing = Framing::Chunked(Chunk::Size);
+                }
+                Framing::Chunked(Chunk::Trailers) => {
+                    let mut trailers = vec![httparse::EMPTY_HEADER; self.max_headers];
+                    match httparse::parse_headers(&self.buf, &mut trailers)? {
+                        httparse::Status::Complete((len, trailers)) => {
+                            let headers = match &mut pending.head {
+                                Head::Request(parts) => &mut parts.headers,
+                                Head::Response(parts) => &mut parts.headers,
+                            };
+                            append_headers(headers, trailers)?;
+                            self.buf.advance(len);
+                            pending.consumed += len;
+                            break;
+                        }
+                        httparse::Status::Partial => {
+                            if self.buf.len() > self.max_head_size {
+                                return Err(ParseError::HeadTooLarge(self.max_head_size));
+                            }
+                            return self.body_partial();
+                        }
+                    }
+                }
+            }
+            check_body_size(&pending.body, self.max_body_size)?;
         }
 
-        let mut builder = Response::builder()
-            .status(res.code.unwrap())
-            .version(Version::HTTP_11);
-
-        if let Some(headers) = builder.headers_mut() {
-            for h in res.headers {
-                headers.insert(
-                    h.name.parse::<hyper::header::HeaderName>().unwrap(),
-                    String::from_utf8_lossy(h.value)
-                        .into_owned()
-                        .try_into()
-                        .unwrap(),
-                );
+        let pending = self.pending.take().expect("pending message");
+        Ok(Status::Complete(
+            (pending.head, pending.body.freeze()),
+            pending.consumed,
+        ))
+    }
+
+    fn body_partial<T>(&self) -> Result<Status<T>, ParseError> {
+        if self.eof {
+            return Err(ParseError::UnexpectedEof);
+        }
+        Ok(Status::Partial)
+    }
+}
+
+fn check_body_size(body: &[u8], limit: Option<usize>) -> Result<(), ParseError> {
+    match limit {
+        Some(limit) if body.len() > limit => Err(ParseError::BodyTooLarge(limit)),
+        _ => Ok(()),
+    }
+}
+
+/// Repeated headers such as `Set-Cookie` keep every value.
+fn append_headers(map: &mut HeaderMap, headers: &[httparse::Header<'_>]) -> Result<(), ParseError> {
+    for h in headers {
+        let name = HeaderName::from_bytes(h.name.as_bytes()).map_err(|_| ParseError::HeaderName)?;
+        let value = HeaderValue::from_bytes(h.value)
+            .map_err(|_| ParseError::HeaderValue(name.to_string()))?;
+        map.append(name, value);
+    }
+    Ok(())
+}
+
+/// Chunked wins over `Content-Length`. Conflicting lengths are rejected
+/// since they are a request smuggling vector.
+fn framing(headers: &HeaderMap, default: Framing, request: bool) -> Result<Framing, ParseError> {
+    let codings: Vec<String> = headers
+        .get_all(TRANSFER_ENCODING)
+        .iter()
+        .map(|v| v.to_str().map_err(|_| ParseError::TransferEncoding))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flat_map(|v| v.split(','))
+        .map(|c| c.trim().to_ascii_lowercase())
+        .filter(|c| !c.is_empty())
+        .collect();
+    if let Some(last) = codings.last() {
+        return match last.as_str() {
+            "chunked" => Ok(Framing::Chunked(Chunk::Size)),
+            // A request body must be chunked last to know where it ends
+            _ if request => Err(ParseError::TransferEncoding),
+            _ => Ok(Framing::UntilEof),
+        };
+    }
+
+    let mut length = None;
+    for value in headers.get_all(CONTENT_LENGTH) {
+        let value = value.to_str().map_err(|_| ParseError::ContentLength)?;
+        for part in value.split(',') {
+            let part = part.trim();
+            if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(ParseError::ContentLength);
             }
+            let n: u64 = part.parse().map_err(|_| ParseError::ContentLength)?;
+            if length.is_some_and(|len| len != n) {
+                return Err(ParseError::ContentLength);
+            }
+            length = Some(n);
         }
+    }
+    Ok(length.map_or(default, Framing::Length))
+}
 
-        Ok(builder.body(()).unwrap())
+/// Offset of the first CRLF.
+fn line(buf: &[u8]) -> Option<usize> {
+    buf.windows(2).position(|w| w == b"\r\n")
+}
+
+fn chunk_size(line: &[u8]) -> Result<u64, ParseError> {
+    // Extensions after `;` carry nothing we use
+    let size = line.split(|&b| b == b';').next().unwrap_or_default();
+    let size = std::str::from_utf8(size)
+        .map_err(|_| ParseError::ChunkSize)?
+        .trim();
+    if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ParseError::ChunkSize);
+    }
+    u64::from_str_radix(size, 16).map_err(|_| ParseError::ChunkSize)
+}
+
+/// httparse only understands HTTP/1.x and reports the minor version.
+fn version(minor: Option<u8>) -> Version {
+    match minor {
+        Some(0) => Version::HTTP_10,
+        _ => Version::HTTP_11,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_parse_request_across_buffers() {
+        let raw = b"POST /items?x=1 HTTP/1.1\r\nHost: a\r\nCookie: a=1\r\nCookie: b=2\r\n\
+                    Content-Length: 5\r\n\r\nhelloGET / HTTP/1.0\r\n\r\n";
+        let mut parser = Parser::default();
+        parser.feed(&raw[..10]);
+        assert!(matches!(parser.parse_request().unwrap(), Status::Partial));
+        parser.feed(&raw[10..70]);
+        assert!(parser.parse_request().unwrap().complete().is_none());
+        parser.feed(&raw[70..]);
+
+        let Status::Complete(req, consumed) = parser.parse_request().unwrap() else {
+            panic!("request should be complete");
+        };
+        assert_eq!(req.method(), Method::POST);
+        assert_eq!(req.uri(), "/items?x=1");
+        assert_eq!(req.headers().get_all("cookie").iter().count(), 2);
+        assert_eq!(req.body(), &Bytes::from("hello"));
+        assert_eq!(consumed, raw.len() - "GET / HTTP/1.0\r\n\r\n".len());
+
+        // The pipelined request is still there
+        let req = parser.parse_request().unwrap().complete().unwrap();
+        assert_eq!(req.version(), Version::HTTP_10);
+        assert!(parser.buffered().is_empty());
+    }
+
+    #[test]
+    fn test_parse_chunked_response() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nX-Trailer: yes\r\n\r\n";
+        let mut parser = Parser::default();
+        let mut result = Status::Partial;
+        for byte in raw.iter() {
+            parser.feed(std::slice::from_ref(byte));
+            result = parser.parse_response().unwrap();
+        }
+        let Status::Complete(res, consumed) = result else {
+            panic!("response should be complete");
+        };
+        assert_eq!(consumed, raw.len());
+        assert_eq!(res.body(), &Bytes::from("hello world"));
+        assert_eq!(res.headers()["x-trailer"], "yes");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let cases: [(&[u8], ParseError); 6] = [
+            (
+                b"GET / HTTP/1.1\r\nBad Header: x\r\n\r\n",
+                ParseError::Head(httparse::Error::HeaderName),
+            ),
+            (b"HTTP/1.1 099 Nope\r\n\r\n", ParseError::Status(99)),
+            (
+                b"POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n",
+                ParseError::ContentLength,
+            ),
+            (
+                b"POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n",
+                ParseError::ContentLength,
+            ),
+            (
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n",
+                ParseError::TransferEncoding,
+            ),
+            (
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+                ParseError::ChunkSize,
+            ),
+        ];
+        for (raw, expected) in cases {
+            let mut parser = Parser::new(Bytes::from_static(raw));
+            let result = if raw.starts_with(b"HTTP") {
+                parser.parse_response().map(|_| ())
+            } else {
+                parser.parse_request().map(|_| ())
+            };
+            assert_eq!(result, Err(expected), "{}", String::from_utf8_lossy(raw));
+        }
+
+        let mut parser = Parser::new(Bytes::from_static(b"HTTP/1.1 200 OK\r\n\r\npartial"));
+        assert!(matches!(parser.parse_response().unwrap(), Status::Partial));
+        parser.eof();
+        let res = parser.parse_response().unwrap().complete().unwrap();
+        assert_eq!(res.body(), &Bytes::from("partial"));
+
+        let mut parser = Parser::new(Bytes::from_static(b"GET / HTTP/1.1\r\n")).max_head_size(8);
+        assert!(matches!(
+            parser.parse_request(),
+            Err(ParseError::HeadTooLarge(8))
+        ));
+    }
+
+    fn header() -> impl Strategy<Value = (String, String)> {
+        ("x-[a-z0-9-]{1,12}", "[!-~]([ -~]{0,30}[!-~])?")
+    }
+
+    /// Cuts `raw` into pieces at the given fractions of its length.
+    fn split(raw: &[u8], cuts: &[f64]) -> Vec<Vec<u8>> {
+        let mut points: Vec<usize> = cuts
+            .iter()
+            .map(|c| (c * raw.len() as f64) as usize)
+            .collect();
+        points.sort_unstable();
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        for point in points.into_iter().chain([raw.len()]) {
+            pieces.push(raw[start..point.max(start)].to_vec());
+            start = point.max(start);
+        }
+        pieces
+    }
+
+    proptest! {
+        #[test]
+        fn prop_never_panics(data in proptest::collection::vec(any::<u8>(), 0..512)) {
+            let _ = Parser::new(Bytes::from(data.clone())).parse_request();
+            let mut parser = Parser::new(Bytes::from(data));
+            parser.eof();
+            let _ = parser.parse_response();
+        }
+
+        #[test]
+        fn prop_roundtrip(
+            headers in proptest::collection::vec(header(), 0..8),
+            body in proptest::collection::vec(any::<u8>(), 0..300),
+            chunked in any::<bool>(),
+            chunk_len in 1usize..64,
+            cuts in proptest::collection::vec(0.0f64..1.0, 0..6),
+        ) {
+            let mut raw = b"PUT /upload HTTP/1.1\r\n".to_vec();
+            for (name, value) in &headers {
+                raw.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+            }
+            if chunked {
+                raw.extend_from_slice(b"Transfer-Encoding: chunked\r\n\r\n");
+                for chunk in body.chunks(chunk_len) {
+                    raw.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+                    raw.extend_from_slice(chunk);
+                    raw.extend_from_slice(b"\r\n");
+                }
+                raw.extend_from_slice(b"0\r\n\r\n");
+            } else {
+                raw.extend_from_slice(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes());
+                raw.extend_from_slice(&body);
+            }
+
+            let mut parser = Parser::default();
+            let mut result = Status::Partial;
+            for piece in split(&raw, &cuts) {
+                parser.feed(&piece);
+                result = parser.parse_request().unwrap();
+            }
+            let Status::Complete(req, consumed) = result else {
+                return Err(TestCaseError::fail("request incomplete"));
+            };
+            prop_assert_eq!(consumed, raw.len());
+            prop_assert_eq!(req.body().as_ref(), &body[..]);
+            for (name, value) in &headers {
+                let values: Vec<_> = req.headers().get_all(name.as_str()).iter().collect();
+                prop_assert!(values.iter().any(|v| v.as_bytes() == value.as_bytes()));
+            }
+        }
     }
 }
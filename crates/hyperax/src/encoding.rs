@@ -0,0 +1,170 @@
+//! Transparent `Content-Encoding` decoding for client responses.
+
+use crate::client::Error;
+use crate::transport::ResponseBody;
+use async_compression::tokio::bufread::{BrotliDecoder, GzipDecoder, ZlibDecoder, ZstdDecoder};
+use futures_util::TryStreamExt;
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::{Body, Frame};
+use hyper::header::{HeaderMap, CONTENT_ENCODING, CONTENT_LENGTH};
+use hyper::Response;
+use std::io;
+use tokio::io::AsyncBufRead;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+/// `Accept-Encoding` sent by clients that decode responses.
+pub(crate) const ACCEPT_ENCODING: &str = "gzip, deflate, br, zstd";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    Gzip,
+    Deflate,
+    Brotli,
+    Zstd,
+}
+
+impl Encoding {
+    fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "deflate" => Some(Encoding::Deflate),
+            "br" => Some(Encoding::Brotli),
+            "zstd" => Some(Encoding::Zstd),
+            _ => None,
+        }
+    }
+
+    fn decoder(
+        self,
+        reader: impl AsyncBufRead + Send + 'static,
+    ) -> Box<dyn tokio::io::AsyncRead + Send + Unpin> {
+        match self {
+            Encoding::Gzip => Box::new(Box::pin(GzipDecoder::new(reader))),
+            // HTTP's "deflate" is the zlib format
+            Encoding::Deflate => Box::new(Box::pin(ZlibDecoder::new(reader))),
+            Encoding::Brotli => Box::new(Box::pin(BrotliDecoder::new(reader))),
+            Encoding::Zstd => Box::new(Box::pin(ZstdDecoder::new(reader))),
+        }
+    }
+}
+
+/// Encodings listed in `Content-Encoding`, in the order they were applied.
+/// `None` if any of them is unsupported, in which case the body is passed
+/// through untouched.
+fn encodings(headers: &HeaderMap) -> Option<Vec<Encoding>> {
+    let mut encodings = Vec::new();
+    for value in headers.get_all(CONTENT_ENCODING) {
+        for name in value.to_str().ok()?.split(',') {
+            if name.trim().eq_ignore_ascii_case("identity") || name.trim().is_empty() {
+                continue;
+            }
+            encodings.push(Encoding::parse(name)?);
+        }
+    }
+    Some(encodings)
+}
+
+/// Decodes `resp` according to its `Content-Encoding` as the body streams
+/// in. The encoding and length headers are removed since they no longer
+/// describe the body.
+pub(crate) fn decode(resp: Response<ResponseBody>) -> Response<ResponseBody> {
+    let Some(encodings) = encodings(resp.headers()) else {
+        return resp;
+    };
+    if encodings.is_empty() || resp.body().size_hint().exact() == Some(0) {
+        return resp;
+    }
+
+    let (mut parts, body) = resp.into_parts();
+    parts.headers.remove(CONTENT_ENCODING);
+    parts.headers.remove(CONTENT_LENGTH);
+
+    // Trailers are dropped; the decoders only see data
+    let data = body.into_data_stream().map_err(io::Error::other);
+    let mut reader: Box<dyn tokio::io::AsyncRead + Send + Unpin> =
+        Box::new(StreamReader::new(data));
+    for encoding in encodings.into_iter().rev() {
+        reader = encoding.decoder(tokio::io::BufReader::new(reader));
+    }
+
+    let frames = ReaderStream::new(reader)
+        .map_ok(Frame::data)
+        .map_err(into_client_error);
+    Response::from_parts(parts, StreamBody::new(frames).boxed_unsync())
+}
+
+/// Unwraps transport errors that were tunneled through `io::Error`.
+fn into_client_error(e: io::Error) -> Error {
+    let kind = e.kind();
+    match e.into_inner().map(|inner| inner.downcast::<Error>()) {
+        Some(Ok(inner)) => *inner,
+        Some(Err(inner)) => Error::Decode(io::Error::new(kind, inner)),
+        None => Error::Decode(kind.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_compression::tokio::bufread::{BrotliEncoder, GzipEncoder, ZlibEncoder, ZstdEncoder};
+    use hyper::body::Bytes;
+    use std::convert::Infallible;
+    use tokio::io::AsyncReadExt;
+
+    async fn read_all(mut reader: impl tokio::io::AsyncRead + Unpin) -> Vec<u8> {
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).await.unwrap();
+        out
+    }
+
+    /// A response whose body arrives in small chunks, split mid-frame.
+    fn chunked(encoding: &str, data: Vec<u8>) -> Response<ResponseBody> {
+        let chunks: Vec<Result<_, Infallible>> = data
+            .chunks(3)
+            .map(|c| Ok(Frame::data(Bytes::copy_from_slice(c))))
+            .collect();
+        let body = StreamBody::new(futures_util::stream::iter(chunks));
+        let body = BodyExt::map_err(body, |never| -> Error { match never {} }).boxed_unsync();
+        Response::builder()
+            .header(CONTENT_ENCODING, encoding)
+            .header(CONTENT_LENGTH, data.len())
+            .body(body)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_decode_encodings() {
+        let text = "hello hello hello, compressed world".repeat(20);
+        let plain = text.as_bytes();
+        let encoded = [
+            ("gzip", read_all(GzipEncoder::new(plain)).await),
+            ("deflate", read_all(ZlibEncoder::new(plain)).await),
+            ("br", read_all(BrotliEncoder::new(plain)).await),
+            ("zstd", read_all(ZstdEncoder::new(plain)).await),
+            (
+                "gzip, br",
+                read_all(BrotliEncoder::new(
+                    &read_all(GzipEncoder::new(plain)).await[..],
+                ))
+                .await,
+            ),
+        ];
+        for (encoding, data) in encoded {
+            let resp = decode(chunked(encoding, data));
+            assert!(!resp.headers().contains_key(CONTENT_ENCODING));
+            assert!(!resp.headers().contains_key(CONTENT_LENGTH));
+            let body = resp.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, text.as_bytes(), "{}", encoding);
+        }
+
+        // Unknown encodings pass through, corrupt data is an error
+        let resp = decode(chunked("compress", b"raw".to_vec()));
+        assert_eq!(resp.headers()[CONTENT_ENCODING], "compress");
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, Bytes::from("raw"));
+
+        let resp = decode(chunked("gzip", b"not gzip at all".to_vec()));
+        let error = resp.into_body().collect().await.unwrap_err();
+        assert!(matches!(error, Error::Decode(_)));
+    }
+}
@@ -1,10 +1,10 @@
 //! Re-exports of commonly used types from hyper and related crates
 
+pub use http_body_util::BodyExt;
 pub use hyper::{
-    header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE},
-    Request, Response, StatusCode, Method,
     body::Bytes,
+    header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE},
+    Method, Request, Response, StatusCode,
 };
-pub use http_body_util::BodyExt;
 
-pub use crate::client::{Client, Error as ClientError};
+pub use crate::client::Error as ClientError;
@@ -1,32 +1,47 @@
+pub mod body;
 pub mod client;
 pub mod common;
-pub mod server;
+mod encoding;
 mod export;
+mod listener;
+pub mod middleware;
+pub mod multipart;
+pub mod random;
+pub mod redirect;
+pub mod retry;
+pub mod router;
+pub mod server;
+pub mod sse;
+pub mod tls;
+pub mod transport;
+pub mod websocket;
 
-pub use client::{Client, Error};
-pub use server::Server;
+pub use client::{Client, Error, StatusError};
 pub use export::*;
+pub use listener::LocalAddr;
+pub use middleware::{Middleware, Next};
+pub use redirect::RedirectPolicy;
+pub use retry::RetryPolicy;
+pub use router::{PathParams, Router};
+pub use server::{RequestBody, ResponseBody, Server, ServerResponse, ShutdownReport};
+pub use transport::Proxy;
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use bytes::Bytes;
-    use hyper::Response;
+    use transport::MockTransport;
 
     #[tokio::test]
     async fn test_client_mock() {
-        let client = Client::new();
-
         // Set up mock response
-        let mock_response = Response::builder()
-            .status(200)
-            .body(Bytes::from("test response"))
-            .unwrap();
+        let mock = MockTransport::new();
+        mock.expect(Method::GET, "/").respond(200, "test response");
 
-        Client::mock_response(mock_response);
+        let client = Client::builder().transport(mock).build();
 
         // Test the client with mock
-        let response = client.get("http://test.com").await.unwrap();
+        let response = client.get("http://test.com/").await.unwrap();
         assert_eq!(response.status(), 200);
         assert_eq!(response.body(), &Bytes::from("test response"));
     }
@@ -0,0 +1,149 @@
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+/// Where a server listens.
+#[derive(Clone, Debug)]
+pub(crate) enum BindTarget {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix {
+        path: PathBuf,
+        mode: Option<u32>,
+    },
+}
+
+/// Address a server is actually listening on, e.g. with the port the OS
+/// picked for `127.0.0.1:0`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LocalAddr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl LocalAddr {
+    pub fn tcp(&self) -> Option<SocketAddr> {
+        match self {
+            LocalAddr::Tcp(addr) => Some(*addr),
+            #[cfg(unix)]
+            LocalAddr::Unix(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for LocalAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LocalAddr::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            LocalAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+pub(crate) enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Listener {
+    pub(crate) async fn bind(target: &BindTarget) -> io::Result<Self> {
+        match target {
+            BindTarget::Tcp(addr) => Ok(Listener::Tcp(TcpListener::bind(addr).await?)),
+            #[cfg(unix)]
+            BindTarget::Unix { path, mode } => bind_unix(path, *mode),
+        }
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<LocalAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(LocalAddr::Tcp),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => Ok(LocalAddr::Unix(path.clone())),
+        }
+    }
+
+    pub(crate) async fn accept(&self) -> io::Result<Connection> {
+        match self {
+            Listener::Tcp(listener) => Ok(Connection::Tcp(listener.accept().await?.0)),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => Ok(Connection::Unix(listener.accept().await?.0)),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Binds a Unix socket at `path`, replacing a stale socket left behind by a
+/// previous run. Any other kind of file at `path` is an error.
+#[cfg(unix)]
+fn bind_unix(path: &Path, mode: Option<u32>) -> io::Result<Listener> {
+    use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is already in use", path.display()),
+                ));
+            }
+            std::fs::remove_file(path)?;
+        }
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let Some(mode) = mode else {
+        return Ok(Listener::Unix(
+            UnixListener::bind(path)?,
+            path.to_path_buf(),
+        ));
+    };
+
+    // Bind in a directory only we can enter and apply the mode there, so
+    // nobody can connect while the socket still has umask permissions
+    static STAGED: AtomicUsize = AtomicUsize::new(0);
+    let dir = path.parent().unwrap_or(Path::new(".")).join(format!(
+        ".hyperax-{}-{}",
+        std::process::id(),
+        STAGED.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let staged = dir.join("s");
+    let listener = UnixListener::bind(&staged).and_then(|listener| {
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(mode))?;
+        std::fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_file(&staged);
+    let _ = std::fs::remove_dir(&dir);
+    Ok(Listener::Unix(listener?, path.to_path_buf()))
+}
@@ -0,0 +1,540 @@
+use crate::random;
+use crate::server::{RequestBody, ResponseBody, ServerResponse};
+use futures_util::FutureExt;
+use http_body_util::{BodyExt, Limited};
+use hyper::header::{self, HeaderValue};
+use hyper::{Method, Request, Response, StatusCode};
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub type MiddlewareFuture = Pin<Box<dyn Future<Output = Response<ResponseBody>> + Send>>;
+
+pub(crate) type Endpoint = Arc<dyn Fn(Request<RequestBody>) -> MiddlewareFuture + Send + Sync>;
+
+/// Cross-cutting behavior wrapped around every request a `Server` handles.
+///
+/// A middleware can inspect or change the request, answer it directly, or
+/// pass it on with [`Next::run`] and adjust the response. Middleware added
+/// first with `Server::layer` runs outermost.
+///
+/// Plain async functions of the form `Fn(Request<RequestBody>, Next)` are
+/// middleware too.
+pub trait Middleware: Send + Sync + 'static {
+    fn call(&self, req: Request<RequestBody>, next: Next) -> MiddlewareFuture;
+}
+
+impl<F, Fut> Middleware for F
+where
+    F: Fn(Request<RequestBody>, Next) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response<ResponseBody>> + Send + 'static,
+{
+    fn call(&self, req: Request<RequestBody>, next: Next) -> MiddlewareFuture {
+        Box::pin(self(req, next))
+    }
+}
+
+/// The rest of the middleware stack, ending in the handler.
+pub struct Next {
+    stack: Arc<[Arc<dyn Middleware>]>,
+    index: usize,
+    endpoint: Endpoint,
+}
+
+impl Next {
+    pub(crate) fn new(stack: Arc<[Arc<dyn Middleware>]>, endpoint: Endpoint) -> Self {
+        Self {
+            stack,
+            index: 0,
+            endpoint,
+        }
+    }
+
+    pub fn run(mut self, req: Request<RequestBody>) -> MiddlewareFuture {
+        match self.stack.get(self.index).cloned() {
+            Some(middleware) => {
+                self.index += 1;
+                middleware.call(req, self)
+            }
+            None => (self.endpoint)(req),
+        }
+    }
+}
+
+/// Logs method, path, status and latency of every request.
+#[derive(Clone, Debug, Default)]
+pub struct Logger;
+
+impl Middleware for Logger {
+    fn call(&self, req: Request<RequestBody>, next: Next) -> MiddlewareFuture {
+        let method = req.method().clone();
+        let path = req.uri().path().to_string();
+        let id = req.extensions().get::<RequestId>().cloned();
+        let start = Instant::now();
+        Box::pin(async move {
+            let response = next.run(req).await;
+            match id {
+                Some(id) => eprintln!(
+                    "[{}] {} {} -> {} ({:?})",
+                    id.0,
+                    method,
+                    path,
+                    response.status().as_u16(),
+                    start.elapsed()
+                ),
+                None => eprintln!(
+                    "{} {} -> {} ({:?})",
+                    method,
+                    path,
+                    response.status().as_u16(),
+                    start.elapsed()
+                ),
+            }
+            response
+        })
+    }
+}
+
+/// Identifier of the current request, available from the request extensions
+/// once [`RequestIdLayer`] ran.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestId(pub String);
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Tags each request with a [`RequestId`], reusing an incoming
+/// `X-Request-Id` header, and echoes it in the response.
+#[derive(Clone, Debug, Default)]
+pub struct RequestIdLayer;
+
+impl RequestIdLayer {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Middleware for RequestIdLayer {
+    fn call(&self, mut req: Request<RequestBody>, next: Next) -> MiddlewareFuture {
+        let id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty() && v.len() <= 128)
+            .map(str::to_string)
+            .unwrap_or_else(random::hex::<8>);
+        req.extensions_mut().insert(RequestId(id.clone()));
+        Box::pin(async move {
+            let mut response = next.run(req).await;
+            if let Ok(value) = HeaderValue::from_str(&id) {
+                response.headers_mut().insert(REQUEST_ID_HEADER, value);
+            }
+            response
+        })
+    }
+}
+
+/// Adds CORS headers and answers preflight requests.
+#[derive(Clone, Debug)]
+pub struct Cors {
+    origins: Option<Vec<String>>,
+    methods: String,
+    headers: String,
+    max_age: Option<Duration>,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self {
+            origins: Some(Vec::new()),
+            methods: "GET, POST, PUT, PATCH, DELETE, OPTIONS".to_string(),
+            headers: "authorization, content-type".to_string(),
+            max_age: None,
+        }
+    }
+}
+
+impl Cors {
+    /// Allows no origins until configured.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow_any_origin(mut self) -> Self {
+        self.origins = None;
+        self
+    }
+
+    pub fn allow_origin(mut self, origin: impl Into<String>) -> Self {
+        self.origins
+            .get_or_insert_with(Vec::new)
+            .push(origin.into());
+        self
+    }
+
+    pub fn allow_methods(mut self, methods: &[Method]) -> Self {
+        self.methods = methods
+            .iter()
+            .map(Method::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+        self
+    }
+
+    pub fn allow_headers(mut self, headers: &[&str]) -> Self {
+        self.headers = headers.join(", ");
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    fn allowed_origin(&self, origin: &str) -> Option<&str> {
+        match &self.origins {
+            None => Some("*"),
+            Some(origins) => origins
+                .iter()
+                .find(|allowed| allowed.as_str() == origin)
+                .map(String::as_str),
+        }
+    }
+}
+
+impl Middleware for Cors {
+    fn call(&self, req: Request<RequestBody>, next: Next) -> MiddlewareFuture {
+        let origin = req
+            .headers()
+            .get(header::ORIGIN)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|origin| self.allowed_origin(origin))
+            .and_then(|origin| HeaderValue::from_str(origin).ok());
+        let Some(origin) = origin else {
+            // Not a CORS request, or from an origin we do not allow
+            return next.run(req);
+        };
+
+        let preflight = req.method() == Method::OPTIONS
+            && req
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+        if preflight {
+            let mut response = ServerResponse::new(StatusCode::NO_CONTENT)
+                .header(header::ACCESS_CONTROL_ALLOW_METHODS, self.methods.as_str())
+                .header(header::ACCESS_CONTROL_ALLOW_HEADERS, self.headers.as_str())
+                .empty();
+            if let Some(max_age) = self.max_age {
+                response
+                    .headers_mut()
+                    .insert(header::ACCESS_CONTROL_MAX_AGE, max_age.as_secs().into());
+            }
+            with_origin(&mut response, origin);
+            return Box::pin(std::future::ready(response));
+        }
+
+        Box::pin(async move {
+            let mut response = next.run(req).await;
+            with_origin(&mut response, origin);
+            response
+        })
+    }
+}
+
+fn with_origin(response: &mut Response<ResponseBody>, origin: HeaderValue) {
+    let headers = response.headers_mut();
+    if origin != "*" {
+        headers.append(header::VARY, HeaderValue::from_static("origin"));
+    }
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+}
+
+/// Rejects requests without `Authorization: Bearer <token>` with a 401.
+#[derive(Clone)]
+pub struct BearerAuth {
+    token: String,
+}
+
+impl BearerAuth {
+    pub fn new(token: impl Into<String>) -> Self {
+        Self {
+            token: token.into(),
+        }
+    }
+
+    fn authorized<B>(&self, req: &Request<B>) -> bool {
+        req.headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .is_some_and(|token| constant_time_eq(token.trim().as_bytes(), self.token.as_bytes()))
+    }
+}
+
+impl std::fmt::Debug for BearerAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BearerAuth").finish_non_exhaustive()
+    }
+}
+
+impl Middleware for BearerAuth {
+    fn call(&self, req: Request<RequestBody>, next: Next) -> MiddlewareFuture {
+        if self.authorized(&req) {
+            return next.run(req);
+        }
+        let response = ServerResponse::new(StatusCode::UNAUTHORIZED)
+            .header(header::WWW_AUTHENTICATE, "Bearer")
+            .text("Unauthorized");
+        Box::pin(std::future::ready(response))
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Caps request bodies at `limit` bytes.
+///
+/// Requests announcing a larger `Content-Length` get a 413 right away.
+/// Streamed bodies fail with a length error once they cross the limit, which
+/// handlers see when reading the body.
+#[derive(Clone, Debug)]
+pub struct BodyLimit {
+    limit: usize,
+}
+
+impl BodyLimit {
+    pub fn new(limit: usize) -> Self {
+        Self { limit }
+    }
+}
+
+impl Middleware for BodyLimit {
+    fn call(&self, req: Request<RequestBody>, next: Next) -> MiddlewareFuture {
+        let declared = req
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        if declared.is_some_and(|len| len > self.limit as u64) {
+            let response =
+                ServerResponse::new(StatusCode::PAYLOAD_TOO_LARGE).text("Payload Too Large");
+            return Box::pin(std::future::ready(response));
+        }
+        let limit = self.limit;
+        next.run(req.map(|body| Limited::new(body, limit).boxed_unsync()))
+    }
+}
+
+/// Answers with 504 when the rest of the stack takes longer than `duration`.
+///
+/// Not 408, which clients may take to mean the request was never processed
+/// and send again.
+#[derive(Clone, Debug)]
+pub struct Timeout {
+    duration: Duration,
+}
+
+impl Timeout {
+    pub fn new(duration: Duration) -> Self {
+        Self { duration }
+    }
+}
+
+impl Middleware for Timeout {
+    fn call(&self, req: Request<RequestBody>, next: Next) -> MiddlewareFuture {
+        let duration = self.duration;
+        Box::pin(async move {
+            match tokio::time::timeout(duration, next.run(req)).await {
+                Ok(response) => response,
+                Err(_) => ServerResponse::new(StatusCode::GATEWAY_TIMEOUT).text("Gateway Timeout"),
+            }
+        })
+    }
+}
+
+/// Turns a panicking handler into a 500 response instead of a dropped
+/// connection.
+#[derive(Clone, Debug, Default)]
+pub struct CatchPanic;
+
+impl Middleware for CatchPanic {
+    fn call(&self, req: Request<RequestBody>, next: Next) -> MiddlewareFuture {
+        let method = req.method().clone();
+        let path = req.uri().path().to_string();
+        Box::pin(async move {
+            // A handler may panic before returning its future too
+            let run = std::panic::catch_unwind(AssertUnwindSafe(|| next.run(req)));
+            let result = match run {
+                Ok(future) => AssertUnwindSafe(future).catch_unwind().await,
+                Err(panic) => Err(panic),
+            };
+            result.unwrap_or_else(|panic| {
+                let message = panic
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| panic.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                eprintln!("Handler panicked on {} {}: {}", method, path, message);
+                ServerResponse::new(StatusCode::INTERNAL_SERVER_ERROR).text("Internal Server Error")
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::dispatch;
+
+    fn request(method: Method, body: &'static str) -> Request<RequestBody> {
+        Request::builder()
+            .method(method)
+            .uri("/echo")
+            .body(crate::body::full(body))
+            .unwrap()
+    }
+
+    fn stack(layers: Vec<Arc<dyn Middleware>>) -> Arc<[Arc<dyn Middleware>]> {
+        layers.into()
+    }
+
+    async fn echo(
+        req: Request<RequestBody>,
+    ) -> Result<Response<ResponseBody>, std::convert::Infallible> {
+        Ok(match req.into_body().collect().await {
+            Ok(body) => ServerResponse::ok().body(body.to_bytes()),
+            Err(_) => ServerResponse::new(StatusCode::PAYLOAD_TOO_LARGE).empty(),
+        })
+    }
+
+    async fn text(response: Response<ResponseBody>) -> String {
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_middleware_order() {
+        let outer = |req: Request<RequestBody>, next: Next| async move {
+            let mut response = next.run(req).await;
+            response
+                .headers_mut()
+                .append("x-trace", HeaderValue::from_static("outer"));
+            response
+        };
+        let inner = |req: Request<RequestBody>, next: Next| async move {
+            let mut response = next.run(req).await;
+            response
+                .headers_mut()
+                .append("x-trace", HeaderValue::from_static("inner"));
+            response
+        };
+        let layers = stack(vec![
+            Arc::new(outer),
+            Arc::new(inner),
+            Arc::new(RequestIdLayer::new()),
+        ]);
+
+        let response = dispatch(layers, echo, request(Method::POST, "hi")).await;
+        let trace: Vec<_> = response.headers().get_all("x-trace").iter().collect();
+        assert_eq!(trace, ["inner", "outer"]);
+        assert_eq!(response.headers()[REQUEST_ID_HEADER].len(), 16);
+        assert_eq!(text(response).await, "hi");
+    }
+
+    #[tokio::test]
+    async fn test_auth_and_limits() {
+        let layers = stack(vec![
+            Arc::new(BearerAuth::new("secret")),
+            Arc::new(BodyLimit::new(4)),
+        ]);
+
+        let response = dispatch(layers.clone(), echo, request(Method::POST, "hi")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let mut req = request(Method::POST, "hi");
+        req.headers_mut().insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer secret"),
+        );
+        let response = dispatch(layers.clone(), echo, req).await;
+        assert_eq!(text(response).await, "hi");
+
+        let mut req = request(Method::POST, "too long");
+        req.headers_mut().insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer secret"),
+        );
+        let response = dispatch(layers.clone(), echo, req).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let mut req = request(Method::POST, "hi");
+        req.headers_mut().insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer secret"),
+        );
+        req.headers_mut()
+            .insert(header::CONTENT_LENGTH, HeaderValue::from_static("1000"));
+        let response = dispatch(layers, echo, req).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_cors() {
+        let layers = stack(vec![Arc::new(
+            Cors::new()
+                .allow_origin("http://localhost:3000")
+                .max_age(Duration::from_secs(600)),
+        )]);
+
+        let mut req = request(Method::OPTIONS, "");
+        req.headers_mut().insert(
+            header::ORIGIN,
+            HeaderValue::from_static("http://localhost:3000"),
+        );
+        req.headers_mut().insert(
+            header::ACCESS_CONTROL_REQUEST_METHOD,
+            HeaderValue::from_static("POST"),
+        );
+        let response = dispatch(layers.clone(), echo, req).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "http://localhost:3000"
+        );
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_MAX_AGE], "600");
+
+        let mut req = request(Method::GET, "");
+        req.headers_mut()
+            .insert(header::ORIGIN, HeaderValue::from_static("http://evil.test"));
+        let response = dispatch(layers, echo, req).await;
+        assert!(!response
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[tokio::test]
+    async fn test_timeout_and_panics() {
+        let layers = stack(vec![
+            Arc::new(CatchPanic),
+            Arc::new(Timeout::new(Duration::from_millis(10))),
+        ]);
+
+        let slow = |_req: Request<RequestBody>| async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(ServerResponse::ok().empty())
+        };
+        let response = dispatch(layers.clone(), slow, request(Method::GET, "")).await;
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let panics = |_req: Request<RequestBody>| async {
+            if true {
+                panic!("boom");
+            }
+            Ok(ServerResponse::ok().empty())
+        };
+        let response = dispatch(layers, panics, request(Method::GET, "")).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
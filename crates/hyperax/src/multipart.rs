@@ -0,0 +1,554 @@
+//! `multipart/form-data` bodies. [`Form`] builds them for uploads, streaming
+//! file parts from disk as the request is sent, and [`read`] parses them on
+//! the server.
+//!
+//! ```no_run
+//! # async fn upload() -> Result<(), Box<dyn std::error::Error>> {
+//! use hyperax::multipart::Form;
+//! use hyperax::Client;
+//!
+//! let form = Form::new()
+//!     .text("purpose", "attachments")
+//!     .file("file", "report.pdf")
+//!     .await?;
+//! Client::new()
+//!     .post_multipart("http://localhost:8080/upload", form)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::body::{self, BoxError, Payload, RequestBody};
+use crate::common::Headers;
+use crate::random;
+use futures_util::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use http_body_util::BodyExt;
+use hyper::body::Bytes;
+use hyper::header::{HeaderValue, InvalidHeaderValue, CONTENT_TYPE};
+use hyper::Request;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use tokio_util::io::ReaderStream;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MultipartError {
+    #[error("content type is not multipart/form-data")]
+    ContentType,
+    #[error("multipart boundary missing")]
+    Boundary,
+    #[error("malformed multipart body: {0}")]
+    Malformed(&'static str),
+    #[error("error reading body: {0}")]
+    Body(BoxError),
+}
+
+/// A `multipart/form-data` body under construction.
+#[derive(Clone, Debug)]
+pub struct Form {
+    boundary: String,
+    parts: Vec<(String, Part)>,
+}
+
+impl Default for Form {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Form {
+    pub fn new() -> Self {
+        Self {
+            boundary: random_boundary(),
+            parts: Vec::new(),
+        }
+    }
+
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// `Content-Type` header value for this form.
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    pub fn text(self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.part(name, Part::text(value))
+    }
+
+    pub fn part(mut self, name: impl Into<String>, part: Part) -> Self {
+        self.parts.push((name.into(), part));
+        self
+    }
+
+    /// Adds the file at `path`, named after its file name. Only its size is
+    /// read now; the contents are streamed when the request is sent.
+    pub async fn file(self, name: impl Into<String>, path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(self.part(name, Part::file(path).await?))
+    }
+
+    /// Encoded size in bytes.
+    pub fn content_length(&self) -> u64 {
+        let parts: u64 = self
+            .parts
+            .iter()
+            .map(|(name, part)| part.head(&self.boundary, name).len() as u64 + part.len() + 2)
+            .sum();
+        parts + self.closing().len() as u64
+    }
+
+    /// The encoded body. Files are reopened for each attempt at sending it.
+    pub fn into_payload(self) -> Payload {
+        let length = self.content_length();
+        let form = Arc::new(self);
+        Payload::streaming(move || form.open()).with_length(length)
+    }
+
+    fn open(&self) -> RequestBody {
+        let parts: Vec<_> = self
+            .parts
+            .iter()
+            .map(|(name, part)| {
+                let head = Bytes::from(part.head(&self.boundary, name));
+                stream::once(async { Ok(head) })
+                    .chain(part.data())
+                    .chain(stream::once(async { Ok(Bytes::from_static(b"\r\n")) }))
+            })
+            .collect();
+        let closing = Bytes::from(self.closing());
+        body::stream(
+            stream::iter(parts)
+                .flatten()
+                .chain(stream::once(async { Ok::<_, io::Error>(closing) })),
+        )
+    }
+
+    fn closing(&self) -> String {
+        format!("--{}--\r\n", self.boundary)
+    }
+}
+
+/// One field of a [`Form`].
+#[derive(Clone, Debug)]
+pub struct Part {
+    source: Source,
+    file_name: Option<String>,
+    content_type: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+enum Source {
+    Bytes(Bytes),
+    File { path: PathBuf, len: u64 },
+}
+
+impl Part {
+    pub fn text(value: impl Into<String>) -> Self {
+        Self {
+            source: Source::Bytes(Bytes::from(value.into())),
+            file_name: None,
+            content_type: None,
+        }
+    }
+
+    pub fn bytes(data: impl Into<Bytes>) -> Self {
+        Self {
+            source: Source::Bytes(data.into()),
+            file_name: None,
+            content_type: Some("application/octet-stream".to_string()),
+        }
+    }
+
+    /// The file at `path`, with its file name and a content type guessed
+    /// from the extension.
+    pub async fn file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let len = tokio::fs::metadata(path).await?.len();
+        Ok(Self {
+            source: Source::File {
+                path: path.to_path_buf(),
+                len,
+            },
+            file_name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+            content_type: Some(guess_content_type(path).to_string()),
+        })
+    }
+
+    pub fn file_name(mut self, name: impl Into<String>) -> Self {
+        self.file_name = Some(name.into());
+        self
+    }
+
+    /// Sets the part's `Content-Type`. Fails for values that aren't valid
+    /// in a header, such as ones containing CR or LF.
+    pub fn content_type(mut self, content_type: &str) -> Result<Self, InvalidHeaderValue> {
+        HeaderValue::from_str(content_type)?;
+        self.content_type = Some(content_type.to_string());
+        Ok(self)
+    }
+
+    fn len(&self) -> u64 {
+        match &self.source {
+            Source::Bytes(bytes) => bytes.len() as u64,
+            Source::File { len, .. } => *len,
+        }
+    }
+
+    fn head(&self, boundary: &str, name: &str) -> String {
+        let mut head = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"",
+            boundary,
+            escape(name)
+        );
+        if let Some(file_name) = &self.file_name {
+            head.push_str(&format!("; filename=\"{}\"", escape(file_name)));
+        }
+        head.push_str("\r\n");
+        if let Some(content_type) = &self.content_type {
+            head.push_str(&format!("Content-Type: {}\r\n", content_type));
+        }
+        head.push_str("\r\n");
+        head
+    }
+
+    fn data(&self) -> BoxStream<'static, io::Result<Bytes>> {
+        match &self.source {
+            Source::Bytes(bytes) => {
+                let bytes = bytes.clone();
+                stream::once(async { Ok(bytes) }).boxed()
+            }
+            Source::File { path, len } => {
+                let (path, len) = (path.clone(), *len);
+                // Never send more than the announced length, even if the
+                // file grew in the meantime
+                stream::once(tokio::fs::File::open(path))
+                    .map_ok(move |file| ReaderStream::new(file.take(len)))
+                    .try_flatten()
+                    .boxed()
+            }
+        }
+    }
+}
+
+/// A field parsed by [`parse`] or [`read`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    pub name: String,
+    pub file_name: Option<String>,
+    pub content_type: Option<String>,
+    pub headers: Headers,
+    pub data: Bytes,
+}
+
+impl Field {
+    pub fn text(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(&self.data)
+    }
+}
+
+/// Buffers the body of a `multipart/form-data` request and parses it.
+pub async fn read(req: Request<RequestBody>) -> Result<Vec<Field>, MultipartError> {
+    let content_type = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .ok_or(MultipartError::ContentType)?
+        .to_string();
+    let body = req
+        .into_body()
+        .collect()
+        .await
+        .map_err(MultipartError::Body)?
+        .to_bytes();
+    parse(&content_type, &body)
+}
+
+/// The boundary parameter of a `multipart/form-data` content type.
+pub fn boundary(content_type: &str) -> Result<String, MultipartError> {
+    let mut params = content_type.split(';');
+    let media = params.next().unwrap_or_default().trim();
+    if !media.eq_ignore_ascii_case("multipart/form-data") {
+        return Err(MultipartError::ContentType);
+    }
+    params
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        .filter(|boundary| !boundary.is_empty())
+        .ok_or(MultipartError::Boundary)
+}
+
+/// Parses a complete `multipart/form-data` body.
+pub fn parse(content_type: &str, body: &Bytes) -> Result<Vec<Field>, MultipartError> {
+    let delimiter = format!("--{}", boundary(content_type)?);
+    let delimiter = delimiter.as_bytes();
+
+    // Anything before the first delimiter is preamble
+    let mut pos = find(body, delimiter, 0).ok_or(MultipartError::Malformed("no parts"))?;
+    let mut fields = Vec::new();
+    loop {
+        pos += delimiter.len();
+        if body[pos..].starts_with(b"--") {
+            return Ok(fields);
+        }
+        // Transport padding may follow the delimiter
+        while body.get(pos).is_some_and(|b| *b == b' ' || *b == b'\t') {
+            pos += 1;
+        }
+        if !body[pos..].starts_with(b"\r\n") {
+            return Err(MultipartError::Malformed("expected CRLF after boundary"));
+        }
+        pos += 2;
+
+        let mut raw = [httparse::EMPTY_HEADER; 16];
+        let (consumed, raw) = match httparse::parse_headers(&body[pos..], &mut raw) {
+            Ok(httparse::Status::Complete(parsed)) => parsed,
+            Ok(httparse::Status::Partial) => {
+                return Err(MultipartError::Malformed("truncated part headers"))
+            }
+            Err(_) => return Err(MultipartError::Malformed("invalid part headers")),
+        };
+        pos += consumed;
+
+        let mut closing = b"\r\n".to_vec();
+        closing.extend_from_slice(delimiter);
+        let end =
+            find(body, &closing, pos).ok_or(MultipartError::Malformed("unterminated part"))?;
+        let data = body.slice(pos..end);
+        pos = end + 2;
+
+        let headers: Headers = raw
+            .iter()
+            .map(|h| (h.name, String::from_utf8_lossy(h.value).into_owned()))
+            .collect();
+        let disposition = headers
+            .get("content-disposition")
+            .ok_or(MultipartError::Malformed(
+                "part without Content-Disposition",
+            ))?;
+        let params = disposition_params(disposition);
+        let param = |key: &str| {
+            params
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| v.clone())
+        };
+        fields.push(Field {
+            name: param("name").ok_or(MultipartError::Malformed("part without a name"))?,
+            file_name: param("filename"),
+            content_type: headers.get("content-type").map(str::to_string),
+            headers,
+            data,
+        });
+    }
+}
+
+/// `key=value` parameters of a `Content-Disposition` value, unquoted.
+fn disposition_params(value: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut rest = value.split_once(';').map_or("", |(_, rest)| rest);
+    while let Some((key, after)) = rest.split_once('=') {
+        let after = after.trim_start();
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                        '"' => {
+                            end = i + 1;
+                            break;
+                        }
+                        c => value.push(c),
+                    }
+                }
+                (unescape(&value), &quoted[end..])
+            }
+            None => {
+                let (value, next) = after.split_once(';').unwrap_or((after, ""));
+                (value.trim().to_string(), next)
+            }
+        };
+        params.push((key.trim().to_string(), value));
+        rest = next.split_once(';').map_or("", |(_, rest)| rest);
+    }
+    params
+}
+
+/// Field names and file names are percent-escaped the way browsers do it.
+fn escape(value: &str) -> String {
+    value
+        .replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("%22", "\"")
+        .replace("%0D", "\r")
+        .replace("%0A", "\n")
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|i| from + i)
+}
+
+fn random_boundary() -> String {
+    format!("hyperax-{}", random::hex::<16>())
+}
+
+fn guess_content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "txt" | "md" => "text/plain",
+        "html" | "htm" => "text/html",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "pdf" => "application/pdf",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_multipart() {
+        let body = Bytes::from(
+            "preamble\r\n--XyZ\r\n\
+             Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+             line one\r\nline two\r\n\
+             --XyZ  \r\n\
+             Content-Disposition: form-data; name=\"upload\"; filename=\"a \\\"b\\\".txt\"\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             --XY not the boundary\r\n\
+             --XyZ--\r\nepilogue",
+        );
+        let fields = parse("multipart/form-data; boundary=\"XyZ\"", &body).unwrap();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0].name, "title");
+        assert_eq!(fields[0].text().unwrap(), "line one\r\nline two");
+        assert_eq!(fields[1].file_name.as_deref(), Some("a \"b\".txt"));
+        assert_eq!(fields[1].content_type.as_deref(), Some("text/plain"));
+        assert_eq!(fields[1].data, Bytes::from("--XY not the boundary"));
+
+        assert!(matches!(
+            parse("application/json", &body),
+            Err(MultipartError::ContentType)
+        ));
+        assert!(matches!(
+            parse(
+                "multipart/form-data; boundary=XyZ",
+                &Bytes::from("--XyZ\r\n\r\n")
+            ),
+            Err(MultipartError::Malformed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_form_roundtrip() {
+        let path = std::env::temp_dir().join(format!("hyperax-{}.json", random_boundary()));
+        let contents = "{\"big\": true}".repeat(10_000);
+        tokio::fs::write(&path, &contents).await.unwrap();
+
+        let form = Form::new()
+            .text("purpose", "say \"hi\"")
+            .part(
+                "blob",
+                Part::bytes(vec![0u8, 1, 2])
+                    .file_name("blob.bin")
+                    .content_type("application/octet-stream")
+                    .unwrap(),
+            )
+            .file("doc", &path)
+            .await
+            .unwrap();
+        let content_type = form.content_type();
+        let length = form.content_length();
+        let payload = form.into_payload();
+        assert_eq!(payload.length(), Some(length));
+
+        // Each open streams the whole form again
+        for _ in 0..2 {
+            let body = payload.open().collect().await.unwrap().to_bytes();
+            assert_eq!(body.len() as u64, length);
+            let fields = parse(&content_type, &body).unwrap();
+            assert_eq!(fields[0].text().unwrap(), "say \"hi\"");
+            assert_eq!(fields[1].data, Bytes::from_static(&[0, 1, 2]));
+            assert_eq!(fields[2].content_type.as_deref(), Some("application/json"));
+            assert_eq!(fields[2].text().unwrap(), contents);
+        }
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        let injected = Part::text("x").content_type("text/plain\r\nX-Injected: 1");
+        assert!(injected.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_upload_to_server() {
+        use crate::{Client, Server, ServerResponse};
+        use std::convert::Infallible;
+
+        let path = std::env::temp_dir().join(format!("hyperax-{}.txt", random_boundary()));
+        tokio::fs::write(&path, "x".repeat(256 * 1024))
+            .await
+            .unwrap();
+
+        let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+        let mut server = Server::new("127.0.0.1:0".parse().unwrap());
+        let addr = server.bind().await.unwrap().tcp().unwrap();
+        tokio::spawn(async move {
+            let handler = |req: Request<RequestBody>| async move {
+                let chunked = !req.headers().contains_key("content-length");
+                let summary = match read(req).await {
+                    Ok(fields) => fields
+                        .iter()
+                        .map(|f| format!("{}={}", f.name, f.data.len()))
+                        .collect::<Vec<_>>()
+                        .join(","),
+                    Err(e) => e.to_string(),
+                };
+                Ok::<_, Infallible>(
+                    ServerResponse::ok().text(format!("{} chunked={}", summary, chunked)),
+                )
+            };
+            server.run(handler, shutdown_rx).await
+        });
+
+        let form = Form::new()
+            .text("note", "hi")
+            .file("file", &path)
+            .await
+            .unwrap();
+        let response = Client::new()
+            .post_multipart(&format!("http://{}/upload", addr), form)
+            .await
+            .unwrap();
+        assert_eq!(
+            response.body(),
+            &Bytes::from("note=2,file=262144 chunked=false")
+        );
+
+        shutdown_tx.send(true).unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+}
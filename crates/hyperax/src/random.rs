@@ -0,0 +1,40 @@
+//! Randomness from the operating system's secure generator, for anything
+//! that must not be guessed (tokens, WebSocket masks) as well as jitter.
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// `N` random bytes.
+///
+/// # Panics
+///
+/// If the operating system cannot provide randomness.
+pub fn bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("system random number generator failed");
+    bytes
+}
+
+/// `N` random bytes as lowercase hex.
+pub fn hex<const N: usize>() -> String {
+    bytes::<N>().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A uniform value in `[0, 1)`.
+pub fn unit() -> f64 {
+    (u64::from_le_bytes(bytes()) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random() {
+        let token = hex::<16>();
+        assert_eq!(token.len(), 32);
+        assert_ne!(token, hex::<16>());
+        assert!((0.0..1.0).contains(&unit()));
+    }
+}
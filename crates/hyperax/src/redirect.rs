@@ -0,0 +1,186 @@
+use hyper::header::{
+    HeaderName, AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, HOST,
+    LOCATION, PROXY_AUTHORIZATION,
+};
+use hyper::http::request::Parts;
+use hyper::http::uri::{Authority, PathAndQuery, Scheme};
+use hyper::{Method, StatusCode, Uri};
+
+/// Controls whether and how far [`Client`](crate::Client) follows redirects.
+///
+/// A 303, or a 301 or 302 answering a `POST`, is followed with a bodiless
+/// `GET` like browsers do; 307 and 308 resend the original method and body.
+/// Credentials are dropped when a redirect leaves the original scheme, host
+/// and port.
+#[derive(Clone, Debug)]
+pub struct RedirectPolicy {
+    max_hops: usize,
+    sensitive: Vec<HeaderName>,
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        Self {
+            max_hops: 10,
+            sensitive: vec![AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION],
+        }
+    }
+}
+
+impl RedirectPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A policy that never follows redirects. [`Client::request`] and
+    /// [`Client::stream`] report a 3xx as
+    /// [`Error::Status`](crate::Error::Status) like any other non-2xx
+    /// response, with `Location` among its headers.
+    ///
+    /// [`Client::request`]: crate::Client::request
+    /// [`Client::stream`]: crate::Client::stream
+    pub fn none() -> Self {
+        Self {
+            max_hops: 0,
+            ..Self::default()
+        }
+    }
+
+    /// Redirects to follow before failing with
+    /// [`Error::TooManyRedirects`](crate::Error::TooManyRedirects).
+    pub fn max_hops(mut self, hops: usize) -> Self {
+        self.max_hops = hops;
+        self
+    }
+
+    /// Another header to strip on cross-origin redirects, such as an API
+    /// key header. `Authorization`, `Cookie` and `Proxy-Authorization` are
+    /// always stripped.
+    pub fn sensitive_header(mut self, name: HeaderName) -> Self {
+        self.sensitive.push(name);
+        self
+    }
+
+    pub(crate) fn allows(&self, hops: usize) -> bool {
+        hops < self.max_hops
+    }
+
+    /// The request to send after a `status` response with `location`, or
+    /// `None` if the response is not a redirect that can be followed.
+    pub(crate) fn next(
+        &self,
+        parts: &Parts,
+        status: StatusCode,
+        location: Option<&str>,
+    ) -> Option<Redirect> {
+        let switch_to_get = match status.as_u16() {
+            301 | 302 => parts.method == Method::POST,
+            303 => parts.method != Method::HEAD,
+            307 | 308 => false,
+            _ => return None,
+        };
+        let uri = resolve(&parts.uri, location?)?;
+
+        let mut next = parts.clone();
+        next.headers.remove(HOST);
+        if !same_origin(&parts.uri, &uri) {
+            for name in &self.sensitive {
+                next.headers.remove(name);
+            }
+        }
+        if switch_to_get {
+            next.method = Method::GET;
+            for name in [CONTENT_TYPE, CONTENT_LENGTH, CONTENT_ENCODING] {
+                next.headers.remove(name);
+            }
+        }
+        next.uri = uri;
+        Some(Redirect {
+            parts: next,
+            drops_body: switch_to_get,
+        })
+    }
+}
+
+pub(crate) struct Redirect {
+    pub(crate) parts: Parts,
+    /// Set when the method changed to `GET`.
+    pub(crate) drops_body: bool,
+}
+
+pub(crate) fn location(headers: &hyper::HeaderMap) -> Option<&str> {
+    headers.get(LOCATION)?.to_str().ok()
+}
+
+/// Resolves a `Location` value against the URI that was requested.
+fn resolve(base: &Uri, location: &str) -> Option<Uri> {
+    let location = location.trim();
+    if let Ok(uri) = location.parse::<Uri>() {
+        if uri.scheme().is_some() {
+            return Some(uri);
+        }
+    }
+    let scheme = base.scheme().cloned().unwrap_or(Scheme::HTTP);
+    if let Some(rest) = location.strip_prefix("//") {
+        return format!("{}://{}", scheme, rest).parse().ok();
+    }
+
+    let authority: Authority = base.authority()?.clone();
+    let path: PathAndQuery = if location.starts_with('/') {
+        location.parse().ok()?
+    } else if location.starts_with('?') {
+        format!("{}{}", base.path(), location).parse().ok()?
+    } else {
+        // Relative to the current path's directory
+        let dir = base.path().rsplit_once('/').map_or("", |(dir, _)| dir);
+        format!("{}/{}", dir, location).parse().ok()?
+    };
+    Uri::builder()
+        .scheme(scheme)
+        .authority(authority)
+        .path_and_query(path)
+        .build()
+        .ok()
+}
+
+fn same_origin(a: &Uri, b: &Uri) -> bool {
+    let port = |uri: &Uri| {
+        uri.port_u16()
+            .unwrap_or(if uri.scheme() == Some(&Scheme::HTTPS) {
+                443
+            } else {
+                80
+            })
+    };
+    a.scheme() == b.scheme()
+        && a.host().map(str::to_ascii_lowercase) == b.host().map(str::to_ascii_lowercase)
+        && port(a) == port(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_location() {
+        let base: Uri = "https://api.example.com/v1/items/42?x=1".parse().unwrap();
+        let cases = [
+            ("http://other.example/a", "http://other.example/a"),
+            ("//cdn.example.com/f", "https://cdn.example.com/f"),
+            ("/v2/items", "https://api.example.com/v2/items"),
+            ("43", "https://api.example.com/v1/items/43"),
+            ("?page=2", "https://api.example.com/v1/items/42?page=2"),
+        ];
+        for (location, expected) in cases {
+            assert_eq!(resolve(&base, location).unwrap(), expected, "{}", location);
+        }
+        assert!(!same_origin(
+            &base,
+            &"http://api.example.com/".parse().unwrap()
+        ));
+        assert!(same_origin(
+            &base,
+            &"https://API.example.com:443/".parse().unwrap()
+        ));
+    }
+}
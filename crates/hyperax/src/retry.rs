@@ -0,0 +1,282 @@
+use crate::random;
+use hyper::header::HeaderMap;
+use hyper::{Method, StatusCode};
+use std::time::{Duration, SystemTime};
+
+/// Non-standard status Anthropic uses when the API is overloaded.
+pub const STATUS_OVERLOADED: u16 = 529;
+
+/// Rate limit reset headers sent by the Anthropic API, paired with the
+/// matching `-remaining` header.
+const ANTHROPIC_RATELIMIT_HEADERS: [(&str, &str); 4] = [
+    (
+        "anthropic-ratelimit-requests-remaining",
+        "anthropic-ratelimit-requests-reset",
+    ),
+    (
+        "anthropic-ratelimit-tokens-remaining",
+        "anthropic-ratelimit-tokens-reset",
+    ),
+    (
+        "anthropic-ratelimit-input-tokens-remaining",
+        "anthropic-ratelimit-input-tokens-reset",
+    ),
+    (
+        "anthropic-ratelimit-output-tokens-remaining",
+        "anthropic-ratelimit-output-tokens-reset",
+    ),
+];
+
+/// Controls how many times and how quickly failed requests are retried.
+///
+/// Delays grow exponentially from `base_delay` and are capped at `max_delay`.
+/// A server supplied `Retry-After` replaces the computed backoff, within the
+/// same cap.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+    retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(60),
+            jitter: true,
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Total number of attempts, including the first one.
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    pub fn base_delay(mut self, delay: Duration) -> Self {
+        self.base_delay = delay;
+        self
+    }
+
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    pub fn no_jitter(mut self) -> Self {
+        self.jitter = false;
+        self
+    }
+
+    /// Also retry 5xx responses and connection failures for methods that are
+    /// not idempotent, such as `POST` without an `Idempotency-Key`.
+    pub fn retry_non_idempotent(mut self, enabled: bool) -> Self {
+        self.retry_non_idempotent = enabled;
+        self
+    }
+
+    /// Exponential backoff for the given zero based attempt.
+    ///
+    /// With jitter enabled the delay is picked uniformly from the upper half
+    /// of the window, so concurrent clients spread out without ever retrying
+    /// immediately.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.min(31));
+        let delay = self.base_delay.saturating_mul(factor).min(self.max_delay);
+        if !self.jitter {
+            return delay;
+        }
+        let half = delay / 2;
+        half + half.mul_f64(random::unit())
+    }
+
+    /// Delay before the next attempt, or `None` once the attempt budget is
+    /// spent. `retry_after` is the server hint, if any.
+    pub fn next_delay(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if attempt.saturating_add(1) >= self.max_attempts {
+            return None;
+        }
+        Some(match retry_after {
+            Some(delay) => delay.min(self.max_delay),
+            None => self.backoff(attempt),
+        })
+    }
+
+    /// Whether a response with `status` should be retried for this request.
+    ///
+    /// 429, 503 and 529 mean the server did not process the request, so they
+    /// are retried for any method. 408 and other 5xx errors may come after
+    /// the handler started, so they are only retried when replaying the
+    /// request is safe.
+    pub fn should_retry_status(
+        &self,
+        method: &Method,
+        headers: &HeaderMap,
+        status: StatusCode,
+    ) -> bool {
+        match status.as_u16() {
+            429 | 503 | STATUS_OVERLOADED => true,
+            408 | 500 | 502 | 504 => self.retry_non_idempotent || is_idempotent(method, headers),
+            _ => false,
+        }
+    }
+
+    /// Whether a transport failure should be retried for this request.
+    pub fn should_retry_error(&self, method: &Method, headers: &HeaderMap) -> bool {
+        self.retry_non_idempotent || is_idempotent(method, headers)
+    }
+}
+
+/// Methods that can be replayed safely, plus any request carrying an
+/// `Idempotency-Key`.
+pub fn is_idempotent(method: &Method, headers: &HeaderMap) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS | Method::TRACE
+    ) || headers.contains_key("idempotency-key")
+}
+
+/// How long the server asked us to wait, taken from `Retry-After` (seconds or
+/// an HTTP date) or, failing that, from exhausted `anthropic-ratelimit-*`
+/// buckets.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    if let Some(delay) = header_str(headers, "retry-after").and_then(parse_retry_after) {
+        return Some(delay);
+    }
+
+    ANTHROPIC_RATELIMIT_HEADERS
+        .iter()
+        .filter(|(remaining, _)| header_str(headers, remaining) == Some("0"))
+        .filter_map(|(_, reset)| header_str(headers, reset))
+        .filter_map(|value| humantime::parse_rfc3339_weak(value).ok())
+        .map(until)
+        .max()
+}
+
+/// Parses a `Retry-After` value: whole delay seconds or an HTTP date.
+pub(crate) fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if value.bytes().all(|b| b.is_ascii_digit()) {
+        // Too many seconds to represent is no usable hint
+        return value.parse().ok().map(Duration::from_secs);
+    }
+    httpdate::parse_http_date(value).ok().map(until)
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+}
+
+fn until(at: SystemTime) -> Duration {
+    at.duration_since(SystemTime::now()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    #[test]
+    fn test_backoff_grows_and_caps() {
+        let policy = RetryPolicy::new()
+            .base_delay(Duration::from_millis(100))
+            .max_delay(Duration::from_millis(350))
+            .no_jitter();
+
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(1), Duration::from_millis(200));
+        assert_eq!(policy.backoff(2), Duration::from_millis(350));
+        assert_eq!(policy.backoff(40), Duration::from_millis(350));
+
+        let jittered = RetryPolicy::new().base_delay(Duration::from_millis(100));
+        let delay = jittered.backoff(1);
+        assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_attempt_budget() {
+        let policy = RetryPolicy::new().max_attempts(3).no_jitter();
+        assert!(policy.next_delay(0, None).is_some());
+        assert!(policy.next_delay(1, None).is_some());
+        assert!(policy.next_delay(2, None).is_none());
+        assert!(RetryPolicy::none().next_delay(0, None).is_none());
+
+        let hinted = policy.next_delay(0, Some(Duration::from_secs(600)));
+        assert_eq!(hinted, Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_idempotency() {
+        let policy = RetryPolicy::new();
+        let mut headers = HeaderMap::new();
+        let overloaded = StatusCode::from_u16(STATUS_OVERLOADED).unwrap();
+
+        assert!(policy.should_retry_status(&Method::POST, &headers, StatusCode::TOO_MANY_REQUESTS));
+        assert!(policy.should_retry_status(&Method::POST, &headers, overloaded));
+        assert!(!policy.should_retry_status(&Method::POST, &headers, StatusCode::BAD_GATEWAY));
+        assert!(policy.should_retry_status(&Method::GET, &headers, StatusCode::BAD_GATEWAY));
+        assert!(!policy.should_retry_status(&Method::POST, &headers, StatusCode::REQUEST_TIMEOUT));
+        assert!(policy.should_retry_status(&Method::GET, &headers, StatusCode::REQUEST_TIMEOUT));
+        assert!(!policy.should_retry_status(&Method::GET, &headers, StatusCode::BAD_REQUEST));
+        assert!(!policy.should_retry_error(&Method::POST, &headers));
+
+        headers.insert("idempotency-key", HeaderValue::from_static("abc"));
+        assert!(policy.should_retry_error(&Method::POST, &headers));
+    }
+
+    #[test]
+    fn test_retry_after_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert("retry-after", HeaderValue::from_static("20"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(20)));
+
+        // Values that do not fit a Duration are ignored rather than panicking
+        for huge in ["1e30", "99999999999999999999999", "-5", "1.5"] {
+            assert_eq!(parse_retry_after(huge), None, "{}", huge);
+        }
+
+        let mut headers = HeaderMap::new();
+        let reset = humantime::format_rfc3339_seconds(SystemTime::now() + Duration::from_secs(30));
+        headers.insert(
+            "anthropic-ratelimit-tokens-reset",
+            HeaderValue::from_str(&reset.to_string()).unwrap(),
+        );
+        // Buckets that still have capacity are ignored
+        headers.insert(
+            "anthropic-ratelimit-tokens-remaining",
+            HeaderValue::from_static("10"),
+        );
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert(
+            "anthropic-ratelimit-tokens-remaining",
+            HeaderValue::from_static("0"),
+        );
+        let delay = retry_after(&headers).unwrap();
+        assert!(delay > Duration::from_secs(25) && delay <= Duration::from_secs(30));
+    }
+}
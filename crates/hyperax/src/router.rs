@@ -0,0 +1,466 @@
+use crate::server::{RequestBody, ResponseBody, ServerResponse};
+use hyper::header::{HeaderValue, ALLOW};
+use hyper::{Method, Request, Response, StatusCode};
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+pub type RouteFuture = Pin<Box<dyn Future<Output = Response<ResponseBody>> + Send>>;
+
+/// Future returned by [`Router::into_handler`], in the shape `Server::run`
+/// expects.
+pub type HandlerFuture =
+    Pin<Box<dyn Future<Output = Result<Response<ResponseBody>, Infallible>> + Send>>;
+
+type BoxHandler<B> = Arc<dyn Fn(Request<B>) -> RouteFuture + Send + Sync>;
+
+/// Dispatches requests to handlers by method and path.
+///
+/// Paths are split on `/`. A segment starting with `:` captures one segment
+/// and one starting with `*` captures the rest of the path, so
+/// `/v1/conversations/:id` and `/files/*path` both work. Captures are stored
+/// as [`PathParams`] in the request extensions.
+///
+/// When several routes match, the most specific wins: literal segments beat
+/// captures, which beat wildcards. A path that matches but has no handler
+/// for the method gets a 405 with an `Allow` header, anything else a 404.
+pub struct Router<B = RequestBody> {
+    routes: Vec<Route<B>>,
+    fallbacks: Vec<(Vec<Segment>, BoxHandler<B>)>,
+}
+
+struct Route<B> {
+    segments: Vec<Segment>,
+    handlers: Vec<(Method, BoxHandler<B>)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Static(String),
+    Param(String),
+    Wildcard(String),
+}
+
+impl Segment {
+    fn rank(&self) -> u8 {
+        match self {
+            Segment::Static(_) => 2,
+            Segment::Param(_) => 1,
+            Segment::Wildcard(_) => 0,
+        }
+    }
+}
+
+/// Path captures for the matched route.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PathParams(Vec<(String, String)>);
+
+static NO_PARAMS: PathParams = PathParams(Vec::new());
+
+impl PathParams {
+    /// The params the router attached to `req`, empty if it was not routed.
+    pub fn of<B>(req: &Request<B>) -> &PathParams {
+        req.extensions().get::<PathParams>().unwrap_or(&NO_PARAMS)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// The values percent-decoded, or `None` if one isn't UTF-8 afterwards.
+    fn decoded(self) -> Option<PathParams> {
+        self.0
+            .into_iter()
+            .map(|(name, value)| Some((name, percent_decode(&value)?)))
+            .collect::<Option<_>>()
+            .map(PathParams)
+    }
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+impl<B: Send + 'static> Default for Router<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: Send + 'static> Router<B> {
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            fallbacks: Vec::new(),
+        }
+    }
+
+    pub fn route<H, Fut>(self, method: Method, path: &str, handler: H) -> Self
+    where
+        H: Fn(Request<B>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response<ResponseBody>> + Send + 'static,
+    {
+        let handler: BoxHandler<B> = Arc::new(move |req| Box::pin(handler(req)));
+        self.route_boxed(method, parse_path(path), handler)
+    }
+
+    pub fn get<H, Fut>(self, path: &str, handler: H) -> Self
+    where
+        H: Fn(Request<B>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response<ResponseBody>> + Send + 'static,
+    {
+        self.route(Method::GET, path, handler)
+    }
+
+    pub fn post<H, Fut>(self, path: &str, handler: H) -> Self
+    where
+        H: Fn(Request<B>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response<ResponseBody>> + Send + 'static,
+    {
+        self.route(Method::POST, path, handler)
+    }
+
+    pub fn put<H, Fut>(self, path: &str, handler: H) -> Self
+    where
+        H: Fn(Request<B>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response<ResponseBody>> + Send + 'static,
+    {
+        self.route(Method::PUT, path, handler)
+    }
+
+    pub fn patch<H, Fut>(self, path: &str, handler: H) -> Self
+    where
+        H: Fn(Request<B>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response<ResponseBody>> + Send + 'static,
+    {
+        self.route(Method::PATCH, path, handler)
+    }
+
+    pub fn delete<H, Fut>(self, path: &str, handler: H) -> Self
+    where
+        H: Fn(Request<B>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response<ResponseBody>> + Send + 'static,
+    {
+        self.route(Method::DELETE, path, handler)
+    }
+
+    /// Mounts all routes of `router` under `prefix`. The prefix may contain
+    /// captures of its own.
+    pub fn nest(mut self, prefix: &str, router: Router<B>) -> Self {
+        let prefix = parse_path(prefix);
+        for route in router.routes {
+            let mut segments = prefix.clone();
+            segments.extend(route.segments);
+            for (method, handler) in route.handlers {
+                self = self.route_boxed(method, segments.clone(), handler);
+            }
+        }
+        for (segments, handler) in router.fallbacks {
+            let mut full = prefix.clone();
+            full.extend(segments);
+            self.fallbacks.push((full, handler));
+        }
+        self
+    }
+
+    /// Handles requests no route matched instead of the default 404. A nested
+    /// router's fallback only applies below its prefix.
+    pub fn fallback<H, Fut>(mut self, handler: H) -> Self
+    where
+        H: Fn(Request<B>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response<ResponseBody>> + Send + 'static,
+    {
+        self.fallbacks
+            .push((Vec::new(), Arc::new(move |req| Box::pin(handler(req)))));
+        self
+    }
+
+    fn route_boxed(
+        mut self,
+        method: Method,
+        segments: Vec<Segment>,
+        handler: BoxHandler<B>,
+    ) -> Self {
+        match self.routes.iter_mut().find(|r| r.segments == segments) {
+            Some(route) => route.handlers.push((method, handler)),
+            None => self.routes.push(Route {
+                segments,
+                handlers: vec![(method, handler)],
+            }),
+        }
+        self
+    }
+
+    pub async fn handle(&self, mut req: Request<B>) -> Response<ResponseBody> {
+        let path: Vec<&str> = split(req.uri().path()).collect();
+
+        let matched = self
+            .routes
+            .iter()
+            .filter_map(|route| match_segments(&route.segments, &path).map(|p| (route, p)))
+            .max_by(|(a, _), (b, _)| specificity(&a.segments).cmp(&specificity(&b.segments)));
+
+        let Some((route, raw)) = matched else {
+            return match self.fallback_for(&path) {
+                Some(handler) => handler(req).await,
+                None => ServerResponse::new(StatusCode::NOT_FOUND).text("Not Found"),
+            };
+        };
+
+        let handler = route
+            .handlers
+            .iter()
+            .find(|(method, _)| method == req.method())
+            .or_else(|| {
+                // Answer HEAD with the GET handler, hyper drops the body
+                (req.method() == Method::HEAD)
+                    .then(|| route.handlers.iter().find(|(m, _)| m == Method::GET))
+                    .flatten()
+            });
+
+        match handler {
+            Some((_, handler)) => {
+                let Some(params) = raw.decoded() else {
+                    return ServerResponse::new(StatusCode::BAD_REQUEST).text("Bad Request");
+                };
+                req.extensions_mut().insert(params);
+                handler(req).await
+            }
+            None => {
+                let allow = route
+                    .handlers
+                    .iter()
+                    .map(|(method, _)| method.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let mut response =
+                    ServerResponse::new(StatusCode::METHOD_NOT_ALLOWED).text("Method Not Allowed");
+                if let Ok(allow) = HeaderValue::from_str(&allow) {
+                    response.headers_mut().insert(ALLOW, allow);
+                }
+                response
+            }
+        }
+    }
+
+    /// The fallback with the longest prefix matching `path`.
+    fn fallback_for(&self, path: &[&str]) -> Option<&BoxHandler<B>> {
+        self.fallbacks
+            .iter()
+            .filter(|(prefix, _)| {
+                prefix.len() <= path.len()
+                    && match_segments(prefix, &path[..prefix.len()]).is_some()
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, handler)| handler)
+    }
+
+    /// Converts the router into a handler for `Server::run`.
+    pub fn into_handler(
+        self,
+    ) -> impl Fn(Request<B>) -> HandlerFuture + Clone + Send + Sync + 'static
+    where
+        B: 'static,
+    {
+        let router = Arc::new(self);
+        move |req| {
+            let router = router.clone();
+            Box::pin(async move { Ok(router.handle(req).await) })
+        }
+    }
+}
+
+fn split(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+fn parse_path(path: &str) -> Vec<Segment> {
+    split(path)
+        .map(|segment| {
+            if let Some(name) = segment.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else if let Some(name) = segment.strip_prefix('*') {
+                Segment::Wildcard(name.to_string())
+            } else {
+                Segment::Static(segment.to_string())
+            }
+        })
+        .collect()
+}
+
+fn specificity(segments: &[Segment]) -> Vec<u8> {
+    segments.iter().map(Segment::rank).collect()
+}
+
+fn match_segments(segments: &[Segment], path: &[&str]) -> Option<PathParams> {
+    let mut params = Vec::new();
+    for (i, segment) in segments.iter().enumerate() {
+        match segment {
+            Segment::Wildcard(name) => {
+                params.push((name.clone(), path.get(i..).unwrap_or_default().join("/")));
+                return Some(PathParams(params));
+            }
+            Segment::Static(expected) => {
+                if path.get(i) != Some(&expected.as_str()) {
+                    return None;
+                }
+            }
+            Segment::Param(name) => params.push((name.clone(), path.get(i)?.to_string())),
+        }
+    }
+    (segments.len() == path.len()).then_some(PathParams(params))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+
+    fn request(method: Method, path: &str) -> Request<()> {
+        Request::builder()
+            .method(method)
+            .uri(path)
+            .body(())
+            .unwrap()
+    }
+
+    async fn body(response: Response<ResponseBody>) -> String {
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    fn echo(
+        label: &'static str,
+    ) -> impl Fn(Request<()>) -> std::future::Ready<Response<ResponseBody>> {
+        move |req| {
+            let params = PathParams::of(&req)
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<_>>()
+                .join(",");
+            std::future::ready(ServerResponse::ok().text(format!("{} {}", label, params)))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_router_matching() {
+        let router = Router::new()
+            .get("/v1/conversations", echo("list"))
+            .post("/v1/conversations", echo("create"))
+            .get("/v1/conversations/latest", echo("latest"))
+            .get("/v1/conversations/:id", echo("show"))
+            .get("/files/*path", echo("file"));
+
+        let response = router
+            .handle(request(Method::GET, "/v1/conversations/"))
+            .await;
+        assert_eq!(body(response).await, "list ");
+
+        let response = router
+            .handle(request(Method::POST, "/v1/conversations"))
+            .await;
+        assert_eq!(body(response).await, "create ");
+
+        let response = router
+            .handle(request(Method::GET, "/v1/conversations/42"))
+            .await;
+        assert_eq!(body(response).await, "show id=42");
+
+        let response = router
+            .handle(request(Method::GET, "/v1/conversations/latest"))
+            .await;
+        assert_eq!(body(response).await, "latest ");
+
+        let response = router.handle(request(Method::GET, "/files/a/b.txt")).await;
+        assert_eq!(body(response).await, "file path=a/b.txt");
+
+        let response = router
+            .handle(request(Method::GET, "/v1/conversations/a%20b%C3%A9"))
+            .await;
+        assert_eq!(body(response).await, "show id=a bé");
+
+        let response = router
+            .handle(request(Method::GET, "/v1/conversations/%FF"))
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = router
+            .handle(request(Method::HEAD, "/v1/conversations"))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_router_errors() {
+        let router = Router::new()
+            .get("/items/:id", echo("show"))
+            .delete("/items/:id", echo("delete"));
+
+        let response = router.handle(request(Method::GET, "/nope")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = router.handle(request(Method::GET, "/items/1/extra")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = router.handle(request(Method::PUT, "/items/1")).await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[ALLOW], "GET, DELETE");
+    }
+
+    #[tokio::test]
+    async fn test_router_nesting() {
+        let messages = Router::new()
+            .get("/", echo("messages"))
+            .get("/:message", echo("message"))
+            .fallback(echo("messages-fallback"));
+        let conversations = Router::new().nest("/:id/messages", messages);
+        let router = Router::new()
+            .nest("/v1/conversations", conversations)
+            .fallback(echo("root-fallback"));
+
+        let response = router
+            .handle(request(Method::GET, "/v1/conversations/7/messages"))
+            .await;
+        assert_eq!(body(response).await, "messages id=7");
+
+        let response = router
+            .handle(request(Method::GET, "/v1/conversations/7/messages/3"))
+            .await;
+        assert_eq!(body(response).await, "message id=7,message=3");
+
+        let response = router
+            .handle(request(Method::GET, "/v1/conversations/7/messages/3/x"))
+            .await;
+        assert_eq!(body(response).await, "messages-fallback ");
+
+        let response = router.handle(request(Method::GET, "/v2")).await;
+        assert_eq!(body(response).await, "root-fallback ");
+    }
+}
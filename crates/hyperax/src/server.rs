@@ -1,15 +1,26 @@
-use http_body_util::Full;
-use hyper::body::Bytes;
+use crate::body;
+use crate::listener::{BindTarget, Connection, Listener, LocalAddr};
+use crate::middleware::{Endpoint, Middleware, Next};
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes, Incoming};
+use hyper::header::{HeaderName, HeaderValue, CONTENT_TYPE};
 use hyper::service::Service;
-use hyper::{Request, Response};
-use hyper_util::rt::TokioIo;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use serde::Serialize;
 use std::convert::Infallible;
 use std::future::Future;
 use std::net::SocketAddr;
-use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::pin::Pin;
 use std::sync::Arc;
-use tokio::net::TcpListener;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio_rustls::TlsAcceptor;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -19,87 +30,343 @@ pub enum Error {
     Io(#[from] std::io::Error),
 }
 
+pub use crate::body::{BoxError, RequestBody, ResponseBody};
+
 pub struct Server {
-    addr: SocketAddr,
+    target: BindTarget,
+    listener: Option<Listener>,
+    tls: Option<TlsAcceptor>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    drain_timeout: Duration,
 }
 
 impl Server {
     pub fn new(addr: SocketAddr) -> Self {
-        Self { addr }
+        Self::with_target(BindTarget::Tcp(addr))
+    }
+
+    /// Listens on a Unix domain socket at `path` instead of a TCP port. A
+    /// stale socket file from an earlier run is replaced, and the file is
+    /// removed again on shutdown.
+    #[cfg(unix)]
+    pub fn unix(path: impl Into<PathBuf>) -> Self {
+        Self::with_target(BindTarget::Unix {
+            path: path.into(),
+            mode: None,
+        })
+    }
+
+    fn with_target(target: BindTarget) -> Self {
+        Self {
+            target,
+            listener: None,
+            tls: None,
+            middleware: Vec::new(),
+            drain_timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// File mode for the Unix socket, e.g. `0o600` so only the current user
+    /// can connect. Without it the socket gets the process umask. Has no
+    /// effect on TCP servers.
+    #[cfg(unix)]
+    pub fn permissions(mut self, mode: u32) -> Self {
+        if let BindTarget::Unix { mode: m, .. } = &mut self.target {
+            *m = Some(mode);
+        }
+        self
+    }
+
+    /// Serves HTTPS. Clients pick HTTP/2 or HTTP/1.1 through ALPN; `h2` and
+    /// `http/1.1` are offered unless `config` lists its own protocols.
+    pub fn tls(mut self, config: crate::tls::rustls::ServerConfig) -> Self {
+        self.tls = Some(TlsAcceptor::from(crate::tls::with_server_alpn(config)));
+        self
+    }
+
+    /// Wraps every request in `middleware`. Layers added first run outermost.
+    pub fn layer(mut self, middleware: impl Middleware) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// How long shutdown waits for in-flight requests before closing their
+    /// connections. Defaults to 30 seconds.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
     }
 
-    pub async fn run<F, Fut>(
+    /// Opens the listener without serving yet and returns the address it is
+    /// bound to. Binding port 0 lets the OS pick a free port, which this
+    /// reports. `run` binds on its own if this was not called.
+    pub async fn bind(&mut self) -> Result<LocalAddr, Error> {
+        if self.listener.is_none() {
+            self.listener = Some(Listener::bind(&self.target).await?);
+        }
+        self.local_addr()
+    }
+
+    /// Address of the bound listener. Fails if `bind` has not been called.
+    pub fn local_addr(&self) -> Result<LocalAddr, Error> {
+        match &self.listener {
+            Some(listener) => Ok(listener.local_addr()?),
+            None => Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "server is not bound",
+            ))),
+        }
+    }
+
+    /// Serves requests until `shutdown` changes.
+    ///
+    /// On shutdown the listener is closed and every connection is asked to
+    /// close gracefully: idle keep-alive connections close right away and
+    /// busy ones after their current response. Connections still open when
+    /// the drain timeout expires are aborted.
+    ///
+    /// Connections speak HTTP/1.1 or HTTP/2, chosen per connection.
+    ///
+    /// Handlers may return any body, buffered or streaming; streaming bodies
+    /// are written out as their frames become ready.
+    pub async fn run<F, Fut, B>(
         &mut self,
         handler: F,
         mut shutdown: watch::Receiver<bool>,
-    ) -> Result<(), Error>
+    ) -> Result<ShutdownReport, Error>
     where
-        F: Fn(Request<hyper::body::Incoming>) -> Fut + Clone + Send + Sync + 'static,
-        Fut: Future<Output = Result<Response<Full<Bytes>>, Infallible>> + Send + 'static,
+        F: Fn(Request<RequestBody>) -> Fut + Clone + Send + Sync + 'static,
+        Fut: Future<Output = Result<Response<B>, Infallible>> + Send + 'static,
+        B: Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<BoxError>,
     {
-        let listener = TcpListener::bind(self.addr).await?;
-        let active_connections = Arc::new(AtomicUsize::new(0));
+        let service = HandlerService {
+            stack: self.middleware.clone().into(),
+            endpoint: endpoint(handler),
+        };
+        self.bind().await?;
+        let listener = self.listener.take().expect("listener was just bound");
+        let mut connections = JoinSet::new();
 
         loop {
             tokio::select! {
                 accept_result = listener.accept() => {
                     match accept_result {
-                        Ok((stream, _)) => {
-                            let io = TokioIo::new(stream);
-                            let handler = handler.clone();
-                            let mut shutdown = shutdown.clone();
-                            let connections = active_connections.clone();
-                            connections.fetch_add(1, Ordering::SeqCst);
-
-                            tokio::task::spawn(async move {
-                                let conn = hyper::server::conn::http1::Builder::new()
-                                    .serve_connection(io, HandlerService(handler));
-
-                                // Run connection until shutdown signal received
-                                tokio::select! {
-                                    result = conn => {
-                                        if let Err(err) = result {
-                                            eprintln!("Error serving connection: {:?}", err);
-                                        }
+                        Ok(Connection::Tcp(stream)) => match self.tls.clone() {
+                            Some(acceptor) => {
+                                let (service, shutdown) = (service.clone(), shutdown.clone());
+                                connections.spawn(async move {
+                                    match acceptor.accept(stream).await {
+                                        Ok(stream) => serve(stream, service, shutdown).await,
+                                        Err(e) => eprintln!("TLS handshake failed: {}", e),
                                     }
-                                    _ = shutdown.changed() => {}
-                                }
-
-                                connections.fetch_sub(1, Ordering::SeqCst);
-                            });
+                                });
+                            }
+                            None => {
+                                connections.spawn(serve(stream, service.clone(), shutdown.clone()));
+                            }
+                        },
+                        #[cfg(unix)]
+                        Ok(Connection::Unix(stream)) => {
+                            connections.spawn(serve(stream, service.clone(), shutdown.clone()));
                         }
                         Err(e) => eprintln!("Accept error: {}", e),
                     }
                 }
+                // Reap finished connections so the set does not grow
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
                 _ = shutdown.changed() => {
                     println!("Server shutting down...");
-                    // Wait for active connections to complete
-                    while active_connections.load(Ordering::SeqCst) > 0 {
-                        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-                    }
-                    return Ok(());
+                    drop(listener);
+                    return Ok(drain(connections, self.drain_timeout).await);
                 }
             }
         }
     }
 }
 
-struct HandlerService<F>(F);
-
-impl<F, Fut> Service<Request<hyper::body::Incoming>> for HandlerService<F>
+async fn serve<I>(io: I, service: HandlerService, mut shutdown: watch::Receiver<bool>)
 where
-    F: Fn(Request<hyper::body::Incoming>) -> Fut,
-    Fut: Future<Output = Result<Response<Full<Bytes>>, Infallible>>,
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
-    type Response = Response<Full<Bytes>>;
+    // Speaks HTTP/1.1, and HTTP/2 when the client opens with the h2 preface
+    // (after ALPN, or as h2c prior knowledge over plain TCP). Upgrades such
+    // as CONNECT are handed to the handler through `hyper::upgrade::on`
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let conn = builder.serve_connection_with_upgrades(TokioIo::new(io), service);
+    tokio::pin!(conn);
+
+    // On shutdown, finish the in-flight response and close
+    let result = tokio::select! {
+        result = conn.as_mut() => result,
+        _ = shutdown.changed() => {
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+    };
+    if let Err(err) = result {
+        eprintln!("Error serving connection: {:?}", err);
+    }
+}
+
+/// Outcome of a graceful shutdown.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// Connections that closed on their own within the drain timeout.
+    pub drained: usize,
+    /// Connections that were still busy at the deadline and got cut.
+    pub aborted: usize,
+}
+
+async fn drain(mut connections: JoinSet<()>, timeout: Duration) -> ShutdownReport {
+    let mut report = ShutdownReport::default();
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        match tokio::time::timeout_at(deadline, connections.join_next()).await {
+            Ok(Some(_)) => report.drained += 1,
+            Ok(None) => return report,
+            Err(_) => break,
+        }
+    }
+
+    report.aborted = connections.len();
+    if report.aborted > 0 {
+        eprintln!(
+            "Drain timeout expired, aborting {} connection(s)",
+            report.aborted
+        );
+    }
+    connections.shutdown().await;
+    report
+}
+
+#[derive(Clone)]
+struct HandlerService {
+    stack: Arc<[Arc<dyn Middleware>]>,
+    endpoint: Endpoint,
+}
+
+impl Service<Request<Incoming>> for HandlerService {
+    type Response = Response<ResponseBody>;
     type Error = Infallible;
-    type Future = Fut;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Infallible>> + Send>>;
 
-    fn call(&self, req: Request<hyper::body::Incoming>) -> Self::Future {
-        (self.0)(req)
+    fn call(&self, req: Request<Incoming>) -> Self::Future {
+        let req = req.map(|body| body.map_err(BoxError::from).boxed_unsync());
+        let response = Next::new(self.stack.clone(), self.endpoint.clone()).run(req);
+        Box::pin(async move { Ok(response.await) })
     }
 }
 
+fn endpoint<F, Fut, B>(handler: F) -> Endpoint
+where
+    F: Fn(Request<RequestBody>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Response<B>, Infallible>> + Send + 'static,
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    Arc::new(move |req| {
+        let response = handler(req);
+        Box::pin(async move {
+            match response.await {
+                Ok(response) => response.map(body::boxed),
+                Err(never) => match never {},
+            }
+        })
+    })
+}
+
+/// Runs `req` through `stack` and `handler` without a connection.
+#[cfg(test)]
+pub(crate) fn dispatch<F, Fut, B>(
+    stack: Arc<[Arc<dyn Middleware>]>,
+    handler: F,
+    req: Request<RequestBody>,
+) -> crate::middleware::MiddlewareFuture
+where
+    F: Fn(Request<RequestBody>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Response<B>, Infallible>> + Send + 'static,
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    Next::new(stack, endpoint(handler)).run(req)
+}
+
+/// Builder for handler responses.
+///
+/// ```
+/// use hyperax::{ServerResponse, StatusCode};
+///
+/// let response = ServerResponse::new(StatusCode::CREATED)
+///     .header("x-request-id", "42")
+///     .json(&serde_json::json!({ "id": 1 }));
+/// assert_eq!(response.status(), 201);
+/// ```
+pub struct ServerResponse {
+    builder: hyper::http::response::Builder,
+}
+
+impl ServerResponse {
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            builder: Response::builder().status(status),
+        }
+    }
+
+    pub fn ok() -> Self {
+        Self::new(StatusCode::OK)
+    }
+
+    pub fn header<K, V>(mut self, name: K, value: V) -> Self
+    where
+        HeaderName: TryFrom<K>,
+        <HeaderName as TryFrom<K>>::Error: Into<hyper::http::Error>,
+        HeaderValue: TryFrom<V>,
+        <HeaderValue as TryFrom<V>>::Error: Into<hyper::http::Error>,
+    {
+        self.builder = self.builder.header(name, value);
+        self
+    }
+
+    /// Serializes `value` as the JSON body. Serialization failures turn into
+    /// a plain 500 response.
+    pub fn json<T: Serialize + ?Sized>(self, value: &T) -> Response<ResponseBody> {
+        match serde_json::to_vec(value) {
+            Ok(body) => self
+                .header(CONTENT_TYPE, "application/json")
+                .body(Bytes::from(body)),
+            Err(e) => internal_error(format!("failed to serialize response: {}", e)),
+        }
+    }
+
+    pub fn text(self, body: impl Into<String>) -> Response<ResponseBody> {
+        self.header(CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(Bytes::from(body.into()))
+    }
+
+    pub fn empty(self) -> Response<ResponseBody> {
+        self.body(Bytes::new())
+    }
+
+    pub fn body(self, body: impl Into<Bytes>) -> Response<ResponseBody> {
+        self.streaming(body::full(body))
+    }
+
+    /// Sends `body` as it is produced, e.g. from [`body::channel`].
+    pub fn streaming(self, body: ResponseBody) -> Response<ResponseBody> {
+        self.builder
+            .body(body)
+            .unwrap_or_else(|e| internal_error(format!("invalid response: {}", e)))
+    }
+}
+
+fn internal_error(message: String) -> Response<ResponseBody> {
+    eprintln!("{}", message);
+    let mut response = Response::new(body::full(Bytes::from_static(b"Internal Server Error")));
+    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+    response
+}
+
 // async fn main() {
 //     let mut server = Server::new(SocketAddr::from(([127, 0, 0, 1], 8080)));
 //     let (tx, rx) = watch::channel(false);
@@ -126,13 +393,35 @@ mod tests {
     use http_body_util::Full;
     use std::convert::Infallible;
     use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use tokio::sync::watch;
 
+    /// Binds `server` and returns the port the OS picked.
+    async fn bound(mut server: Server) -> (Server, SocketAddr) {
+        let addr = server.bind().await.unwrap().tcp().unwrap();
+        (server, addr)
+    }
+
+    fn any_port() -> SocketAddr {
+        "127.0.0.1:0".parse().unwrap()
+    }
+
+    async fn sleepy(
+        _req: Request<RequestBody>,
+        delay: Duration,
+    ) -> Result<Response<ResponseBody>, Infallible> {
+        tokio::time::sleep(delay).await;
+        Ok(ServerResponse::ok().text("done"))
+    }
+
     #[tokio::test]
     async fn test_server_basic() {
         // Setup
         let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
         let mut server = Server::new(addr);
+        let bound = server.bind().await.unwrap();
+        assert_ne!(bound.tcp().unwrap().port(), 0);
+        assert_eq!(server.local_addr().unwrap(), bound);
         let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
         // Track request count
@@ -178,4 +467,234 @@ mod tests {
             "No requests should have been processed"
         );
     }
+
+    #[tokio::test]
+    async fn test_server_response_builder() {
+        use http_body_util::BodyExt;
+
+        let response = ServerResponse::ok().json(&serde_json::json!({ "ok": true }));
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, Bytes::from(r#"{"ok":true}"#));
+
+        let response = ServerResponse::new(StatusCode::NOT_FOUND).text("missing");
+        assert_eq!(response.status(), 404);
+        assert_eq!(
+            response.headers()[CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+
+        let response = ServerResponse::ok().header("bad header", "x").empty();
+        assert_eq!(response.status(), 500);
+    }
+
+    #[tokio::test]
+    async fn test_server_drains_in_flight_requests() {
+        let (mut server, addr) = bound(Server::new(any_port())).await;
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let server = tokio::spawn(async move {
+            server
+                .run(|req| sleepy(req, Duration::from_millis(200)), shutdown_rx)
+                .await
+        });
+
+        let request =
+            tokio::spawn(
+                async move { crate::Client::new().get(&format!("http://{}/", addr)).await },
+            );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        shutdown_tx.send(true).unwrap();
+
+        // The slow response still completes
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(response.body(), &Bytes::from("done"));
+
+        let report = server.await.unwrap().unwrap();
+        assert_eq!(
+            report,
+            ShutdownReport {
+                drained: 1,
+                aborted: 0
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_aborts_after_drain_timeout() {
+        let server = Server::new(any_port()).drain_timeout(Duration::from_millis(100));
+        let (mut server, addr) = bound(server).await;
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let server = tokio::spawn(async move {
+            server
+                .run(|req| sleepy(req, Duration::from_secs(30)), shutdown_rx)
+                .await
+        });
+
+        let request =
+            tokio::spawn(
+                async move { crate::Client::new().get(&format!("http://{}/", addr)).await },
+            );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        shutdown_tx.send(true).unwrap();
+
+        let report = tokio::time::timeout(Duration::from_secs(1), server)
+            .await
+            .expect("drain timeout was not enforced")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            report,
+            ShutdownReport {
+                drained: 0,
+                aborted: 1
+            }
+        );
+        assert!(request.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_server_streams_sse() {
+        use crate::sse::{self, Event, Sse};
+        use crate::transport::{HttpTransport, Transport};
+        use futures_util::StreamExt;
+
+        let (mut server, addr) = bound(Server::new(any_port())).await;
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let server = tokio::spawn(async move {
+            server
+                .run(
+                    |_req| async {
+                        let (tx, sse) = Sse::channel();
+                        tokio::spawn(async move {
+                            for n in 0..3 {
+                                tx.send(Event::new(n.to_string())).await.unwrap();
+                                tokio::time::sleep(Duration::from_millis(30)).await;
+                            }
+                        });
+                        Ok::<_, Infallible>(sse.into_response())
+                    },
+                    shutdown_rx,
+                )
+                .await
+        });
+
+        let request = Request::get(format!("http://{}/events", addr))
+            .body(body::empty())
+            .unwrap();
+        let response = HttpTransport::new().send(request).await.unwrap();
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/event-stream");
+
+        // The first event arrives before the handler has produced the rest
+        let started = tokio::time::Instant::now();
+        let chunks = response.into_body().into_data_stream();
+        let mut events = Box::pin(sse::decode(chunks));
+        let first = events.next().await.unwrap().unwrap();
+        assert_eq!(first.data, "0");
+        assert!(started.elapsed() < Duration::from_millis(50));
+
+        let rest: Vec<_> = events.map(|e| e.unwrap().data).collect().await;
+        assert_eq!(rest, vec!["1", "2"]);
+
+        shutdown_tx.send(true).unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_server_unix_socket() {
+        use std::os::unix::fs::PermissionsExt;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let path = std::env::temp_dir().join(format!("hyperax-{}.sock", std::process::id()));
+        let mut server = Server::unix(&path).permissions(0o600);
+        assert_eq!(server.bind().await.unwrap(), LocalAddr::Unix(path.clone()));
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let server = tokio::spawn(async move {
+            server
+                .run(
+                    |_req| async { Ok::<_, Infallible>(ServerResponse::ok().text("local")) },
+                    shutdown_rx,
+                )
+                .await
+        });
+
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("local"), "{}", response);
+
+        shutdown_tx.send(true).unwrap();
+        server.await.unwrap().unwrap();
+        assert!(!path.exists(), "socket file was not cleaned up");
+    }
+
+    #[tokio::test]
+    async fn test_server_http2_over_tls_and_h2c() {
+        use crate::tls::rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
+        use crate::tls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+        use crate::transport::HttpVersion;
+
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_der = CertificateDer::from(cert.cert.der().to_vec());
+        let key_der = PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
+        let server_config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert_der.clone()], key_der.into())
+            .unwrap();
+        let mut roots = RootCertStore::empty();
+        roots.add(cert_der).unwrap();
+        let client_config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        let version = |req: Request<RequestBody>| async move {
+            Ok::<_, Infallible>(ServerResponse::ok().text(format!("{:?}", req.version())))
+        };
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let (mut tls_server, tls_addr) = bound(Server::new(any_port()).tls(server_config)).await;
+        let (mut plain_server, plain_addr) = bound(Server::new(any_port())).await;
+        let tls_server = tokio::spawn({
+            let shutdown_rx = shutdown_rx.clone();
+            async move { tls_server.run(version, shutdown_rx).await }
+        });
+        let plain_server =
+            tokio::spawn(async move { plain_server.run(version, shutdown_rx).await });
+
+        // ALPN picks h2 unless the client only offers HTTP/1.1
+        let tls_url = format!("https://localhost:{}/", tls_addr.port());
+        let client = crate::Client::builder()
+            .tls_config(client_config.clone())
+            .build();
+        let response = client.get(&tls_url).await.unwrap();
+        assert_eq!(response.version(), hyper::Version::HTTP_2);
+        assert_eq!(response.body(), &Bytes::from("HTTP/2.0"));
+
+        let client = crate::Client::builder()
+            .tls_config(client_config)
+            .http_version(HttpVersion::Http1Only)
+            .build();
+        let response = client.get(&tls_url).await.unwrap();
+        assert_eq!(response.body(), &Bytes::from("HTTP/1.1"));
+
+        // Plain TCP serves HTTP/1.1 and h2c prior knowledge side by side
+        let plain_url = format!("http://{}/", plain_addr);
+        let response = crate::Client::new().get(&plain_url).await.unwrap();
+        assert_eq!(response.body(), &Bytes::from("HTTP/1.1"));
+        let client = crate::Client::builder().http2_prior_knowledge().build();
+        let response = client.get(&plain_url).await.unwrap();
+        assert_eq!(response.body(), &Bytes::from("HTTP/2.0"));
+
+        shutdown_tx.send(true).unwrap();
+        tls_server.await.unwrap().unwrap();
+        plain_server.await.unwrap().unwrap();
+    }
 }
@@ -0,0 +1,448 @@
+//! Server-sent events: a response helper for handlers and an incremental
+//! decoder for clients reading `text/event-stream` bodies.
+
+use crate::body::{self, BoxError, ResponseBody};
+use futures_util::{Stream, StreamExt};
+use hyper::body::Bytes;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::{Response, StatusCode};
+use serde::Serialize;
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{Instant, Sleep};
+
+pub const CONTENT_TYPE_EVENT_STREAM: &str = "text/event-stream";
+
+/// Comment written when the stream has been idle for the keep-alive interval.
+const KEEP_ALIVE: &[u8] = b": keep-alive\n\n";
+
+/// A single server-sent event.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Event {
+    pub id: Option<String>,
+    pub event: Option<String>,
+    pub data: String,
+    pub retry: Option<Duration>,
+}
+
+impl Event {
+    pub fn new(data: impl Into<String>) -> Self {
+        Self {
+            data: data.into(),
+            ..Self::default()
+        }
+    }
+
+    /// An event whose data is `value` serialized as JSON.
+    pub fn json<T: Serialize + ?Sized>(value: &T) -> Result<Self, serde_json::Error> {
+        serde_json::to_string(value).map(Self::new)
+    }
+
+    pub fn event(mut self, name: impl Into<String>) -> Self {
+        self.event = Some(name.into());
+        self
+    }
+
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Reconnection delay the client should use.
+    pub fn retry(mut self, delay: Duration) -> Self {
+        self.retry = Some(delay);
+        self
+    }
+
+    /// Wire encoding, including the blank line that terminates the event.
+    /// Multi-line data is split across several `data:` fields.
+    pub fn to_bytes(&self) -> Bytes {
+        let mut out = String::new();
+        if let Some(event) = &self.event {
+            push_field(&mut out, "event", event);
+        }
+        if let Some(id) = &self.id {
+            push_field(&mut out, "id", id);
+        }
+        if let Some(retry) = self.retry {
+            push_field(&mut out, "retry", &retry.as_millis().to_string());
+        }
+        for line in self.data.split('\n') {
+            push_field(&mut out, "data", line.strip_suffix('\r').unwrap_or(line));
+        }
+        out.push('\n');
+        Bytes::from(out)
+    }
+}
+
+fn push_field(out: &mut String, name: &str, value: &str) {
+    // Line breaks would end the field early, so they are dropped
+    let value: String = value
+        .chars()
+        .filter(|c| !matches!(c, '\r' | '\n'))
+        .collect();
+    out.push_str(name);
+    out.push_str(": ");
+    out.push_str(&value);
+    out.push('\n');
+}
+
+/// An event stream response.
+///
+/// ```
+/// use futures_util::stream;
+/// use hyperax::sse::{Event, Sse};
+/// use std::time::Duration;
+///
+/// let events = stream::iter(vec![Event::new("hello"), Event::new("world")]);
+/// let response = Sse::new(events)
+///     .keep_alive(Duration::from_secs(15))
+///     .into_response();
+/// assert_eq!(response.headers()["content-type"], "text/event-stream");
+/// ```
+pub struct Sse {
+    events: Pin<Box<dyn Stream<Item = Event> + Send>>,
+    keep_alive: Option<Duration>,
+}
+
+impl Sse {
+    pub fn new<S>(events: S) -> Self
+    where
+        S: Stream<Item = Event> + Send + 'static,
+    {
+        Self {
+            events: Box::pin(events),
+            keep_alive: None,
+        }
+    }
+
+    /// An event stream fed from another task. The response ends when the
+    /// sender is dropped.
+    pub fn channel() -> (mpsc::Sender<Event>, Self) {
+        let (tx, mut rx) = mpsc::channel(16);
+        let events = futures_util::stream::poll_fn(move |cx| rx.poll_recv(cx));
+        (tx, Self::new(events))
+    }
+
+    /// Sends a comment line whenever no event was written for `interval`,
+    /// so proxies do not close an idle connection.
+    pub fn keep_alive(mut self, interval: Duration) -> Self {
+        self.keep_alive = Some(interval);
+        self
+    }
+
+    pub fn into_body(self) -> ResponseBody {
+        let stream = KeepAlive {
+            events: self.events,
+            interval: self.keep_alive,
+            timer: None,
+        };
+        body::stream(stream.map(Ok::<_, Infallible>))
+    }
+
+    pub fn into_response(self) -> Response<ResponseBody> {
+        let mut response = Response::new(self.into_body());
+        *response.status_mut() = StatusCode::OK;
+        let headers = response.headers_mut();
+        headers.insert(CONTENT_TYPE, CONTENT_TYPE_EVENT_STREAM.parse().unwrap());
+        headers.insert(CACHE_CONTROL, "no-cache".parse().unwrap());
+        response
+    }
+}
+
+struct KeepAlive {
+    events: Pin<Box<dyn Stream<Item = Event> + Send>>,
+    interval: Option<Duration>,
+    // Created on first poll, which is when the runtime is known to exist
+    timer: Option<Pin<Box<Sleep>>>,
+}
+
+impl Stream for KeepAlive {
+    type Item = Bytes;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Bytes>> {
+        let Some(interval) = self.interval else {
+            return self
+                .events
+                .poll_next_unpin(cx)
+                .map(|e| e.map(|e| e.to_bytes()));
+        };
+        let timer = self
+            .timer
+            .get_or_insert_with(|| Box::pin(tokio::time::sleep(interval)));
+        if timer.as_mut().poll(cx).is_ready() {
+            timer.as_mut().reset(Instant::now() + interval);
+            return Poll::Ready(Some(Bytes::from_static(KEEP_ALIVE)));
+        }
+
+        let event = self.events.poll_next_unpin(cx);
+        if let Poll::Ready(Some(_)) = event {
+            if let Some(timer) = &mut self.timer {
+                timer.as_mut().reset(Instant::now() + interval);
+            }
+        }
+        event.map(|e| e.map(|e| e.to_bytes()))
+    }
+}
+
+/// Incremental `text/event-stream` parser.
+///
+/// Feed it body chunks as they arrive; chunk boundaries may fall anywhere,
+/// including inside a UTF-8 sequence or between `\r` and `\n`. Comments are
+/// skipped and an event without a terminating blank line at the end of the
+/// stream is discarded, as the spec requires.
+///
+/// Lines longer than [`DEFAULT_MAX_LINE`] bytes fail with
+/// [`DecodeError::LineTooLong`], so a peer that never sends a newline can't
+/// exhaust memory.
+#[derive(Debug)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+    max_line: usize,
+    pending: Event,
+    has_data: bool,
+    last_event_id: Option<String>,
+    skip_lf: bool,
+    started: bool,
+}
+
+/// Longest line [`Decoder`] accepts unless told otherwise.
+pub const DEFAULT_MAX_LINE: usize = 1024 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeError {
+    #[error("event stream is not valid UTF-8")]
+    InvalidUtf8,
+    #[error("event stream line is longer than {0} bytes")]
+    LineTooLong(usize),
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self {
+            buffer: Vec::new(),
+            max_line: DEFAULT_MAX_LINE,
+            pending: Event::default(),
+            has_data: false,
+            last_event_id: None,
+            skip_lf: false,
+            started: false,
+        }
+    }
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the [`DEFAULT_MAX_LINE`] limit.
+    pub fn max_line(mut self, bytes: usize) -> Self {
+        self.max_line = bytes;
+        self
+    }
+
+    /// The `id` of the most recent event, carried over to later events that
+    /// do not set their own.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    /// Parses `chunk` and returns every event it completed.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<Event>, DecodeError> {
+        let mut events = Vec::new();
+        for &byte in chunk {
+            if self.skip_lf {
+                self.skip_lf = false;
+                if byte == b'\n' {
+                    continue;
+                }
+            }
+            match byte {
+                b'\r' | b'\n' => {
+                    self.skip_lf = byte == b'\r';
+                    let line = std::mem::take(&mut self.buffer);
+                    if let Some(event) = self.line(&line)? {
+                        events.push(event);
+                    }
+                }
+                _ if self.buffer.len() >= self.max_line => {
+                    return Err(DecodeError::LineTooLong(self.max_line))
+                }
+                _ => self.buffer.push(byte),
+            }
+        }
+        Ok(events)
+    }
+
+    fn line(&mut self, line: &[u8]) -> Result<Option<Event>, DecodeError> {
+        let mut line = std::str::from_utf8(line).map_err(|_| DecodeError::InvalidUtf8)?;
+        if !self.started {
+            self.started = true;
+            line = line.strip_prefix('\u{feff}').unwrap_or(line);
+        }
+
+        if line.is_empty() {
+            return Ok(self.dispatch());
+        }
+        if line.starts_with(':') {
+            return Ok(None);
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.pending.event = Some(value.to_string()),
+            "data" => {
+                if self.has_data {
+                    self.pending.data.push('\n');
+                }
+                self.pending.data.push_str(value);
+                self.has_data = true;
+            }
+            "id" if !value.contains('\0') => self.pending.id = Some(value.to_string()),
+            "retry" => {
+                if let Ok(millis) = value.parse() {
+                    self.pending.retry = Some(Duration::from_millis(millis));
+                }
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn dispatch(&mut self) -> Option<Event> {
+        let mut event = std::mem::take(&mut self.pending);
+        if event.id.is_some() {
+            self.last_event_id = event.id.clone();
+        }
+        if !std::mem::take(&mut self.has_data) {
+            return None;
+        }
+        if event.id.is_none() {
+            event.id = self.last_event_id.clone();
+        }
+        Some(event)
+    }
+}
+
+/// Decodes a streaming body into events.
+pub fn decode<S, E>(chunks: S) -> impl Stream<Item = Result<Event, BoxError>>
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<BoxError> + 'static,
+{
+    let chunks = Box::pin(chunks);
+    futures_util::stream::unfold(
+        (chunks, Decoder::new(), std::collections::VecDeque::new()),
+        |(mut chunks, mut decoder, mut ready)| async move {
+            loop {
+                if let Some(event) = ready.pop_front() {
+                    return Some((Ok(event), (chunks, decoder, ready)));
+                }
+                let chunk = match chunks.next().await? {
+                    Ok(chunk) => chunk,
+                    Err(e) => return Some((Err(e.into()), (chunks, decoder, ready))),
+                };
+                match decoder.push(&chunk) {
+                    Ok(events) => ready.extend(events),
+                    Err(e) => return Some((Err(e.into()), (chunks, decoder, ready))),
+                }
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+
+    #[test]
+    fn test_event_encoding() {
+        let event = Event::new("line one\nline two")
+            .event("update")
+            .id("7")
+            .retry(Duration::from_secs(3));
+        assert_eq!(
+            event.to_bytes(),
+            Bytes::from("event: update\nid: 7\nretry: 3000\ndata: line one\ndata: line two\n\n")
+        );
+
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.push(&event.to_bytes()).unwrap(), vec![event]);
+    }
+
+    #[test]
+    fn test_decoder_split_chunks() {
+        let stream = "\u{feff}: comment\r\nevent: message_start\r\ndata: {\"a\":1}\r\n\r\n\
+                      data: first\ndata: second\n\nid\ndata:no-space\n\ndata: dangling";
+        let mut decoder = Decoder::new();
+        let mut events = Vec::new();
+        // Byte at a time covers every possible split, including `\r|\n`
+        for byte in stream.as_bytes() {
+            events.extend(decoder.push(std::slice::from_ref(byte)).unwrap());
+        }
+
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].event.as_deref(), Some("message_start"));
+        assert_eq!(events[0].data, r#"{"a":1}"#);
+        assert_eq!(events[1].data, "first\nsecond");
+        assert_eq!(events[2].id.as_deref(), Some(""));
+        assert_eq!(events[2].data, "no-space");
+    }
+
+    #[test]
+    fn test_decoder_line_limit() {
+        let mut decoder = Decoder::new().max_line(8);
+        assert!(
+            decoder
+                .push(
+                    b"data: 12
+
+"
+                )
+                .unwrap()
+                .len()
+                == 1
+        );
+        assert!(matches!(
+            decoder.push(b"data: 123"),
+            Err(DecodeError::LineTooLong(8))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_sse_keep_alive() {
+        let (tx, sse) = Sse::channel();
+        let response = sse.keep_alive(Duration::from_millis(20)).into_response();
+        assert_eq!(response.headers()[CACHE_CONTROL], "no-cache");
+
+        tokio::spawn(async move {
+            tx.send(Event::new("one")).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(70)).await;
+            tx.send(Event::new("two")).await.unwrap();
+        });
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(
+            text.starts_with("data: one\n\n: keep-alive\n\n"),
+            "{}",
+            text
+        );
+        assert!(text.ends_with("data: two\n\n"), "{}", text);
+
+        let events: Vec<_> = decode(futures_util::stream::iter([Ok::<_, Infallible>(body)]))
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(events, vec![Event::new("one"), Event::new("two")]);
+    }
+}
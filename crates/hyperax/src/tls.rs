@@ -0,0 +1,48 @@
+//! TLS configuration shared by the client transport and the server.
+
+pub use rustls;
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use std::io;
+use std::sync::Arc;
+
+pub(crate) const ALPN_H2: &[u8] = b"h2";
+pub(crate) const ALPN_HTTP1: &[u8] = b"http/1.1";
+
+/// Client configuration trusting the Mozilla root store.
+pub fn client_config() -> ClientConfig {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default protocol versions")
+        .with_root_certificates(roots)
+        .with_no_client_auth()
+}
+
+/// Offers `h2` ahead of `http/1.1` unless HTTP/2 is disabled. Protocols the
+/// caller configured explicitly are left alone.
+pub(crate) fn with_client_alpn(mut config: ClientConfig, http2: bool) -> Arc<ClientConfig> {
+    if config.alpn_protocols.is_empty() {
+        if http2 {
+            config.alpn_protocols.push(ALPN_H2.to_vec());
+        }
+        config.alpn_protocols.push(ALPN_HTTP1.to_vec());
+    }
+    Arc::new(config)
+}
+
+pub(crate) fn with_server_alpn(mut config: ServerConfig) -> Arc<ServerConfig> {
+    if config.alpn_protocols.is_empty() {
+        config.alpn_protocols = vec![ALPN_H2.to_vec(), ALPN_HTTP1.to_vec()];
+    }
+    Arc::new(config)
+}
+
+pub(crate) fn server_name(host: &str) -> io::Result<ServerName<'static>> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    ServerName::try_from(host.to_string())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
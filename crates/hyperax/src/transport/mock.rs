@@ -0,0 +1,172 @@
+use super::{ResponseBody, Transport, TransportFuture};
+use crate::body::RequestBody;
+use crate::client::Error;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::{Method, Request, Response};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// In-memory transport that answers requests from a script.
+///
+/// Requests are matched against expectations by method, path and optionally
+/// body, in the order the expectations were registered. Each scripted
+/// response is returned once; an expectation with no responses left no
+/// longer matches.
+///
+/// ```
+/// # use hyperax::transport::MockTransport;
+/// # use hyperax::{Client, Method};
+/// let mock = MockTransport::new();
+/// mock.expect(Method::GET, "/v1/models").respond(200, r#"{"data":[]}"#);
+/// let client = Client::builder().transport(mock.clone()).build();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct MockTransport {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    expectations: Vec<ExpectationState>,
+    requests: Vec<Request<Bytes>>,
+}
+
+#[derive(Debug)]
+struct ExpectationState {
+    method: Method,
+    path: String,
+    body: Option<Bytes>,
+    responses: VecDeque<Response<Bytes>>,
+}
+
+impl ExpectationState {
+    fn matches(&self, req: &Request<Bytes>) -> bool {
+        !self.responses.is_empty()
+            && self.method == req.method()
+            && self.path == req.uri().path()
+            && self.body.as_ref().is_none_or(|body| body == req.body())
+    }
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers an expectation for `method` and `path`. Add responses to it
+    /// with [`Expectation::respond`].
+    pub fn expect(&self, method: Method, path: impl Into<String>) -> Expectation {
+        let mut state = self.state.lock().unwrap();
+        state.expectations.push(ExpectationState {
+            method,
+            path: path.into(),
+            body: None,
+            responses: VecDeque::new(),
+        });
+        Expectation {
+            state: self.state.clone(),
+            index: state.expectations.len() - 1,
+        }
+    }
+
+    /// Every request received so far, with its body buffered.
+    pub fn requests(&self) -> Vec<Request<Bytes>> {
+        self.state
+            .lock()
+            .unwrap()
+            .requests
+            .iter()
+            .map(clone_request)
+            .collect()
+    }
+
+    /// Panics if any scripted response was never used.
+    pub fn assert_done(&self) {
+        let state = self.state.lock().unwrap();
+        for expectation in &state.expectations {
+            assert!(
+                expectation.responses.is_empty(),
+                "{} unused response(s) for {} {}",
+                expectation.responses.len(),
+                expectation.method,
+                expectation.path
+            );
+        }
+    }
+}
+
+impl Transport for MockTransport {
+    fn send(&self, req: Request<RequestBody>) -> TransportFuture<'_> {
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body = body.collect().await.unwrap_or_default().to_bytes();
+            let req = Request::from_parts(parts, body);
+
+            let mut state = self.state.lock().unwrap();
+            let response = state
+                .expectations
+                .iter_mut()
+                .find(|expectation| expectation.matches(&req))
+                .and_then(|expectation| expectation.responses.pop_front());
+            let response = response.ok_or_else(|| {
+                Error::Mock(format!(
+                    "No mock response for {} {}",
+                    req.method(),
+                    req.uri().path()
+                ))
+            });
+            state.requests.push(req);
+
+            Ok(response?.map(boxed))
+        })
+    }
+}
+
+/// Handle for adding constraints and responses to a registered expectation.
+pub struct Expectation {
+    state: Arc<Mutex<State>>,
+    index: usize,
+}
+
+impl Expectation {
+    /// Only match requests whose body equals `body`.
+    pub fn body(self, body: impl Into<Bytes>) -> Self {
+        self.state.lock().unwrap().expectations[self.index].body = Some(body.into());
+        self
+    }
+
+    /// Queues a response with `status` and `body`.
+    pub fn respond(self, status: u16, body: impl Into<Bytes>) -> Self {
+        let response = Response::builder()
+            .status(status)
+            .body(body.into())
+            .expect("invalid mock status");
+        self.respond_with(response)
+    }
+
+    /// Queues a fully built response, for scripting headers.
+    pub fn respond_with(self, response: Response<Bytes>) -> Self {
+        self.state.lock().unwrap().expectations[self.index]
+            .responses
+            .push_back(response);
+        self
+    }
+}
+
+fn boxed(body: Bytes) -> ResponseBody {
+    Full::new(body)
+        .map_err(|never| match never {})
+        .boxed_unsync()
+}
+
+fn clone_request(req: &Request<Bytes>) -> Request<Bytes> {
+    let mut clone = Request::builder()
+        .method(req.method().clone())
+        .uri(req.uri().clone())
+        .version(req.version())
+        .body(req.body().clone())
+        .unwrap();
+    *clone.headers_mut() = req.headers().clone();
+    clone
+}
@@ -0,0 +1,472 @@
+#[cfg(any(test, feature = "test-util"))]
+mod mock;
+mod proxy;
+
+#[cfg(any(test, feature = "test-util"))]
+pub use mock::{Expectation, MockTransport};
+pub use proxy::Proxy;
+
+use crate::body::RequestBody;
+use crate::client::Error;
+use crate::tls;
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::BodyExt;
+use hyper::body::Bytes;
+use hyper::client::conn::http2;
+use hyper::header::{HeaderValue, HOST, UPGRADE};
+use hyper::{Request, Response};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use proxy::ProxyServer;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex as AsyncMutex;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+/// Response body handed back by a [`Transport`].
+pub type ResponseBody = UnsyncBoxBody<Bytes, Error>;
+
+pub type TransportFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Response<ResponseBody>, Error>> + Send + 'a>>;
+
+/// Sends a fully prepared request and returns the response head as soon as
+/// it arrives. The body is streamed.
+///
+/// `Client` takes care of base urls, default headers and retries, so a
+/// transport only has to move bytes.
+pub trait Transport: fmt::Debug + Send + Sync + 'static {
+    fn send(&self, req: Request<RequestBody>) -> TransportFuture<'_>;
+}
+
+/// Which HTTP versions [`HttpTransport`] may speak.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HttpVersion {
+    /// HTTP/2 when a TLS server selects it through ALPN, HTTP/1.1 otherwise.
+    #[default]
+    Auto,
+    Http1Only,
+    /// HTTP/2 from the first byte, over TLS and plain TCP (h2c) alike. Only
+    /// for servers known to support it, such as a local `hyperax::Server`.
+    Http2PriorKnowledge,
+}
+
+type H2Sender = http2::SendRequest<RequestBody>;
+
+/// Live HTTP/2 connection for an origin, if any.
+type H2Slot = Arc<AsyncMutex<Option<H2Sender>>>;
+
+/// HTTP/1.1 and HTTP/2 over TCP, with TLS for `https` URLs.
+///
+/// HTTP/1.1 opens a connection per request. HTTP/2 connections are kept and
+/// shared, so concurrent requests to one origin are multiplexed as streams on
+/// a single connection.
+#[derive(Clone, Debug)]
+pub struct HttpTransport {
+    connector: HttpConnector,
+    version: HttpVersion,
+    /// TLS settings as configured, before ALPN is derived from `version`.
+    tls_base: Arc<rustls::ClientConfig>,
+    tls: Arc<rustls::ClientConfig>,
+    /// `tls` without h2 in ALPN, for connections that must stay HTTP/1.1.
+    tls_http1: Arc<rustls::ClientConfig>,
+    proxy: Option<Proxy>,
+    h2_pool: Arc<Mutex<HashMap<Origin, H2Slot>>>,
+}
+
+impl HttpTransport {
+    pub fn new() -> Self {
+        let base = Arc::new(tls::client_config());
+        let (tls, tls_http1) = Self::derive_tls(&base, HttpVersion::Auto);
+        Self {
+            connector: HttpConnector::new(),
+            version: HttpVersion::Auto,
+            tls_base: base,
+            tls,
+            tls_http1,
+            proxy: Proxy::from_env(),
+            h2_pool: Arc::default(),
+        }
+    }
+
+    /// How long opening a TCP connection may take. Defaults to 60 seconds.
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connector.timeout = timeout;
+        self
+    }
+
+    pub fn version(mut self, version: HttpVersion) -> Self {
+        self.version = version;
+        (self.tls, self.tls_http1) = Self::derive_tls(&self.tls_base, version);
+        self
+    }
+
+    /// Replaces the default TLS settings, e.g. to trust a private CA. ALPN is
+    /// filled in unless `config` already lists protocols.
+    pub fn tls_config(mut self, config: rustls::ClientConfig) -> Self {
+        self.tls_base = Arc::new(config);
+        (self.tls, self.tls_http1) = Self::derive_tls(&self.tls_base, self.version);
+        self
+    }
+
+    /// The configs for `version` and for HTTP/1.1-only connections, both
+    /// from `base`. Protocols listed in `base` are kept, minus h2 where
+    /// HTTP/2 is off.
+    fn derive_tls(
+        base: &rustls::ClientConfig,
+        version: HttpVersion,
+    ) -> (Arc<rustls::ClientConfig>, Arc<rustls::ClientConfig>) {
+        let mut http1 = base.clone();
+        let explicit = !http1.alpn_protocols.is_empty();
+        http1.alpn_protocols.retain(|p| p != tls::ALPN_H2);
+        if explicit && http1.alpn_protocols.is_empty() {
+            http1.alpn_protocols.push(tls::ALPN_HTTP1.to_vec());
+        }
+        let http1 = tls::with_client_alpn(http1, false);
+        let tls = match version {
+            HttpVersion::Http1Only => http1.clone(),
+            _ => tls::with_client_alpn(base.clone(), true),
+        };
+        (tls, http1)
+    }
+
+    /// Routes requests through `proxy` instead of the environment's proxy
+    /// settings.
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Connects directly, ignoring `HTTP_PROXY` and friends.
+    pub fn no_proxy(mut self) -> Self {
+        self.proxy = None;
+        self
+    }
+
+    /// A TCP stream to `origin`, tunneled through `proxy` if there is one.
+    async fn open(&self, origin: &Origin, proxy: Option<&ProxyServer>) -> Result<TcpStream, Error> {
+        match proxy {
+            Some(proxy) => {
+                let mut stream = self.connector.connect(&proxy.origin).await?;
+                proxy.tunnel(&mut stream, origin).await?;
+                Ok(stream)
+            }
+            None => Ok(self.connector.connect(origin).await?),
+        }
+    }
+
+    async fn send_h2(
+        &self,
+        sender: &mut H2Sender,
+        req: Request<RequestBody>,
+    ) -> Result<Response<ResponseBody>, Error> {
+        let resp = sender.send_request(req).await?;
+        Ok(resp.map(|body| body.map_err(Error::from).boxed_unsync()))
+    }
+
+    async fn tls_connect(
+        &self,
+        stream: TcpStream,
+        origin: &Origin,
+        http1: bool,
+    ) -> Result<TlsStream<TcpStream>, Error> {
+        let name = tls::server_name(&origin.host)?;
+        let config = match http1 {
+            true => self.tls_http1.clone(),
+            false => self.tls.clone(),
+        };
+        let connector = TlsConnector::from(config);
+        Ok(connector.connect(name, stream).await?)
+    }
+
+    fn h2_slot(&self, origin: &Origin) -> H2Slot {
+        let mut pool = self.h2_pool.lock().unwrap_or_else(|e| e.into_inner());
+        pool.entry(origin.clone()).or_default().clone()
+    }
+}
+
+impl Default for HttpTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport for HttpTransport {
+    fn send(&self, mut req: Request<RequestBody>) -> TransportFuture<'_> {
+        Box::pin(async move {
+            let origin = Origin::of(req.uri())?;
+            let proxy = self.proxy.as_ref().and_then(|p| p.server_for(&origin));
+            if let Some(proxy) = proxy.filter(|_| !origin.tls) {
+                // Plain HTTP goes to the proxy with the full URL in the
+                // request line
+                let stream = self.connector.connect(&proxy.origin).await?;
+                proxy.authorize(&mut req);
+                set_host(&mut req);
+                return send_http1(stream, req).await;
+            }
+
+            // Upgrades such as WebSocket only exist in HTTP/1.1
+            let upgrade = req.headers().contains_key(UPGRADE);
+            let may_h2 = match self.version {
+                _ if upgrade => false,
+                HttpVersion::Auto => origin.tls,
+                HttpVersion::Http1Only => false,
+                HttpVersion::Http2PriorKnowledge => true,
+            };
+            if !may_h2 {
+                let stream = self.open(&origin, proxy).await?;
+                origin_form(&mut req);
+                return match origin.tls {
+                    true => send_http1(self.tls_connect(stream, &origin, true).await?, req).await,
+                    false => send_http1(stream, req).await,
+                };
+            }
+
+            // Hold the slot while connecting so concurrent requests wait for
+            // this connection instead of racing to open their own
+            let slot = self.h2_slot(&origin);
+            let mut pooled = slot.lock().await;
+            if let Some(sender) = pooled.as_ref().filter(|s| !s.is_closed()) {
+                let mut sender = sender.clone();
+                drop(pooled);
+                return self.send_h2(&mut sender, req).await;
+            }
+
+            let stream = self.open(&origin, proxy).await?;
+            let mut sender = if origin.tls {
+                let stream = self.tls_connect(stream, &origin, false).await?;
+                let alpn_h2 = stream.get_ref().1.alpn_protocol() == Some(tls::ALPN_H2);
+                if !alpn_h2 && self.version == HttpVersion::Auto {
+                    // The server only speaks HTTP/1.1
+                    drop(pooled);
+                    origin_form(&mut req);
+                    return send_http1(stream, req).await;
+                }
+                handshake_h2(stream).await?
+            } else {
+                handshake_h2(stream).await?
+            };
+            *pooled = Some(sender.clone());
+            drop(pooled);
+
+            // HTTP/2 carries the authority in the request URI, not `Host`
+            req.headers_mut().remove(HOST);
+            self.send_h2(&mut sender, req).await
+        })
+    }
+}
+
+async fn send_http1<I>(io: I, req: Request<RequestBody>) -> Result<Response<ResponseBody>, Error>
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(io)).await?;
+    tokio::task::spawn(async move {
+        if let Err(err) = conn.with_upgrades().await {
+            eprintln!("Connection failed: {:?}", err);
+        }
+    });
+
+    let resp = sender.send_request(req).await?;
+    Ok(resp.map(|body| body.map_err(Error::from).boxed_unsync()))
+}
+
+async fn handshake_h2<I>(io: I) -> Result<H2Sender, Error>
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (sender, conn) = http2::handshake(TokioExecutor::new(), TokioIo::new(io)).await?;
+    tokio::task::spawn(async move {
+        if let Err(err) = conn.await {
+            eprintln!("Connection failed: {:?}", err);
+        }
+    });
+    Ok(sender)
+}
+
+/// HTTP/1.1 servers expect the path in the request line and the authority in
+/// the `Host` header.
+fn origin_form<B>(req: &mut Request<B>) {
+    set_host(req);
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/");
+    if let Ok(uri) = path.parse() {
+        *req.uri_mut() = uri;
+    }
+}
+
+fn set_host<B>(req: &mut Request<B>) {
+    if let Some(authority) = req.uri().authority().cloned() {
+        if !req.headers().contains_key(HOST) {
+            if let Ok(host) = HeaderValue::from_str(authority.as_str()) {
+                req.headers_mut().insert(HOST, host);
+            }
+        }
+    }
+}
+
+/// Scheme, host and port: what a connection can be reused for.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct Origin {
+    tls: bool,
+    host: String,
+    port: u16,
+}
+
+impl Origin {
+    fn of(uri: &hyper::Uri) -> std::io::Result<Self> {
+        let invalid =
+            |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidInput, msg.to_string());
+        let tls = match uri.scheme_str() {
+            Some("https") => true,
+            Some("http") | None => false,
+            Some(_) => return Err(invalid("unsupported uri scheme")),
+        };
+        let host = uri
+            .host()
+            .ok_or_else(|| invalid("invalid uri"))?
+            .to_string();
+        let port = uri.port_u16().unwrap_or(if tls { 443 } else { 80 });
+        Ok(Self { tls, host, port })
+    }
+
+    fn authority(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct HttpConnector {
+    timeout: Option<Duration>,
+}
+
+impl HttpConnector {
+    fn new() -> Self {
+        Self {
+            timeout: Some(Duration::from_secs(60)),
+        }
+    }
+
+    async fn connect(&self, origin: &Origin) -> std::io::Result<TcpStream> {
+        let host = origin.host.trim_start_matches('[').trim_end_matches(']');
+        let connect = TcpStream::connect((host, origin.port));
+        let stream = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, connect).await.map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::TimedOut, "connect timed out")
+            })??,
+            None => connect.await?,
+        };
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::Full;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_version_keeps_explicit_alpn() {
+        let mut config = tls::client_config();
+        config.alpn_protocols = vec![b"h2".to_vec(), b"custom".to_vec()];
+        let transport = HttpTransport::new()
+            .tls_config(config)
+            .version(HttpVersion::Http1Only);
+        assert_eq!(transport.tls.alpn_protocols, [b"custom".to_vec()]);
+        assert_eq!(transport.tls_http1.alpn_protocols, [b"custom".to_vec()]);
+
+        let transport = transport.version(HttpVersion::Auto);
+        assert_eq!(
+            transport.tls.alpn_protocols,
+            [b"h2".to_vec(), b"custom".to_vec()]
+        );
+
+        let transport = HttpTransport::new().version(HttpVersion::Http1Only);
+        assert_eq!(transport.tls.alpn_protocols, [b"http/1.1".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn test_http_transport_roundtrip() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 1024];
+            let n = stream.read(&mut buf).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 201 Created\r\ncontent-length: 5\r\n\r\nhello")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&buf[..n]).into_owned()
+        });
+
+        let req = Request::builder()
+            .method("POST")
+            .uri(format!("http://{}/items", addr))
+            .body(crate::body::full("payload"))
+            .unwrap();
+        let resp = HttpTransport::new().send(req).await.unwrap();
+        assert_eq!(resp.status(), 201);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, Bytes::from("hello"));
+
+        let raw = server.await.unwrap();
+        assert!(raw.starts_with("POST /items HTTP/1.1\r\n"));
+        assert!(raw.ends_with("payload"));
+    }
+
+    #[tokio::test]
+    async fn test_http2_multiplexes_one_connection() {
+        use hyper::server::conn::http2 as server_http2;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                let service = hyper::service::service_fn(|req: Request<_>| async move {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    let version = format!("{:?}", req.version());
+                    Ok::<_, std::convert::Infallible>(Response::new(Full::new(Bytes::from(
+                        version,
+                    ))))
+                });
+                tokio::spawn(
+                    server_http2::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(stream), service),
+                );
+            }
+        });
+
+        let transport = HttpTransport::new().version(HttpVersion::Http2PriorKnowledge);
+        let requests = (0..4).map(|_| {
+            let req = Request::get(format!("http://{}/", addr))
+                .body(crate::body::empty())
+                .unwrap();
+            transport.send(req)
+        });
+        for resp in futures_util::future::join_all(requests).await {
+            let resp = resp.unwrap();
+            assert_eq!(resp.version(), hyper::Version::HTTP_2);
+            let body = resp.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, Bytes::from("HTTP/2.0"));
+        }
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    }
+}
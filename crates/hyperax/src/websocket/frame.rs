@@ -0,0 +1,231 @@
+use super::WebSocketError;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Frame opcodes from RFC 6455 section 5.2.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OpCode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl OpCode {
+    fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            0x0 => Some(OpCode::Continuation),
+            0x1 => Some(OpCode::Text),
+            0x2 => Some(OpCode::Binary),
+            0x8 => Some(OpCode::Close),
+            0x9 => Some(OpCode::Ping),
+            0xA => Some(OpCode::Pong),
+            _ => None,
+        }
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            OpCode::Continuation => 0x0,
+            OpCode::Text => 0x1,
+            OpCode::Binary => 0x2,
+            OpCode::Close => 0x8,
+            OpCode::Ping => 0x9,
+            OpCode::Pong => 0xA,
+        }
+    }
+
+    pub(crate) fn is_control(self) -> bool {
+        matches!(self, OpCode::Close | OpCode::Ping | OpCode::Pong)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Frame {
+    pub(crate) fin: bool,
+    pub(crate) opcode: OpCode,
+    pub(crate) payload: Bytes,
+}
+
+impl Frame {
+    pub(crate) fn new(opcode: OpCode, payload: impl Into<Bytes>) -> Self {
+        Self {
+            fin: true,
+            opcode,
+            payload: payload.into(),
+        }
+    }
+}
+
+/// Which end of the connection we are. Clients mask every frame they send
+/// and servers must not.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+/// Splits the byte stream into frames and writes frames back out.
+#[derive(Debug)]
+pub(crate) struct Codec {
+    pub(crate) role: Role,
+    pub(crate) max_frame_size: usize,
+}
+
+impl Decoder for Codec {
+    type Item = Frame;
+    type Error = WebSocketError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, WebSocketError> {
+        if src.len() < 2 {
+            return Ok(None);
+        }
+        let (first, second) = (src[0], src[1]);
+        if first & 0x70 != 0 {
+            return Err(WebSocketError::Protocol("reserved bits set"));
+        }
+        let fin = first & 0x80 != 0;
+        let opcode =
+            OpCode::from_u8(first & 0x0F).ok_or(WebSocketError::Protocol("unknown opcode"))?;
+        let masked = second & 0x80 != 0;
+        if masked != (self.role == Role::Server) {
+            return Err(WebSocketError::Protocol(match self.role {
+                Role::Server => "client frame not masked",
+                Role::Client => "server frame masked",
+            }));
+        }
+
+        let (len, mut header) = match second & 0x7F {
+            126 if src.len() >= 4 => (u16::from_be_bytes([src[2], src[3]]) as u64, 4),
+            127 if src.len() >= 10 => {
+                let mut len = [0u8; 8];
+                len.copy_from_slice(&src[2..10]);
+                (u64::from_be_bytes(len), 10)
+            }
+            126 | 127 => return Ok(None),
+            len => (len as u64, 2),
+        };
+        if opcode.is_control() && (!fin || len > 125) {
+            return Err(WebSocketError::Protocol("invalid control frame"));
+        }
+        if len > self.max_frame_size as u64 {
+            return Err(WebSocketError::TooLarge);
+        }
+        let len = len as usize;
+        let mask_len = if masked { 4 } else { 0 };
+        if src.len() < header + mask_len + len {
+            src.reserve(header + mask_len + len - src.len());
+            return Ok(None);
+        }
+
+        let mut mask = [0u8; 4];
+        if masked {
+            mask.copy_from_slice(&src[header..header + 4]);
+            header += 4;
+        }
+        src.advance(header);
+        let mut payload = src.split_to(len);
+        if masked {
+            apply_mask(&mut payload, mask);
+        }
+        Ok(Some(Frame {
+            fin,
+            opcode,
+            payload: payload.freeze(),
+        }))
+    }
+}
+
+impl Encoder<Frame> for Codec {
+    type Error = WebSocketError;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> Result<(), WebSocketError> {
+        let len = frame.payload.len();
+        dst.reserve(14 + len);
+        dst.put_u8((frame.fin as u8) << 7 | frame.opcode.as_u8());
+        let mask_bit = if self.role == Role::Client { 0x80 } else { 0 };
+        match len {
+            0..=125 => dst.put_u8(mask_bit | len as u8),
+            126..=0xFFFF => {
+                dst.put_u8(mask_bit | 126);
+                dst.put_u16(len as u16);
+            }
+            _ => {
+                dst.put_u8(mask_bit | 127);
+                dst.put_u64(len as u64);
+            }
+        }
+        match self.role {
+            Role::Client => {
+                let mask = crate::random::bytes::<4>();
+                dst.put_slice(&mask);
+                let start = dst.len();
+                dst.put_slice(&frame.payload);
+                apply_mask(&mut dst[start..], mask);
+            }
+            Role::Server => dst.put_slice(&frame.payload),
+        }
+        Ok(())
+    }
+}
+
+fn apply_mask(data: &mut [u8], mask: [u8; 4]) {
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codec(role: Role) -> Codec {
+        Codec {
+            role,
+            max_frame_size: 1 << 20,
+        }
+    }
+
+    #[test]
+    fn test_frame_roundtrip() {
+        for len in [0, 125, 126, 0xFFFF, 0x10000] {
+            let frame = Frame {
+                fin: len != 126,
+                opcode: OpCode::Binary,
+                payload: Bytes::from(vec![7u8; len]),
+            };
+            // Client frames are masked on the wire and unmasked by the server
+            let mut wire = BytesMut::new();
+            codec(Role::Client)
+                .encode(frame.clone(), &mut wire)
+                .unwrap();
+            let mut partial = wire.split_to(wire.len() / 2);
+            let mut server = codec(Role::Server);
+            assert_eq!(server.decode(&mut partial).unwrap(), None);
+            partial.unsplit(wire);
+            assert_eq!(server.decode(&mut partial).unwrap(), Some(frame));
+            assert!(partial.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_frame_violations() {
+        // Unmasked frame sent to a server
+        let mut wire = BytesMut::new();
+        codec(Role::Server)
+            .encode(Frame::new(OpCode::Text, "hi"), &mut wire)
+            .unwrap();
+        assert!(codec(Role::Server).decode(&mut wire).is_err());
+
+        // Fragmented ping, oversized frame
+        let mut wire = BytesMut::from(&[0x09u8, 0x00][..]);
+        assert!(codec(Role::Client).decode(&mut wire).is_err());
+        let mut wire = BytesMut::from(&[0x82u8, 127, 0, 0, 0, 1, 0, 0, 0, 0][..]);
+        assert!(matches!(
+            codec(Role::Client).decode(&mut wire),
+            Err(WebSocketError::TooLarge)
+        ));
+    }
+}
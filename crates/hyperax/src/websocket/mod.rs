@@ -0,0 +1,603 @@
+//! WebSocket connections (RFC 6455) over an HTTP/1.1 upgrade.
+//!
+//! Servers answer the handshake with [`accept`], clients open connections
+//! with [`Client::websocket`](crate::Client::websocket). Either way the
+//! result is a [`WebSocket`], which is a `Stream` of incoming messages and a
+//! `Sink` for outgoing ones. Pings are answered automatically and fragmented
+//! messages are reassembled before they are yielded.
+//!
+//! ```no_run
+//! use futures_util::{SinkExt, StreamExt};
+//! use hyperax::websocket::{self, Message};
+//! use hyperax::{RequestBody, Request};
+//!
+//! async fn echo(req: Request<RequestBody>) -> Result<hyperax::Response<hyperax::ResponseBody>, std::convert::Infallible> {
+//!     Ok(websocket::accept(req, |mut ws| async move {
+//!         while let Some(Ok(message)) = ws.next().await {
+//!             if let Message::Text(_) | Message::Binary(_) = message {
+//!                 let _ = ws.send(message).await;
+//!             }
+//!         }
+//!     }))
+//! }
+//! ```
+
+mod frame;
+
+pub use frame::Role;
+
+use crate::body::{RequestBody, ResponseBody};
+use crate::random;
+use crate::server::ServerResponse;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bytes::{Bytes, BytesMut};
+use frame::{Codec, Frame, OpCode};
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{ready, Sink, Stream, StreamExt};
+use hyper::header::{
+    HeaderMap, CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_VERSION, UPGRADE,
+};
+use hyper::upgrade::Upgraded;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::Framed;
+
+/// Appended to the client's key to derive `Sec-WebSocket-Accept`.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Close code for a normal closure.
+pub const CLOSE_NORMAL: u16 = 1000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebSocketError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("protocol error: {0}")]
+    Protocol(&'static str),
+    #[error("text message is not valid UTF-8")]
+    Utf8,
+    #[error("message too large")]
+    TooLarge,
+    #[error("connection closed")]
+    Closed,
+    #[error("handshake failed: {0}")]
+    Handshake(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Bytes),
+    Ping(Bytes),
+    Pong(Bytes),
+    /// The closing handshake. `None` when the peer sent no status code.
+    Close(Option<CloseFrame>),
+}
+
+impl Message {
+    pub fn text(text: impl Into<String>) -> Self {
+        Message::Text(text.into())
+    }
+
+    pub fn binary(data: impl Into<Bytes>) -> Self {
+        Message::Binary(data.into())
+    }
+
+    pub fn is_close(&self) -> bool {
+        matches!(self, Message::Close(_))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+impl CloseFrame {
+    pub fn new(code: u16, reason: impl Into<String>) -> Self {
+        Self {
+            code,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Limits and framing for a [`WebSocket`].
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    max_message_size: usize,
+    fragment_size: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_message_size: 64 << 20,
+            fragment_size: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Largest message accepted from the peer, after reassembly.
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// Splits outgoing text and binary messages into frames of at most
+    /// `size` bytes. Messages go out as single frames by default.
+    pub fn fragment_size(mut self, size: usize) -> Self {
+        self.fragment_size = Some(size.max(1));
+        self
+    }
+}
+
+/// An open WebSocket connection.
+pub struct WebSocket<S = TokioIo<Upgraded>> {
+    framed: Framed<S, Codec>,
+    config: Config,
+    /// Opcode and data of a fragmented message being reassembled.
+    partial: Option<(OpCode, BytesMut)>,
+    /// Pongs and the close echo, sent ahead of anything else.
+    replies: VecDeque<Frame>,
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> WebSocket<S> {
+    /// Wraps a connection whose handshake is already done.
+    pub fn from_raw(io: S, role: Role) -> Self {
+        let config = Config::default();
+        let codec = Codec {
+            role,
+            max_frame_size: config.max_message_size,
+        };
+        Self {
+            framed: Framed::new(io, codec),
+            config,
+            partial: None,
+            replies: VecDeque::new(),
+            close_sent: false,
+            close_received: false,
+        }
+    }
+
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.framed.codec_mut().max_frame_size = config.max_message_size;
+        self.config = config;
+        self
+    }
+
+    pub fn role(&self) -> Role {
+        self.framed.codec().role
+    }
+
+    /// Splits the connection into halves that can be used from different
+    /// tasks.
+    pub fn split(self) -> (SplitSink<Self, Message>, SplitStream<Self>) {
+        StreamExt::split(self)
+    }
+
+    /// Sends queued replies and flushes.
+    fn poll_replies(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), WebSocketError>> {
+        while !self.replies.is_empty() {
+            ready!(Pin::new(&mut self.framed).poll_ready(cx))?;
+            if let Some(frame) = self.replies.pop_front() {
+                Pin::new(&mut self.framed).start_send(frame)?;
+            }
+        }
+        Pin::new(&mut self.framed).poll_flush(cx)
+    }
+
+    fn on_frame(&mut self, frame: Frame) -> Result<Option<Message>, WebSocketError> {
+        match frame.opcode {
+            OpCode::Ping => {
+                self.replies
+                    .push_back(Frame::new(OpCode::Pong, frame.payload.clone()));
+                Ok(Some(Message::Ping(frame.payload)))
+            }
+            OpCode::Pong => Ok(Some(Message::Pong(frame.payload))),
+            OpCode::Close => {
+                let close = parse_close(frame.payload)?;
+                self.close_received = true;
+                if !self.close_sent {
+                    // Echo the status code to complete the handshake
+                    self.close_sent = true;
+                    let code = close.as_ref().map(|c| c.code);
+                    self.replies
+                        .push_back(Frame::new(OpCode::Close, close_payload(code, "")));
+                }
+                Ok(Some(Message::Close(close)))
+            }
+            OpCode::Text | OpCode::Binary => {
+                if self.partial.is_some() {
+                    return Err(WebSocketError::Protocol("expected a continuation frame"));
+                }
+                if frame.fin {
+                    return message(frame.opcode, frame.payload).map(Some);
+                }
+                self.partial = Some((frame.opcode, BytesMut::from(&frame.payload[..])));
+                Ok(None)
+            }
+            OpCode::Continuation => {
+                let (_, data) = self
+                    .partial
+                    .as_mut()
+                    .ok_or(WebSocketError::Protocol("unexpected continuation frame"))?;
+                if data.len() + frame.payload.len() > self.config.max_message_size {
+                    return Err(WebSocketError::TooLarge);
+                }
+                data.extend_from_slice(&frame.payload);
+                match (frame.fin, self.partial.take()) {
+                    (true, Some((opcode, data))) => message(opcode, data.freeze()).map(Some),
+                    (_, partial) => {
+                        self.partial = partial;
+                        Ok(None)
+                    }
+                }
+            }
+        }
+    }
+
+    fn frames(&self, message: Message) -> Result<Vec<Frame>, WebSocketError> {
+        let (opcode, payload) = match message {
+            Message::Text(text) => (OpCode::Text, Bytes::from(text)),
+            Message::Binary(data) => (OpCode::Binary, data),
+            Message::Ping(data) | Message::Pong(data) if data.len() > 125 => {
+                return Err(WebSocketError::Protocol("control frame payload too long"))
+            }
+            Message::Ping(data) => return Ok(vec![Frame::new(OpCode::Ping, data)]),
+            Message::Pong(data) => return Ok(vec![Frame::new(OpCode::Pong, data)]),
+            Message::Close(close) => {
+                let payload = match close {
+                    Some(close) => close_payload(Some(close.code), &close.reason),
+                    None => Bytes::new(),
+                };
+                return Ok(vec![Frame::new(OpCode::Close, payload)]);
+            }
+        };
+
+        let size = self.config.fragment_size.unwrap_or(usize::MAX);
+        if payload.len() <= size {
+            return Ok(vec![Frame::new(opcode, payload)]);
+        }
+        let count = payload.len().div_ceil(size);
+        Ok((0..count)
+            .map(|i| Frame {
+                fin: i + 1 == count,
+                opcode: if i == 0 { opcode } else { OpCode::Continuation },
+                payload: payload.slice(i * size..((i + 1) * size).min(payload.len())),
+            })
+            .collect())
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Stream for WebSocket<S> {
+    type Item = Result<Message, WebSocketError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if this.close_received {
+                // Finish sending the close echo before ending the stream
+                return match ready!(this.poll_replies(cx)) {
+                    Ok(()) => Poll::Ready(None),
+                    Err(e) => Poll::Ready(Some(Err(e))),
+                };
+            }
+            // Pongs go out as soon as possible; a slow writer must not
+            // stall reading
+            if let Poll::Ready(Err(e)) = this.poll_replies(cx) {
+                return Poll::Ready(Some(Err(e)));
+            }
+
+            let frame = match ready!(Pin::new(&mut this.framed).poll_next(cx)) {
+                Some(Ok(frame)) => frame,
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => return Poll::Ready(None),
+            };
+            match this.on_frame(frame) {
+                Ok(Some(message)) => return Poll::Ready(Some(Ok(message))),
+                Ok(None) => continue,
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Sink<Message> for WebSocket<S> {
+    type Error = WebSocketError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_replies(cx))?;
+        Pin::new(&mut this.framed).poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: Message) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        if this.close_sent {
+            return Err(WebSocketError::Closed);
+        }
+        this.close_sent = message.is_close();
+        for frame in this.frames(message)? {
+            Pin::new(&mut this.framed).start_send(frame)?;
+        }
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_replies(cx)
+    }
+
+    /// Starts the closing handshake if it has not begun and shuts down the
+    /// sending side. Keep reading to receive the peer's close.
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if !self.close_sent {
+            ready!(self.as_mut().poll_ready(cx))?;
+            let close = CloseFrame::new(CLOSE_NORMAL, "");
+            self.as_mut().start_send(Message::Close(Some(close)))?;
+        }
+        let this = self.get_mut();
+        ready!(this.poll_replies(cx))?;
+        Pin::new(&mut this.framed).poll_close(cx)
+    }
+}
+
+fn message(opcode: OpCode, data: Bytes) -> Result<Message, WebSocketError> {
+    match opcode {
+        OpCode::Text => String::from_utf8(data.into())
+            .map(Message::Text)
+            .map_err(|_| WebSocketError::Utf8),
+        _ => Ok(Message::Binary(data)),
+    }
+}
+
+fn parse_close(payload: Bytes) -> Result<Option<CloseFrame>, WebSocketError> {
+    match payload.len() {
+        0 => Ok(None),
+        1 => Err(WebSocketError::Protocol("truncated close frame")),
+        _ => {
+            let code = u16::from_be_bytes([payload[0], payload[1]]);
+            let reason =
+                String::from_utf8(payload[2..].to_vec()).map_err(|_| WebSocketError::Utf8)?;
+            Ok(Some(CloseFrame { code, reason }))
+        }
+    }
+}
+
+fn close_payload(code: Option<u16>, reason: &str) -> Bytes {
+    let Some(code) = code else {
+        return Bytes::new();
+    };
+    let mut payload = code.to_be_bytes().to_vec();
+    // Control frames carry at most 125 bytes
+    let mut end = reason.len().min(123);
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    payload.extend_from_slice(&reason.as_bytes()[..end]);
+    payload.into()
+}
+
+/// A fresh `Sec-WebSocket-Key`.
+pub(crate) fn generate_key() -> String {
+    BASE64.encode(random::bytes::<16>())
+}
+
+/// `Sec-WebSocket-Accept` for a client's key.
+pub(crate) fn accept_key(key: &str) -> String {
+    let digest = ring::digest::digest(
+        &ring::digest::SHA1_FOR_LEGACY_USE_ONLY,
+        format!("{}{}", key.trim(), GUID).as_bytes(),
+    );
+    BASE64.encode(digest.as_ref())
+}
+
+fn has_token(headers: &HeaderMap, name: hyper::header::HeaderName, token: &str) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|t| t.trim().eq_ignore_ascii_case(token))
+}
+
+/// Whether `req` asks to open a WebSocket.
+pub fn is_upgrade_request<B>(req: &Request<B>) -> bool {
+    req.method() == Method::GET
+        && has_token(req.headers(), UPGRADE, "websocket")
+        && has_token(req.headers(), CONNECTION, "upgrade")
+        && req.headers().contains_key(SEC_WEBSOCKET_KEY)
+}
+
+/// Completes the server side of the handshake and runs `handler` on the
+/// connection once hyper hands it over. Return the response from the
+/// request handler. Requests that are not WebSocket upgrades get a 400.
+pub fn accept<F, Fut>(mut req: Request<RequestBody>, handler: F) -> Response<ResponseBody>
+where
+    F: FnOnce(WebSocket) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    if !is_upgrade_request(&req) || req.version() != hyper::Version::HTTP_11 {
+        return ServerResponse::new(StatusCode::BAD_REQUEST).text("expected a WebSocket upgrade");
+    }
+    if req
+        .headers()
+        .get(SEC_WEBSOCKET_VERSION)
+        .map(|v| v.as_bytes())
+        != Some(b"13")
+    {
+        return ServerResponse::new(StatusCode::UPGRADE_REQUIRED)
+            .header(SEC_WEBSOCKET_VERSION, "13")
+            .empty();
+    }
+    let key = req.headers()[SEC_WEBSOCKET_KEY]
+        .to_str()
+        .unwrap_or_default()
+        .to_string();
+
+    let on_upgrade = hyper::upgrade::on(&mut req);
+    tokio::spawn(async move {
+        match on_upgrade.await {
+            Ok(upgraded) => {
+                handler(WebSocket::from_raw(TokioIo::new(upgraded), Role::Server)).await
+            }
+            Err(e) => eprintln!("WebSocket upgrade failed: {}", e),
+        }
+    });
+
+    ServerResponse::new(StatusCode::SWITCHING_PROTOCOLS)
+        .header(UPGRADE, "websocket")
+        .header(CONNECTION, "upgrade")
+        .header(SEC_WEBSOCKET_ACCEPT, accept_key(&key))
+        .empty()
+}
+
+/// Checks the server's answer to a client handshake sent with `key`.
+pub(crate) fn check_response<B>(resp: &Response<B>, key: &str) -> Result<(), WebSocketError> {
+    if !has_token(resp.headers(), UPGRADE, "websocket") {
+        return Err(WebSocketError::Handshake(
+            "server did not upgrade to websocket".into(),
+        ));
+    }
+    let accept = resp.headers().get(SEC_WEBSOCKET_ACCEPT);
+    if accept.map(|v| v.as_bytes()) != Some(accept_key(key).as_bytes()) {
+        return Err(WebSocketError::Handshake(
+            "invalid Sec-WebSocket-Accept".into(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::SinkExt;
+
+    #[test]
+    fn test_accept_key() {
+        // Example from RFC 6455 section 1.3
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fragments_ping_and_close() {
+        let (a, b) = tokio::io::duplex(1024);
+        let mut client =
+            WebSocket::from_raw(a, Role::Client).with_config(Config::new().fragment_size(4));
+        let mut server = WebSocket::from_raw(b, Role::Server);
+
+        client.send(Message::text("hello, world")).await.unwrap();
+        client.send(Message::Ping("p".into())).await.unwrap();
+        assert_eq!(
+            server.next().await.unwrap().unwrap(),
+            Message::text("hello, world")
+        );
+        assert_eq!(
+            server.next().await.unwrap().unwrap(),
+            Message::Ping("p".into())
+        );
+        // The server answers the ping on its own
+        server.flush().await.unwrap();
+        assert_eq!(
+            client.next().await.unwrap().unwrap(),
+            Message::Pong("p".into())
+        );
+
+        // Close handshake: the server echoes and both streams end
+        client
+            .send(Message::Close(Some(CloseFrame::new(4000, "bye"))))
+            .await
+            .unwrap();
+        assert!(client.send(Message::text("late")).await.is_err());
+        assert_eq!(
+            server.next().await.unwrap().unwrap(),
+            Message::Close(Some(CloseFrame::new(4000, "bye")))
+        );
+        assert!(server.next().await.is_none());
+        assert_eq!(
+            client.next().await.unwrap().unwrap(),
+            Message::Close(Some(CloseFrame::new(4000, "")))
+        );
+        assert!(client.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_websocket_loopback() {
+        use crate::{Client, Server};
+        use std::convert::Infallible;
+
+        let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+        let mut server = Server::new("127.0.0.1:0".parse().unwrap());
+        let addr = server.bind().await.unwrap().tcp().unwrap();
+        tokio::spawn(async move {
+            let handler = |req: Request<RequestBody>| async move {
+                Ok::<_, Infallible>(accept(req, |ws| async move {
+                    // Echo data messages through split halves
+                    let (mut tx, mut rx) = ws.split();
+                    while let Some(Ok(message)) = rx.next().await {
+                        if let Message::Text(_) | Message::Binary(_) = message {
+                            tx.send(message).await.unwrap();
+                        }
+                    }
+                }))
+            };
+            server.run(handler, shutdown_rx).await
+        });
+
+        let client = Client::new();
+        let plain = client.get(&format!("http://{}/ws", addr)).await;
+        assert_eq!(plain.unwrap_err().status(), Some(StatusCode::BAD_REQUEST));
+
+        let ws = client
+            .websocket(&format!("ws://{}/ws", addr))
+            .await
+            .unwrap()
+            .with_config(Config::new().fragment_size(1000));
+        let (mut tx, mut rx) = ws.split();
+        let big = "x".repeat(5000);
+        tx.send(Message::text("hello")).await.unwrap();
+        tx.send(Message::binary(vec![1u8, 2, 3])).await.unwrap();
+        tx.send(Message::text(big.clone())).await.unwrap();
+        tx.send(Message::Ping("are you there".into()))
+            .await
+            .unwrap();
+
+        assert_eq!(rx.next().await.unwrap().unwrap(), Message::text("hello"));
+        assert_eq!(
+            rx.next().await.unwrap().unwrap(),
+            Message::binary(vec![1u8, 2, 3])
+        );
+        assert_eq!(rx.next().await.unwrap().unwrap(), Message::text(big));
+        assert_eq!(
+            rx.next().await.unwrap().unwrap(),
+            Message::Pong("are you there".into())
+        );
+
+        tx.close().await.unwrap();
+        assert_eq!(
+            rx.next().await.unwrap().unwrap(),
+            Message::Close(Some(CloseFrame::new(CLOSE_NORMAL, "")))
+        );
+        assert!(rx.next().await.is_none());
+
+        shutdown_tx.send(true).unwrap();
+    }
+}
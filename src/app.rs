@@ -1,7 +1,10 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use crate::config::Config;
-use conduit::{ClaudeModel, Conduit, ConduitError, StreamEvent};
+use crate::store::{Conversation, ConversationStore, StoredMessage};
+use conduit::{
+    CancelToken, Conduit, ConduitError, MessageRequest, Sampling, StreamEvent, TrimStrategy, Usage,
+};
 use cosmic::app::{Core, Task};
 use cosmic::cosmic_theme;
 use cosmic::iced::advanced::subscription::Recipe;
@@ -19,6 +22,7 @@ use futures_util::StreamExt;
 use std::hash::{Hash, Hasher};
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 
 pub struct AppModel {
     core: Core,
@@ -27,6 +31,69 @@ pub struct AppModel {
     input_value: String,
     conduit: Option<Arc<Conduit>>,
     stream_state: StreamState,
+    /// Stops the response being streamed
+    cancel: CancelToken,
+    /// Incremented for every sent message so each request gets its own
+    /// subscription, which stays alive across UI updates.
+    request_id: u64,
+    /// Set by the "Trim conversation" action: the next request sheds all
+    /// unpinned history before the prompt using the configured strategy,
+    /// while the conversation itself is kept.
+    trim_history: bool,
+    store: ConversationStore,
+    conversation_id: String,
+    /// This conversation's overrides of the configured sampling parameters
+    sampling: Sampling,
+    /// The override fields as typed, which may not parse yet
+    sampling_inputs: SamplingInputs,
+    show_sampling: bool,
+    /// Estimated tokens behind the context meter, updated along with the
+    /// history, settings and draft rather than on every render
+    context: ContextEstimate,
+}
+
+#[derive(Debug, Default)]
+struct ContextEstimate {
+    history: u32,
+    draft: u32,
+    window: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SamplingField {
+    Temperature,
+    TopP,
+    TopK,
+    StopSequences,
+}
+
+#[derive(Debug, Default)]
+struct SamplingInputs {
+    temperature: String,
+    top_p: String,
+    top_k: String,
+    /// Comma separated
+    stop_sequences: String,
+}
+
+impl From<&Sampling> for SamplingInputs {
+    fn from(sampling: &Sampling) -> Self {
+        let show = |value: Option<String>| value.unwrap_or_default();
+        Self {
+            temperature: show(sampling.temperature.map(|t| t.to_string())),
+            top_p: show(sampling.top_p.map(|p| p.to_string())),
+            top_k: show(sampling.top_k.map(|k| k.to_string())),
+            stop_sequences: sampling.stop_sequences.join(", "),
+        }
+    }
+}
+
+/// An empty field clears the override.
+fn parse_override<T: std::str::FromStr>(input: &str) -> Result<Option<T>, T::Err> {
+    match input.trim() {
+        "" => Ok(None),
+        value => value.parse().map(Some),
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -34,6 +101,159 @@ struct ChatMessage {
     content: String,
     is_user: bool,
     is_streaming: bool,
+    /// Kept when older history is trimmed to fit the context window
+    pinned: bool,
+    /// The model's extended thinking before it answered
+    reasoning: String,
+    show_reasoning: bool,
+    /// Transient status line shown under the message, e.g. while retrying
+    status: Option<String>,
+    /// Why the response failed, shown under whatever arrived before
+    error: Option<ChatError>,
+    /// Model that wrote an assistant message
+    model: Option<String>,
+    usage: Option<Usage>,
+    /// In US dollars, when the model's price is known
+    cost: Option<f64>,
+}
+
+impl ChatMessage {
+    fn new(content: impl Into<String>, is_user: bool) -> Self {
+        Self {
+            content: content.into(),
+            is_user,
+            is_streaming: !is_user,
+            pinned: false,
+            reasoning: String::new(),
+            show_reasoning: false,
+            status: None,
+            error: None,
+            model: None,
+            usage: None,
+            cost: None,
+        }
+    }
+
+    /// Token counts and cost shown under an assistant message.
+    fn footer(&self) -> Option<String> {
+        let mut footer = self.usage?.to_string();
+        if let Some(cost) = self.cost {
+            footer.push_str(&format!(" · ${:.4}", cost));
+        }
+        Some(footer)
+    }
+}
+
+impl From<&ChatMessage> for StoredMessage {
+    fn from(message: &ChatMessage) -> Self {
+        Self {
+            is_user: message.is_user,
+            content: message.content.clone(),
+            pinned: message.pinned,
+            reasoning: message.reasoning.clone(),
+            model: message.model.clone(),
+            usage: message.usage,
+            cost: message.cost,
+        }
+    }
+}
+
+impl From<StoredMessage> for ChatMessage {
+    fn from(message: StoredMessage) -> Self {
+        Self {
+            model: message.model,
+            usage: message.usage,
+            cost: message.cost,
+            pinned: message.pinned,
+            reasoning: message.reasoning,
+            is_streaming: false,
+            ..ChatMessage::new(message.content, message.is_user)
+        }
+    }
+}
+
+/// A failed response, with guidance and what the user can do about it.
+#[derive(Debug, Clone)]
+pub struct ChatError {
+    summary: String,
+    guidance: Option<String>,
+    action: Option<ErrorAction>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ErrorAction {
+    Retry,
+    TrimConversation,
+}
+
+impl ChatError {
+    fn other(summary: impl Into<String>) -> Self {
+        Self {
+            summary: summary.into(),
+            guidance: None,
+            action: Some(ErrorAction::Retry),
+        }
+    }
+}
+
+impl From<&ConduitError> for ChatError {
+    fn from(error: &ConduitError) -> Self {
+        let retry_in = |after: Option<Duration>| match after {
+            Some(after) => format!("Retry in {}s", after.as_secs().max(1)),
+            None => "Retry in a moment".to_string(),
+        };
+        let (summary, guidance, action) = match error {
+            ConduitError::AuthenticationFailed { message } => (
+                format!("Authentication failed: {}", message),
+                Some("Add your API key by setting ANTHROPIC_API_KEY".to_string()),
+                None,
+            ),
+            ConduitError::RateLimited { .. } => (
+                "Rate limited".to_string(),
+                Some(retry_in(error.retry_after())),
+                Some(ErrorAction::Retry),
+            ),
+            ConduitError::Overloaded { .. } => (
+                "Claude is overloaded".to_string(),
+                Some(retry_in(error.retry_after())),
+                Some(ErrorAction::Retry),
+            ),
+            ConduitError::ContextTooLong { tokens, limit, .. } => (
+                match (tokens, limit) {
+                    (Some(tokens), Some(limit)) => {
+                        format!("Conversation too long ({} of {} tokens)", tokens, limit)
+                    }
+                    _ => "Conversation too long".to_string(),
+                },
+                Some("Trim conversation".to_string()),
+                Some(ErrorAction::TrimConversation),
+            ),
+            ConduitError::InvalidRequest { field, message } => (
+                format!("Invalid request: {}", message),
+                field
+                    .as_ref()
+                    .map(|field| format!("Check the {} setting", field)),
+                None,
+            ),
+            ConduitError::Network(_) => (
+                error.to_string(),
+                Some("Check your connection".to_string()),
+                Some(ErrorAction::Retry),
+            ),
+            ConduitError::Timeout => (
+                "Claude did not respond in time".to_string(),
+                None,
+                Some(ErrorAction::Retry),
+            ),
+            ConduitError::Cancelled => ("Stopped".to_string(), None, Some(ErrorAction::Retry)),
+            _ => return ChatError::other(error.to_string()),
+        };
+        Self {
+            summary,
+            guidance,
+            action,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -43,8 +263,22 @@ pub enum Message {
     UpdateConfig(Config),
     StreamStarted,
     StreamUpdate(String),
+    /// Extended thinking, shown apart from the answer
+    StreamReasoning(String),
+    /// Model and running token counts for the response being streamed
+    StreamUsage(String, Usage),
+    /// Number of earlier messages trimmed or summarized to fit the context
+    ContextTrimmed(usize),
+    StreamRetrying(Duration),
     StreamCompleted,
-    StreamError(String),
+    StreamError(ChatError),
+    CancelStream,
+    Retry,
+    TrimConversation,
+    TogglePin(usize),
+    ToggleReasoning(usize),
+    ToggleSampling,
+    SamplingChanged(SamplingField, String),
 }
 
 #[derive(Debug)]
@@ -70,18 +304,38 @@ impl cosmic::Application for AppModel {
 
     fn init(core: Core, _flags: Self::Flags) -> (Self, Task<Message>) {
         let config = Config::default();
-        let conduit = Conduit::new(config.anthropic.api_key.clone())
-            .ok()
-            .map(Arc::new);
+        let conduit = Some(Arc::new(Conduit::new(config.anthropic.api_key.clone())));
+
+        // Pick up where the last conversation left off
+        let store = ConversationStore::open_default();
+        let conversation = store.latest().unwrap_or_else(|e| {
+            eprintln!("Failed to load conversations: {}", e);
+            None
+        });
+        let conversation = conversation.unwrap_or_else(Conversation::new);
 
-        let app = AppModel {
+        let mut app = AppModel {
             core,
             config,
-            messages: Vec::new(),
+            messages: conversation
+                .messages
+                .into_iter()
+                .map(ChatMessage::from)
+                .collect(),
             input_value: String::new(),
             conduit,
             stream_state: StreamState::Idle,
+            cancel: CancelToken::new(),
+            request_id: 0,
+            trim_history: false,
+            store,
+            conversation_id: conversation.id,
+            sampling_inputs: SamplingInputs::from(&conversation.sampling),
+            sampling: conversation.sampling,
+            show_sampling: false,
+            context: ContextEstimate::default(),
         };
+        app.refresh_context();
 
         (app, Task::none())
     }
@@ -89,15 +343,17 @@ impl cosmic::Application for AppModel {
         match &self.stream_state {
             StreamState::Streaming => {
                 if let Some(conduit) = &self.conduit {
-                    // Only use the last user message
-                    if let Some(last_user_msg) = self.messages.iter().rev().find(|msg| msg.is_user)
-                    {
-                        let prompt = last_user_msg.content.clone();
-                        eprintln!("Creating subscription for message: '{}'", prompt);
-
+                    // Send the whole conversation so far
+                    let (request, pinned) = self.request();
+                    if !request.messages.is_empty() {
                         struct StreamSubscription {
                             conduit: Arc<Conduit>,
-                            prompt: String,
+                            cancel: CancelToken,
+                            request: MessageRequest,
+                            pinned: Vec<bool>,
+                            strategy: TrimStrategy,
+                            trim_history: bool,
+                            request_id: u64,
                         }
 
                         impl Recipe for StreamSubscription {
@@ -108,15 +364,9 @@ impl cosmic::Application for AppModel {
                                 state: &mut cosmic::iced::advanced::graphics::futures::subscription::Hasher,
                             ) {
                                 use std::hash::Hash;
-                                // Include timestamp in hash to ensure unique subscriptions
-                                (
-                                    self.prompt.clone(),
-                                    std::time::SystemTime::now()
-                                        .duration_since(std::time::UNIX_EPOCH)
-                                        .unwrap_or_default()
-                                        .as_secs(),
-                                )
-                                    .hash(state);
+                                // Each sent message gets a fresh subscription while
+                                // updates keep the current one alive
+                                self.request_id.hash(state);
                             }
 
                             fn stream(
@@ -125,48 +375,97 @@ impl cosmic::Application for AppModel {
                             ) -> Pin<Box<dyn Stream<Item = Message> + Send>>
                             {
                                 Box::pin(async_stream::stream! {
-                                    eprintln!("Starting stream for message: '{}'", self.prompt);
-                                    match self.conduit.stream_message(&self.prompt, ClaudeModel::Claude35Sonnet, 1024).await {
+                                    let mut request = self.request.clone();
+                                    // The trim action drops everything but the prompt and pins
+                                    let budget = match self.trim_history {
+                                        true => 0,
+                                        false => conduit::input_budget(&request),
+                                    };
+                                    let fit = self.conduit.fit_context_to(&mut request, &self.pinned, self.strategy, budget);
+                                    // Tokens spent summarizing count towards this reply
+                                    let mut summary_usage = Usage::default();
+                                    match self.cancel.run(fit).await {
+                                        Ok((0, _)) => {}
+                                        Ok((trimmed, spent)) => {
+                                            eprintln!("Trimmed {} messages to fit the context window", trimmed);
+                                            summary_usage = spent;
+                                            yield Message::ContextTrimmed(trimmed);
+                                        }
+                                        Err(e) => {
+                                            eprintln!("Failed to fit the context window: {}", e);
+                                            yield Message::StreamError(ChatError::from(&e));
+                                            return;
+                                        }
+                                    }
+
+                                    let mut attempt = 0;
+                                    let opened = loop {
+                                        match self.cancel.run(self.conduit.stream(&request)).await {
+                                            Err(e) => match self.conduit.retry_delay(&e, attempt) {
+                                                Some(delay) => {
+                                                    eprintln!("Retrying in {:?}: {}", delay, e);
+                                                    yield Message::StreamRetrying(delay);
+                                                    let wait = self.cancel.run(async {
+                                                        tokio::time::sleep(delay).await;
+                                                        Ok(())
+                                                    });
+                                                    if let Err(e) = wait.await {
+                                                        break Err(e);
+                                                    }
+                                                    attempt += 1;
+                                                }
+                                                None => break Err(e),
+                                            },
+                                            opened => break opened,
+                                        }
+                                    };
+                                    match opened {
                                         Ok(stream) => {
-                                            let mut pinned = Box::pin(stream);
+                                            let mut pinned = Box::pin(self.cancel.wrap(stream));
                                             let mut content_started = false;
+                                            let mut model = String::new();
+                                            let mut usage = Usage::default();
 
                                             yield Message::StreamStarted;
 
                                             while let Some(event) = pinned.next().await {
-                                                match event {
-                                                    Ok(StreamEvent::ContentBlockDelta(content)) => {
-                                                        if !content.delta.text.is_empty() {
-                                                            content_started = true;
-                                                            eprintln!("Got content: {}", content.delta.text);
-                                                            yield Message::StreamUpdate(content.delta.text);
-                                                        }
+                                                // Reasoning and answer text arrive in separate blocks
+                                                if let Ok(event) = &event {
+                                                    if let Some(thinking) = event.thinking() {
+                                                        yield Message::StreamReasoning(thinking.to_string());
+                                                        continue;
+                                                    }
+                                                    if let Some(text) = event.text() {
+                                                        content_started = true;
+                                                        yield Message::StreamUpdate(text.to_string());
+                                                        continue;
                                                     }
+                                                }
+                                                match event {
                                                     Ok(StreamEvent::MessageStop) => {
-                                                        eprintln!("Stream complete");
                                                         if content_started {
                                                             yield Message::StreamCompleted;
                                                         } else {
                                                             // If no content was received, treat as an error
-                                                            yield Message::StreamError("No content received".to_string());
+                                                            yield Message::StreamError(ChatError::other("No content received"));
                                                         }
                                                         break;
                                                     }
-                                                    Ok(StreamEvent::MessageStart { .. }) => {
-                                                        eprintln!("Message start received");
-                                                    }
-                                                    Ok(StreamEvent::ContentBlockStart(_)) => {
-                                                        eprintln!("Content block start received");
-                                                    }
-                                                    Ok(StreamEvent::ContentBlockStop(_)) => {
-                                                        eprintln!("Content block stop received");
+                                                    Ok(event @ StreamEvent::MessageStart { .. }) => {
+                                                        if let StreamEvent::MessageStart { message } = &event {
+                                                            model = message.model.id().to_string();
+                                                        }
+                                                        usage.record(&event);
+                                                        yield Message::StreamUsage(model.clone(), usage + summary_usage);
                                                     }
-                                                    Ok(_) => {
-                                                        eprintln!("Other event type received");
+                                                    Ok(event @ StreamEvent::MessageDelta { .. }) => {
+                                                        usage.record(&event);
+                                                        yield Message::StreamUsage(model.clone(), usage + summary_usage);
                                                     }
+                                                    Ok(_) => {}
                                                     Err(e) => {
                                                         eprintln!("Stream error: {}", e);
-                                                        yield Message::StreamError(e.to_string());
+                                                        yield Message::StreamError(ChatError::from(&e));
                                                         break;
                                                     }
                                                 }
@@ -174,7 +473,7 @@ impl cosmic::Application for AppModel {
                                         }
                                         Err(e) => {
                                             eprintln!("Failed to create stream: {}", e);
-                                            yield Message::StreamError(e.to_string());
+                                            yield Message::StreamError(ChatError::from(&e));
                                         }
                                     }
                                 })
@@ -184,7 +483,12 @@ impl cosmic::Application for AppModel {
                         cosmic::iced::advanced::graphics::futures::subscription::from_recipe(
                             StreamSubscription {
                                 conduit: Arc::clone(conduit),
-                                prompt,
+                                cancel: self.cancel.clone(),
+                                request,
+                                pinned,
+                                strategy: self.config.anthropic.context_strategy,
+                                trim_history: self.trim_history,
+                                request_id: self.request_id,
                             },
                         )
                     } else {
@@ -201,6 +505,7 @@ impl cosmic::Application for AppModel {
     fn update(&mut self, message: Message) -> Task<Message> {
         match message {
             Message::InputChanged(value) => {
+                self.context.draft = conduit::estimate_tokens(&value);
                 self.input_value = value;
             }
             Message::SendMessage => {
@@ -209,25 +514,25 @@ impl cosmic::Application for AppModel {
                     self.stream_state = StreamState::Error("Cannot send empty message".to_string());
                 } else if self.conduit.is_some() && matches!(self.stream_state, StreamState::Idle) {
                     // Only allow sending if we're in Idle state
-                    eprintln!("Sending message: {}", prompt);
-
                     // Add user message
-                    self.messages.push(ChatMessage {
-                        content: prompt.to_string(),
-                        is_user: true,
-                        is_streaming: false,
-                    });
-
-                    // Add placeholder for assistant response
-                    self.messages.push(ChatMessage {
-                        content: String::new(),
-                        is_user: false,
-                        is_streaming: true,
-                    });
-
-                    // Set streaming state and clear input
-                    self.stream_state = StreamState::Streaming;
+                    self.messages.push(ChatMessage::new(prompt, true));
+                    self.start_response();
                     self.input_value.clear();
+                    self.context.draft = 0;
+                    self.refresh_context();
+                }
+            }
+            Message::CancelStream => self.cancel.cancel(),
+            Message::Retry | Message::TrimConversation => {
+                let failed = self
+                    .messages
+                    .last()
+                    .is_some_and(|last| !last.is_user && last.error.is_some());
+                if failed && matches!(self.stream_state, StreamState::Idle) {
+                    self.messages.pop();
+                    self.start_response();
+                    self.refresh_context();
+                    self.trim_history = matches!(message, Message::TrimConversation);
                 }
             }
 
@@ -242,35 +547,136 @@ impl cosmic::Application for AppModel {
                 if let Some(last) = self.messages.last_mut() {
                     if !last.is_user && last.is_streaming {
                         last.content.push_str(&content);
+                        last.status = None;
+                    }
+                }
+            }
+            Message::StreamReasoning(thinking) => {
+                if let Some(last) = self.messages.last_mut() {
+                    if !last.is_user && last.is_streaming {
+                        last.reasoning.push_str(&thinking);
+                        last.status = None;
+                    }
+                }
+            }
+            Message::ToggleReasoning(index) => {
+                if let Some(message) = self.messages.get_mut(index) {
+                    message.show_reasoning = !message.show_reasoning;
+                }
+            }
+            Message::ToggleSampling => self.show_sampling = !self.show_sampling,
+            Message::SamplingChanged(field, value) => {
+                let inputs = &mut self.sampling_inputs;
+                let sampling = &mut self.sampling;
+                // Keep the last valid override until the field parses again
+                let parsed = match field {
+                    SamplingField::Temperature => {
+                        inputs.temperature = value;
+                        parse_override(&inputs.temperature)
+                            .map(|t| sampling.temperature = t)
+                            .is_ok()
+                    }
+                    SamplingField::TopP => {
+                        inputs.top_p = value;
+                        parse_override(&inputs.top_p)
+                            .map(|p| sampling.top_p = p)
+                            .is_ok()
+                    }
+                    SamplingField::TopK => {
+                        inputs.top_k = value;
+                        parse_override(&inputs.top_k)
+                            .map(|k| sampling.top_k = k)
+                            .is_ok()
+                    }
+                    SamplingField::StopSequences => {
+                        inputs.stop_sequences = value;
+                        sampling.stop_sequences = inputs
+                            .stop_sequences
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|s| !s.is_empty())
+                            .map(String::from)
+                            .collect();
+                        true
+                    }
+                };
+                if parsed {
+                    self.save();
+                    self.refresh_context();
+                }
+            }
+            Message::StreamUsage(model, usage) => {
+                let cost = self
+                    .config
+                    .anthropic
+                    .pricing(&model)
+                    .map(|pricing| pricing.cost(&usage));
+                if let Some(last) = self.messages.last_mut() {
+                    if !last.is_user && last.is_streaming {
+                        last.model = Some(model);
+                        last.usage = Some(usage);
+                        last.cost = cost;
+                    }
+                }
+            }
+            Message::ContextTrimmed(count) => {
+                let verb = match self.config.anthropic.context_strategy {
+                    TrimStrategy::Summarize => "Summarized",
+                    _ => "Left out",
+                };
+                if let Some(last) = self.messages.last_mut() {
+                    if !last.is_user && last.is_streaming {
+                        last.status = Some(format!(
+                            "{} {} earlier messages to fit the context window",
+                            verb, count
+                        ));
+                    }
+                }
+            }
+            Message::TogglePin(index) => {
+                if let Some(message) = self.messages.get_mut(index) {
+                    message.pinned = !message.pinned;
+                    self.save();
+                }
+            }
+            Message::StreamRetrying(delay) => {
+                if let Some(last) = self.messages.last_mut() {
+                    if !last.is_user && last.is_streaming {
+                        last.status = Some(format!("Retrying in {}s…", delay.as_secs().max(1)));
                     }
                 }
             }
             Message::StreamCompleted => {
-                eprintln!("Stream completed, resetting state");
                 if let Some(last) = self.messages.last_mut() {
                     if !last.is_user {
                         last.is_streaming = false;
+                        last.status = None;
                     }
                 }
                 self.stream_state = StreamState::Idle;
+                self.save();
+                self.refresh_context();
             }
 
             Message::StreamError(error) => {
-                eprintln!("Stream error: {}", error);
+                eprintln!("Stream error: {}", error.summary);
                 if let Some(last) = self.messages.last_mut() {
                     if !last.is_user {
                         last.is_streaming = false;
-                        last.content = format!("[Error: {}]", error);
+                        last.status = None;
+                        last.error = Some(error);
                     }
                 }
                 self.stream_state = StreamState::Idle;
+                self.save();
             }
             Message::UpdateConfig(config) => {
                 self.config = config;
                 // Recreate conduit with new config
-                self.conduit = Conduit::new(self.config.anthropic.api_key.clone())
-                    .ok()
-                    .map(Arc::new);
+                self.conduit = Some(Arc::new(Conduit::new(
+                    self.config.anthropic.api_key.clone(),
+                )));
+                self.refresh_context();
             }
         }
         Task::none()
@@ -285,11 +691,11 @@ impl cosmic::Application for AppModel {
         } = theme::active().cosmic().spacing;
 
         // Build message list
-        let messages = self.messages.iter().fold(
+        let messages = self.messages.iter().enumerate().fold(
             column::with_capacity(self.messages.len())
                 .spacing(space_l)
                 .padding(space_m),
-            |column, message| {
+            |column, (index, message)| {
                 let message_text = if message.is_streaming {
                     let mut content = message.content.clone();
                     content.push('▋'); // Add cursor for streaming messages
@@ -298,8 +704,81 @@ impl cosmic::Application for AppModel {
                     text::body(&message.content)
                 };
 
+                let message_body = match (&message.status, &message.error) {
+                    (_, Some(error)) => {
+                        let mut notice = error.summary.clone();
+                        if let Some(guidance) = &error.guidance {
+                            notice = format!("{}. {}", notice, guidance);
+                        }
+                        let action = error.action.map(|action| match action {
+                            ErrorAction::Retry => button::custom("Retry").on_press(Message::Retry),
+                            ErrorAction::TrimConversation => button::custom("Trim conversation")
+                                .on_press(Message::TrimConversation),
+                        });
+                        column::with_capacity(3)
+                            .spacing(space_xxs)
+                            .push_maybe((!message.content.is_empty()).then_some(message_text))
+                            .push(text::caption(notice))
+                            .push_maybe(action.map(|action| action.class(theme::Button::Text)))
+                            .apply(Element::from)
+                    }
+                    (Some(status), None) => column::with_capacity(2)
+                        .spacing(space_xxs)
+                        .push(message_text)
+                        .push(text::caption(status.clone()))
+                        .apply(Element::from),
+                    (None, None) => Element::from(message_text),
+                };
+                // Reasoning sits above the answer, collapsed and dimmed
+                let message_body = if message.reasoning.is_empty() {
+                    message_body
+                } else {
+                    let label = match (message.show_reasoning, message.content.is_empty()) {
+                        (true, _) => "▾ Reasoning",
+                        (false, true) if message.is_streaming => "▸ Thinking…",
+                        (false, _) => "▸ Reasoning",
+                    };
+                    let reasoning = message.show_reasoning.then(|| {
+                        container::Container::new(text::body(&message.reasoning)).style(
+                            |_theme: &Theme| Style {
+                                text_color: Some(Color::new(0.5, 0.5, 0.5, 1.0)),
+                                ..Style::default()
+                            },
+                        )
+                    });
+                    column::with_capacity(3)
+                        .spacing(space_xxs)
+                        .push(
+                            button::custom(label)
+                                .class(theme::Button::Text)
+                                .on_press(Message::ToggleReasoning(index)),
+                        )
+                        .push_maybe(reasoning)
+                        .push(message_body)
+                        .apply(Element::from)
+                };
+                // Pinned messages survive trimming the history
+                let pin = (!message.is_streaming && !message.content.is_empty()).then(|| {
+                    button::custom(if message.pinned { "Unpin" } else { "Pin" })
+                        .class(theme::Button::Text)
+                        .on_press(Message::TogglePin(index))
+                });
+                let message_body = match (message.footer(), pin) {
+                    (None, None) => message_body,
+                    (footer, pin) => column::with_capacity(2)
+                        .spacing(space_xxs)
+                        .push(message_body)
+                        .push(
+                            row::with_capacity(2)
+                                .spacing(space_xxs)
+                                .push_maybe(footer.map(|footer| text::caption(footer)))
+                                .push_maybe(pin),
+                        )
+                        .apply(Element::from),
+                };
+
                 let message_container =
-                    container::Container::new(message_text).style(|_theme: &Theme| Style {
+                    container::Container::new(message_body).style(|_theme: &Theme| Style {
                         text_color: if message.is_streaming {
                             Some(Color::new(0.5, 0.5, 0.5, 1.0))
                         } else {
@@ -320,9 +799,11 @@ impl cosmic::Application for AppModel {
             },
         );
 
-        // Disable the send button while streaming
+        // Swap the send button for a stop button while streaming
         let send_button = if matches!(self.stream_state, StreamState::Streaming) {
-            button::custom("Send").class(theme::Button::Text)
+            button::custom("Stop")
+                .class(theme::Button::Text)
+                .on_press(Message::CancelStream)
         } else {
             button::custom("Send")
                 .class(theme::Button::Text)
@@ -357,9 +838,98 @@ impl cosmic::Application for AppModel {
         //             .on_press(Message::SendMessage),
         //     );
 
+        // How full the context window would be if the draft were sent now
+        let used = self.context.history + self.context.draft;
+        let window = self.context.window;
+        let mut status = format!(
+            "Context: {}% of {} tokens",
+            used as u64 * 100 / window as u64,
+            window
+        );
+
+        // Conversation total across all responses
+        let usage: Usage = self.messages.iter().filter_map(|m| m.usage).sum();
+        let cost: f64 = self.messages.iter().filter_map(|m| m.cost).sum();
+        if usage.total_tokens() > 0 {
+            status.push_str(&format!(
+                " · Conversation: {} tokens · ${:.4}",
+                usage.total_tokens(),
+                cost
+            ));
+            if usage.cache_read_input_tokens > 0 {
+                status.push_str(&format!(
+                    " · {} read from cache",
+                    usage.cache_read_input_tokens
+                ));
+            }
+        }
+        let status = row::with_capacity(2)
+            .spacing(space_xxs)
+            .push(text::caption(status).width(Length::Fill))
+            .push(
+                button::custom("Sampling")
+                    .class(theme::Button::Text)
+                    .on_press(Message::ToggleSampling),
+            )
+            .apply(container::Container::new)
+            .padding([0, space_m]);
+
+        // This conversation's sampling overrides, blank for the configured value
+        let sampling = self.show_sampling.then(|| {
+            let inputs = &self.sampling_inputs;
+            let field = |label, value: &str, field: SamplingField| {
+                text_input::text_input(label, value)
+                    .on_input(move |value| Message::SamplingChanged(field, value))
+                    .width(Length::Fill)
+            };
+            let invalid = [
+                (
+                    "temperature",
+                    parse_override::<f32>(&inputs.temperature).is_err(),
+                ),
+                ("top_p", parse_override::<f32>(&inputs.top_p).is_err()),
+                ("top_k", parse_override::<u32>(&inputs.top_k).is_err()),
+            ]
+            .into_iter()
+            .find(|(_, invalid)| *invalid)
+            .map(|(name, _)| format!("Enter a number for {}", name));
+            let problem = invalid.or_else(|| {
+                self.config
+                    .anthropic
+                    .sampling
+                    .merge(&self.sampling)
+                    .validate()
+                    .err()
+                    .map(|e| e.to_string())
+            });
+            column::with_capacity(2)
+                .spacing(space_xxs)
+                .push(
+                    row::with_capacity(4)
+                        .spacing(space_xxs)
+                        .push(field(
+                            "Temperature",
+                            &inputs.temperature,
+                            SamplingField::Temperature,
+                        ))
+                        .push(field("Top p", &inputs.top_p, SamplingField::TopP))
+                        .push(field("Top k", &inputs.top_k, SamplingField::TopK))
+                        .push(field(
+                            "Stop sequences, comma separated",
+                            &inputs.stop_sequences,
+                            SamplingField::StopSequences,
+                        )),
+                )
+                .push_maybe(problem.map(text::caption))
+                .apply(container::Container::new)
+                .padding([0, space_m])
+        });
+
         // Main layout
-        let content = column::with_capacity(2)
+        let content = column::with_capacity(4)
             .push(cosmic::iced_widget::Scrollable::new(messages).height(Length::Fill))
+            .push(status)
+            .push_maybe(sampling)
             .push(
                 container::Container::new(input)
                     .padding(space_m)
@@ -375,3 +945,55 @@ impl cosmic::Application for AppModel {
             .apply(Element::from)
     }
 }
+
+impl AppModel {
+    /// Adds a placeholder for the assistant's reply and starts streaming it.
+    fn start_response(&mut self) {
+        self.messages.push(ChatMessage::new(String::new(), false));
+        self.cancel = CancelToken::new();
+        self.request_id += 1;
+        self.trim_history = false;
+        self.stream_state = StreamState::Streaming;
+    }
+
+    /// Re-estimates the history for the context meter. Streamed replies
+    /// only count once complete, so this is not needed per token.
+    fn refresh_context(&mut self) {
+        let (request, _) = self.request();
+        self.context.history = conduit::estimate_request(&request);
+        self.context.window = request.model.context_window();
+    }
+
+    /// The conversation as a request, leaving out failed and unfinished
+    /// replies, along with which of its messages are pinned.
+    fn request(&self) -> (MessageRequest, Vec<bool>) {
+        let history: Vec<&ChatMessage> = self
+            .messages
+            .iter()
+            .filter(|m| !m.is_streaming && m.error.is_none() && !m.content.is_empty())
+            .collect();
+        let pinned = history.iter().map(|m| m.pinned).collect();
+        let messages = history
+            .iter()
+            .map(|m| match m.is_user {
+                true => conduit::Message::user(m.content.clone()),
+                false => conduit::Message::assistant(m.content.clone()),
+            })
+            .collect();
+        let mut request = self.config.anthropic.request(messages);
+        request.sampling = request.sampling.merge(&self.sampling);
+        (request, pinned)
+    }
+
+    /// Writes the conversation, usage included, to the store.
+    fn save(&self) {
+        let conversation = Conversation {
+            id: self.conversation_id.clone(),
+            messages: self.messages.iter().map(StoredMessage::from).collect(),
+            sampling: self.sampling.clone(),
+        };
+        if let Err(e) = self.store.save(&conversation) {
+            eprintln!("Failed to save conversation: {}", e);
+        }
+    }
+}
@@ -0,0 +1,409 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Headless mode. `llming ask` and `llming chat` stream replies to stdout
+//! using the same configuration and conversation store as the window, and
+//! `llming serve` exposes the backend as an OpenAI-compatible API.
+
+use crate::config::Config;
+use crate::serve;
+use crate::store::{Conversation, ConversationStore, StoredMessage};
+use conduit::{Conduit, ConduitError, MessageRequest, StreamEvent, Usage};
+use futures_util::StreamExt;
+use std::error::Error;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::net::SocketAddr;
+
+const USAGE: &str = "\
+Usage: llming [COMMAND]
+
+Opens the chat window when run without a command.
+
+Commands:
+  ask [OPTIONS] <PROMPT>...  Answer a prompt. Piped input is sent ahead of it,
+                             e.g. `git diff | llming ask \"review this\"`
+  chat [OPTIONS]             Chat in the terminal until an empty line or EOF
+  serve [OPTIONS]            Serve an OpenAI-compatible API, for editors and
+                             scripts that speak it. Clients use the printed
+                             token, or $LLMING_SERVE_TOKEN, as their API key
+  help                       Print this message
+
+Options:
+  -m, --model <MODEL>    Use MODEL instead of the configured model
+  -s, --system <PROMPT>  Use PROMPT as the system prompt
+  -c, --continue         Continue the most recent conversation
+  -l, --listen <ADDR>    Address for serve to listen on [default: 127.0.0.1:8080]
+";
+
+pub enum Command {
+    Ask {
+        prompt: Vec<String>,
+        options: Options,
+    },
+    Chat(Options),
+    Serve {
+        addr: SocketAddr,
+        options: Options,
+    },
+    Help,
+}
+
+#[derive(Default)]
+pub struct Options {
+    model: Option<String>,
+    system: Option<String>,
+    resume: bool,
+}
+
+impl Options {
+    /// The configuration with these options applied.
+    fn config(&self) -> Config {
+        let mut config = Config::default();
+        if let Some(model) = &self.model {
+            config.anthropic.model = model.clone();
+        }
+        if let Some(system) = &self.system {
+            config.anthropic.system_prompt = Some(system.clone());
+        }
+        config
+    }
+}
+
+impl Command {
+    /// Parses the arguments after the program name. `None` means no command
+    /// was given and the window should open.
+    pub fn parse(args: &[String]) -> Result<Option<Command>, String> {
+        let Some((command, rest)) = args.split_first() else {
+            return Ok(None);
+        };
+        if matches!(command.as_str(), "help" | "-h" | "--help") {
+            return Ok(Some(Command::Help));
+        }
+
+        let mut options = Options::default();
+        let mut listen = None;
+        let mut words = Vec::new();
+        let mut rest = rest.iter();
+        while let Some(arg) = rest.next() {
+            let mut value = || {
+                rest.next()
+                    .cloned()
+                    .ok_or_else(|| format!("{} needs a value", arg))
+            };
+            match arg.as_str() {
+                "-m" | "--model" => options.model = Some(value()?),
+                "-s" | "--system" => options.system = Some(value()?),
+                "-c" | "--continue" => options.resume = true,
+                "-l" | "--listen" => listen = Some(value()?),
+                "--" => words.extend(rest.by_ref().cloned()),
+                flag if flag.starts_with('-') && flag.len() > 1 => {
+                    return Err(format!("unknown option {}", flag))
+                }
+                word => words.push(word.to_string()),
+            }
+        }
+
+        if listen.is_some() && command != "serve" {
+            return Err("--listen only applies to serve".to_string());
+        }
+        match command.as_str() {
+            "ask" => Ok(Some(Command::Ask {
+                prompt: words,
+                options,
+            })),
+            "chat" if words.is_empty() => Ok(Some(Command::Chat(options))),
+            "chat" => Err("chat reads prompts from stdin, not arguments".to_string()),
+            "serve" if words.is_empty() && !options.resume => {
+                let listen = listen.as_deref().unwrap_or(serve::DEFAULT_ADDR);
+                let addr = listen
+                    .parse()
+                    .map_err(|_| format!("invalid address {}", listen))?;
+                Ok(Some(Command::Serve { addr, options }))
+            }
+            "serve" => Err("serve takes no prompt or --continue".to_string()),
+            other => Err(format!("unknown command {}", other)),
+        }
+    }
+}
+
+/// Prints usage for an argument error.
+pub fn usage_error(message: &str) -> i32 {
+    eprintln!("llming: {}\n\n{}", message, USAGE);
+    2
+}
+
+/// Runs `command` to completion and returns the exit code.
+pub fn run(command: Command) -> i32 {
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("llming: {}", e);
+            return 1;
+        }
+    };
+    let result = runtime.block_on(async {
+        match command {
+            Command::Help => {
+                print!("{}", USAGE);
+                Ok(())
+            }
+            Command::Ask { prompt, options } => ask(prompt, options).await,
+            Command::Chat(options) => chat(options).await,
+            Command::Serve { addr, options } => serve::run(options.config(), addr).await,
+        }
+    });
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            report(&*e);
+            1
+        }
+    }
+}
+
+fn report(error: &(dyn Error + 'static)) {
+    eprintln!("llming: {}", error);
+    if let Some(ConduitError::AuthenticationFailed { .. }) = error.downcast_ref() {
+        eprintln!("Set ANTHROPIC_API_KEY to your API key");
+    }
+}
+
+async fn ask(prompt: Vec<String>, options: Options) -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    if !io::stdin().is_terminal() {
+        io::stdin().read_to_string(&mut input)?;
+    }
+    // Piped content first, then what to do with it
+    let prompt = [input.trim_end(), &prompt.join(" ")]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    if prompt.is_empty() {
+        return Err("ask needs a prompt, as arguments or on stdin".into());
+    }
+
+    let mut session = Session::open(options);
+    session.reply(prompt).await?;
+    Ok(())
+}
+
+async fn chat(options: Options) -> Result<(), Box<dyn Error>> {
+    let mut session = Session::open(options);
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        eprint!("> ");
+        let Some(line) = lines.next().transpose()? else {
+            break;
+        };
+        if line.trim().is_empty() {
+            break;
+        }
+        // A failed reply doesn't end the chat
+        if let Err(e) = session.reply(line).await {
+            report(&e);
+        }
+    }
+    Ok(())
+}
+
+/// One conversation, saved after every reply.
+struct Session {
+    config: Config,
+    conduit: Conduit,
+    store: ConversationStore,
+    conversation: Conversation,
+}
+
+impl Session {
+    fn open(options: Options) -> Self {
+        let config = options.config();
+        let store = ConversationStore::open_default();
+        let latest = match options.resume {
+            true => store.latest().unwrap_or_else(|e| {
+                eprintln!("Failed to load conversations: {}", e);
+                None
+            }),
+            false => None,
+        };
+
+        Self {
+            conduit: Conduit::new(config.anthropic.api_key.clone()),
+            config,
+            store,
+            conversation: latest.unwrap_or_else(Conversation::new),
+        }
+    }
+
+    /// The conversation as a request, along with which messages are pinned.
+    fn request(&self) -> (MessageRequest, Vec<bool>) {
+        let history: Vec<&StoredMessage> = self
+            .conversation
+            .messages
+            .iter()
+            .filter(|m| !m.content.is_empty())
+            .collect();
+        let pinned = history.iter().map(|m| m.pinned).collect();
+        let messages = history
+            .iter()
+            .map(|m| match m.is_user {
+                true => conduit::Message::user(m.content.clone()),
+                false => conduit::Message::assistant(m.content.clone()),
+            })
+            .collect();
+        let mut request = self.config.anthropic.request(messages);
+        request.sampling = request.sampling.merge(&self.conversation.sampling);
+        (request, pinned)
+    }
+
+    /// Sends `prompt` with the history before it and streams the answer to
+    /// stdout. Reasoning and token counts go to stderr.
+    async fn reply(&mut self, prompt: String) -> Result<(), ConduitError> {
+        self.conversation
+            .messages
+            .push(StoredMessage::new(prompt, true));
+        self.save();
+
+        let (mut request, pinned) = self.request();
+        let strategy = self.config.anthropic.context_strategy;
+        let (trimmed, summary_usage) = self
+            .conduit
+            .fit_context(&mut request, &pinned, strategy)
+            .await?;
+        if trimmed > 0 {
+            eprintln!(
+                "Left out {} earlier messages to fit the context window",
+                trimmed
+            );
+        }
+
+        let mut attempt = 0;
+        let events = loop {
+            match self.conduit.stream(&request).await {
+                Err(e) => match self.conduit.retry_delay(&e, attempt) {
+                    Some(delay) => {
+                        eprintln!("{}; retrying in {}s", e, delay.as_secs().max(1));
+                        tokio::time::sleep(delay).await;
+                        attempt += 1;
+                    }
+                    None => return Err(e),
+                },
+                Ok(events) => break events,
+            }
+        };
+
+        let mut events = Box::pin(events);
+        let mut answer = StoredMessage::new(String::new(), false);
+        let mut usage = Usage::default();
+        let mut stdout = io::stdout().lock();
+        while let Some(event) = events.next().await {
+            let event = event?;
+            if let Some(thinking) = event.thinking() {
+                answer.reasoning.push_str(thinking);
+                eprint!("{}", thinking);
+            } else if let Some(text) = event.text() {
+                if answer.content.is_empty() && !answer.reasoning.is_empty() {
+                    eprintln!();
+                }
+                answer.content.push_str(text);
+                // Nowhere left to write, e.g. piped into `head`
+                if stdout
+                    .write_all(text.as_bytes())
+                    .and_then(|()| stdout.flush())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            if let StreamEvent::MessageStart { message } = &event {
+                answer.model = Some(message.model.id().to_string());
+            }
+            usage.record(&event);
+            if event == StreamEvent::MessageStop {
+                break;
+            }
+        }
+        let _ = writeln!(stdout);
+
+        if answer.content.is_empty() {
+            return Err(ConduitError::EmptyResponse);
+        }
+        // Summarizing earlier history is part of what this answer cost
+        let usage = usage + summary_usage;
+        answer.cost = answer
+            .model
+            .as_deref()
+            .and_then(|model| self.config.anthropic.pricing(model))
+            .map(|pricing| pricing.cost(&usage));
+        answer.usage = Some(usage);
+        match answer.cost {
+            Some(cost) => eprintln!("{} · ${:.4}", usage, cost),
+            None => eprintln!("{}", usage),
+        }
+        self.conversation.messages.push(answer);
+        self.save();
+        Ok(())
+    }
+
+    fn save(&self) {
+        if let Err(e) = self.store.save(&self.conversation) {
+            eprintln!("Failed to save conversation: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Command>, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Command::parse(&args)
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert!(matches!(parse(&[]), Ok(None)));
+        assert!(matches!(parse(&["--help"]), Ok(Some(Command::Help))));
+        match parse(&["ask", "-m", "claude-3-opus", "why", "--", "-v"]) {
+            Ok(Some(Command::Ask { prompt, options })) => {
+                assert_eq!(prompt, ["why", "-v"]);
+                assert_eq!(options.model.as_deref(), Some("claude-3-opus"));
+            }
+            _ => panic!("expected ask"),
+        }
+        assert!(matches!(
+            parse(&["chat", "--continue", "-s", "Be brief."]),
+            Ok(Some(Command::Chat(Options { resume: true, .. })))
+        ));
+        match parse(&["serve"]) {
+            Ok(Some(Command::Serve { addr, .. })) => {
+                assert_eq!(addr.to_string(), serve::DEFAULT_ADDR)
+            }
+            _ => panic!("expected serve"),
+        }
+        match parse(&["serve", "--listen", "127.0.0.1:9000"]) {
+            Ok(Some(Command::Serve { addr, .. })) => assert_eq!(addr.port(), 9000),
+            _ => panic!("expected serve"),
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse(&["ask", "-m"]).err().as_deref(),
+            Some("-m needs a value")
+        );
+        let invalid: [&[&str]; 7] = [
+            &["ask", "--verbose"],
+            &["chat", "hello"],
+            &["ask", "--listen", "127.0.0.1:9000"],
+            &["serve", "--listen", "nowhere"],
+            &["serve", "--continue"],
+            &["serve", "hello"],
+            &["frobnicate"],
+        ];
+        for args in invalid {
+            assert!(parse(args).is_err(), "{:?} should not parse", args);
+        }
+    }
+}
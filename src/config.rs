@@ -1,18 +1,92 @@
-
+use conduit::{
+    CacheBreakpoints, ClaudeModel, Message, MessageRequest, Pricing, Sampling, TrimStrategy,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Config {
     pub window_pos: Option<(i32, i32)>,
     pub window_size: Option<(u32, u32)>,
     pub anthropic: AnthropicConfig,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AnthropicConfig {
     pub api_key: String,
     pub model: String,
     pub max_tokens: u32,
+    /// Extended thinking budget in tokens, at least 1024, or `None` to
+    /// answer without reasoning first. Sent on top of `max_tokens`.
+    #[serde(default)]
+    pub thinking_budget: Option<u32>,
+    #[serde(default)]
+    pub system_prompt: Option<String>,
+    /// Default temperature, top_p, top_k and stop_sequences, which each
+    /// conversation can override
+    #[serde(flatten)]
+    pub sampling: Sampling,
+    /// How history is shortened once it outgrows the model's context window
+    #[serde(default)]
+    pub context_strategy: TrimStrategy,
+    /// Cache the system prompt and conversation prefix between turns
+    #[serde(default = "default_true")]
+    pub prompt_caching: bool,
+    /// Prices keyed by model name prefix, e.g. `claude-3-5-sonnet` also
+    /// covers `claude-3-5-sonnet-20241022`.
+    #[serde(default = "default_prices")]
+    pub prices: BTreeMap<String, Pricing>,
+}
+
+impl AnthropicConfig {
+    /// A request for `messages` with the configured model and limits.
+    pub fn request(&self, messages: Vec<Message>) -> MessageRequest {
+        let model = self
+            .model
+            .parse::<ClaudeModel>()
+            .unwrap_or_else(|never| match never {});
+        let mut request = MessageRequest::new(model, self.max_tokens, messages);
+        request.system = self.system_prompt.clone();
+        request.sampling = self.sampling.clone();
+        if let Some(budget) = self.thinking_budget {
+            request = request.thinking(budget);
+            request.max_tokens += budget;
+        }
+        if self.prompt_caching {
+            request.cache = CacheBreakpoints::all();
+        }
+        request
+    }
+
+    /// Pricing for a model id, from the longest matching prefix.
+    pub fn pricing(&self, model: &str) -> Option<&Pricing> {
+        let model = model.replace('.', "-");
+        self.prices
+            .iter()
+            .filter(|(prefix, _)| model.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, pricing)| pricing)
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// List prices in US dollars per million tokens.
+fn default_prices() -> BTreeMap<String, Pricing> {
+    [
+        ("claude-3-haiku", Pricing::new(0.25, 1.25)),
+        ("claude-3-opus", Pricing::new(15.0, 75.0)),
+        ("claude-3-5-haiku", Pricing::new(0.8, 4.0)),
+        ("claude-3-5-sonnet", Pricing::new(3.0, 15.0)),
+        ("claude-3-7-sonnet", Pricing::new(3.0, 15.0)),
+        ("claude-sonnet-4", Pricing::new(3.0, 15.0)),
+        ("claude-opus-4", Pricing::new(15.0, 75.0)),
+    ]
+    .into_iter()
+    .map(|(model, pricing)| (model.to_string(), pricing))
+    .collect()
 }
 
 impl Default for AnthropicConfig {
@@ -21,6 +95,12 @@ impl Default for AnthropicConfig {
             api_key: std::env::var("ANTHROPIC_API_KEY").unwrap_or_default(),
             model: "claude-3.5-sonnet".to_string(),
             max_tokens: 1024,
+            thinking_budget: None,
+            system_prompt: None,
+            sampling: Sampling::default(),
+            context_strategy: TrimStrategy::default(),
+            prompt_caching: true,
+            prices: default_prices(),
         }
     }
 }
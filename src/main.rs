@@ -1,15 +1,27 @@
 // SPDX-License-Identifier: MPL-2.0
 
 mod app;
+mod cli;
 // mod app_config;
 mod config;
 // mod http;
 mod i18n;
 // mod llm;
 // mod mcp;
+mod serve;
+mod store;
 
 fn main() -> cosmic::iced::Result {
     dotenv::dotenv().ok();
+
+    // `llming ask` and friends run headless
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match cli::Command::parse(&args) {
+        Ok(Some(command)) => std::process::exit(cli::run(command)),
+        Ok(None) => {}
+        Err(e) => std::process::exit(cli::usage_error(&e)),
+    }
+
     // Get the system's preferred languages.
     let requested_languages = i18n_embed::DesktopLanguageRequester::requested_languages();
 
@@ -0,0 +1,920 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! `llming serve`, an OpenAI-compatible API in front of the configured
+//! backend, so editor plugins and scripts can share one endpoint and key.
+//!
+//! Clients authenticate with a bearer token, which stands in for the
+//! OpenAI API key. Browsers are kept out: any web page can send requests to
+//! a local port, so only loopback `Host` and `Origin` headers are accepted.
+
+use crate::config::Config;
+use conduit::{
+    ClaudeModel, Conduit, ConduitError, ContentBlock, Delta, Message, MessageRequest,
+    MessageResponse, Metadata, Role, Sampling, Source, StopReason, StreamEvent, ThinkingConfig,
+    Tool, ToolChoice, Usage,
+};
+use futures_util::{Stream, StreamExt};
+use hyperax::middleware::{BearerAuth, BodyLimit, Logger, Next};
+use hyperax::sse::{Event, Sse};
+use hyperax::{
+    BodyExt, Request, RequestBody, Response, ResponseBody, Router, Server, ServerResponse,
+    StatusCode,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot, watch};
+
+pub const DEFAULT_ADDR: &str = "127.0.0.1:8080";
+
+/// Large enough for attached files and images.
+const BODY_LIMIT: usize = 32 * 1024 * 1024;
+
+/// Fixes the bearer token across restarts; otherwise one is generated.
+const TOKEN_VAR: &str = "LLMING_SERVE_TOKEN";
+
+/// Body of `POST /v1/chat/completions`. Fields the Messages API has no
+/// counterpart for are ignored.
+#[derive(Debug, Deserialize)]
+struct ChatCompletionRequest {
+    #[serde(default)]
+    model: String,
+    messages: Vec<ChatMessage>,
+    #[serde(default)]
+    stream: bool,
+    stream_options: Option<StreamOptions>,
+    max_tokens: Option<u32>,
+    max_completion_tokens: Option<u32>,
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    stop: Option<Stop>,
+    #[serde(default)]
+    tools: Vec<FunctionTool>,
+    tool_choice: Option<Value>,
+    user: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamOptions {
+    #[serde(default)]
+    include_usage: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Stop {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatMessage {
+    role: String,
+    content: Option<Content>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCall>,
+    tool_call_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Content {
+    Text(String),
+    Parts(Vec<Part>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Part {
+    Text {
+        text: String,
+    },
+    ImageUrl {
+        image_url: ImageUrl,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImageUrl {
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolCall {
+    id: String,
+    function: FunctionCall,
+}
+
+#[derive(Debug, Deserialize)]
+struct FunctionCall {
+    name: String,
+    /// JSON encoded
+    arguments: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct FunctionTool {
+    function: FunctionDefinition,
+}
+
+#[derive(Debug, Deserialize)]
+struct FunctionDefinition {
+    name: String,
+    description: Option<String>,
+    #[serde(default = "empty_schema")]
+    parameters: Value,
+}
+
+fn empty_schema() -> Value {
+    json!({"type": "object", "properties": {}})
+}
+
+impl Content {
+    fn blocks(self) -> Vec<ContentBlock> {
+        match self {
+            Content::Text(text) if text.is_empty() => Vec::new(),
+            Content::Text(text) => vec![ContentBlock::text(text)],
+            Content::Parts(parts) => parts
+                .into_iter()
+                .filter_map(|part| match part {
+                    Part::Text { text } => Some(ContentBlock::text(text)),
+                    Part::ImageUrl { image_url } => Some(ContentBlock::Image {
+                        source: image_source(image_url.url),
+                    }),
+                    Part::Other => None,
+                })
+                .collect(),
+        }
+    }
+
+    fn text(self) -> String {
+        self.blocks()
+            .iter()
+            .filter_map(ContentBlock::as_text)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// `data:` URLs are sent inline, anything else by reference.
+fn image_source(url: String) -> Source {
+    let inline = url
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(";base64,"));
+    match inline {
+        Some((media_type, data)) => Source::Base64 {
+            media_type: media_type.to_string(),
+            data: data.to_string(),
+        },
+        None => Source::Url { url },
+    }
+}
+
+fn tool_choice(choice: &Value) -> Option<ToolChoice> {
+    match choice.as_str() {
+        Some("auto") => Some(ToolChoice::Auto),
+        Some("required") => Some(ToolChoice::Any),
+        Some("none") => Some(ToolChoice::None),
+        Some(_) => None,
+        None => choice
+            .pointer("/function/name")
+            .and_then(Value::as_str)
+            .map(|name| ToolChoice::Tool {
+                name: name.to_string(),
+            }),
+    }
+}
+
+impl ChatCompletionRequest {
+    /// Translates to a Messages API request, taking whatever the client
+    /// leaves out from `config`.
+    fn into_request(self, config: &Config) -> Result<MessageRequest, ConduitError> {
+        let mut system = Vec::new();
+        let mut messages: Vec<Message> = Vec::new();
+        for message in self.messages {
+            let (role, content) = match message.role.as_str() {
+                "system" | "developer" => {
+                    system.extend(message.content.map(Content::text));
+                    continue;
+                }
+                "user" => (Role::User, message.content.map(Content::blocks)),
+                "assistant" => {
+                    let mut content = message.content.map(Content::blocks).unwrap_or_default();
+                    content.extend(message.tool_calls.into_iter().map(|call| {
+                        ContentBlock::ToolUse {
+                            id: call.id,
+                            name: call.function.name,
+                            input: serde_json::from_str(&call.function.arguments)
+                                .unwrap_or_else(|_| json!({})),
+                        }
+                    }));
+                    (Role::Assistant, Some(content))
+                }
+                "tool" => (
+                    Role::User,
+                    Some(vec![ContentBlock::ToolResult {
+                        tool_use_id: message.tool_call_id.unwrap_or_default(),
+                        content: message.content.map(Content::blocks).unwrap_or_default(),
+                        is_error: false,
+                    }]),
+                ),
+                other => {
+                    return Err(ConduitError::InvalidRequest {
+                        field: Some("messages".to_string()),
+                        message: format!("unsupported role {}", other),
+                    })
+                }
+            };
+            let content = content.unwrap_or_default();
+            if content.is_empty() {
+                continue;
+            }
+            // Turns have to alternate, so consecutive tool results and
+            // messages from the same side become one turn
+            match messages.last_mut() {
+                Some(last) if last.role == role => last.content.extend(content),
+                _ => messages.push(Message { role, content }),
+            }
+        }
+
+        let mut request = config.anthropic.request(messages);
+        // Clients preconfigured for OpenAI models get the configured one
+        if self.model.starts_with("claude") {
+            request.model = self.model.parse().unwrap_or_else(|never| match never {});
+        }
+        if let Some(max_tokens) = self.max_completion_tokens.or(self.max_tokens) {
+            // Thinking counts against max_tokens as well
+            request.max_tokens = match request.thinking {
+                Some(ThinkingConfig::Enabled { budget_tokens }) => {
+                    max_tokens.saturating_add(budget_tokens)
+                }
+                _ => max_tokens,
+            };
+        }
+        if !system.is_empty() {
+            request.system = Some(system.join("\n\n"));
+        }
+        let sampling = Sampling {
+            // OpenAI temperatures go up to 2, Anthropic's up to 1
+            temperature: self.temperature.map(|t| t.min(1.0)),
+            top_p: self.top_p,
+            top_k: None,
+            stop_sequences: match self.stop {
+                None => Vec::new(),
+                Some(Stop::One(stop)) => vec![stop],
+                Some(Stop::Many(stops)) => stops,
+            },
+        };
+        request.sampling = request.sampling.merge(&sampling);
+        if !self.tools.is_empty() {
+            request.tools = self
+                .tools
+                .into_iter()
+                .map(|tool| Tool {
+                    name: tool.function.name,
+                    description: tool.function.description,
+                    input_schema: tool.function.parameters,
+                })
+                .collect();
+            request.tool_choice = self.tool_choice.as_ref().and_then(tool_choice);
+        }
+        request.metadata = self.user.map(|user| Metadata {
+            user_id: Some(user),
+        });
+        Ok(request)
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn finish_reason(reason: StopReason) -> &'static str {
+    match reason {
+        StopReason::MaxTokens => "length",
+        StopReason::ToolUse => "tool_calls",
+        StopReason::Refusal => "content_filter",
+        _ => "stop",
+    }
+}
+
+fn usage_json(usage: &Usage) -> Value {
+    let prompt =
+        usage.input_tokens + usage.cache_creation_input_tokens + usage.cache_read_input_tokens;
+    json!({
+        "prompt_tokens": prompt,
+        "completion_tokens": usage.output_tokens,
+        "total_tokens": prompt + usage.output_tokens,
+        "prompt_tokens_details": {"cached_tokens": usage.cache_read_input_tokens},
+    })
+}
+
+/// A `chat.completion` object for a complete response.
+fn completion(response: MessageResponse) -> Value {
+    let text = response.text();
+    let mut reasoning = String::new();
+    let mut tool_calls = Vec::new();
+    for block in &response.content {
+        match block {
+            ContentBlock::Thinking { thinking, .. } => reasoning.push_str(thinking),
+            ContentBlock::ToolUse { id, name, input } => tool_calls.push(json!({
+                "id": id,
+                "type": "function",
+                "function": {"name": name, "arguments": input.to_string()},
+            })),
+            _ => {}
+        }
+    }
+
+    let mut message = json!({"role": "assistant", "content": text});
+    if text.is_empty() && !tool_calls.is_empty() {
+        message["content"] = Value::Null;
+    }
+    if !tool_calls.is_empty() {
+        message["tool_calls"] = tool_calls.into();
+    }
+    // The field DeepSeek's API uses for reasoning
+    if !reasoning.is_empty() {
+        message["reasoning_content"] = reasoning.into();
+    }
+    json!({
+        "id": format!("chatcmpl-{}", response.id),
+        "object": "chat.completion",
+        "created": unix_time(),
+        "model": response.model.id(),
+        "choices": [{
+            "index": 0,
+            "message": message,
+            "finish_reason": response.stop_reason.map(finish_reason),
+        }],
+        "usage": usage_json(&response.usage),
+    })
+}
+
+fn chunk(id: &str, model: &str, created: u64, delta: Value, finish: Option<&str>) -> Event {
+    let chunk = json!({
+        "id": id,
+        "object": "chat.completion.chunk",
+        "created": created,
+        "model": model,
+        "choices": [{"index": 0, "delta": delta, "finish_reason": finish}],
+    });
+    Event::new(chunk.to_string())
+}
+
+/// Forwards Messages API events to `tx` as `chat.completion.chunk` events,
+/// ending with `[DONE]`. Stops early if the client goes away.
+async fn relay<S>(events: S, tx: mpsc::Sender<Event>, include_usage: bool)
+where
+    S: Stream<Item = Result<StreamEvent, ConduitError>>,
+{
+    let mut events = Box::pin(events);
+    let created = unix_time();
+    let (mut id, mut model) = (String::new(), String::new());
+    let mut usage = Usage::default();
+    let mut finish = "stop";
+    // Content block index of each tool call, in call order
+    let mut tool_calls = Vec::new();
+
+    while let Some(event) = events.next().await {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                eprintln!("Stream error: {}", e);
+                let _ = tx.send(Event::new(error_json(&e).1.to_string())).await;
+                return;
+            }
+        };
+        usage.record(&event);
+        let delta = match &event {
+            StreamEvent::MessageStart { message } => {
+                id = format!("chatcmpl-{}", message.id);
+                model = message.model.id().to_string();
+                Some(json!({"role": "assistant", "content": ""}))
+            }
+            StreamEvent::ContentBlockStart {
+                index,
+                content_block: ContentBlock::ToolUse { id, name, .. },
+            } => {
+                tool_calls.push(*index);
+                Some(json!({"tool_calls": [{
+                    "index": tool_calls.len() - 1,
+                    "id": id,
+                    "type": "function",
+                    "function": {"name": name, "arguments": ""},
+                }]}))
+            }
+            StreamEvent::ContentBlockDelta {
+                index,
+                delta: Delta::InputJsonDelta { partial_json },
+            } => tool_calls.iter().position(|i| i == index).map(|call| {
+                json!({"tool_calls": [{"index": call, "function": {"arguments": partial_json}}]})
+            }),
+            StreamEvent::MessageDelta { delta, .. } => {
+                finish = delta.stop_reason.map_or("stop", finish_reason);
+                None
+            }
+            StreamEvent::MessageStop => break,
+            event => match (event.text(), event.thinking()) {
+                (Some(text), _) => Some(json!({"content": text})),
+                (_, Some(thinking)) => Some(json!({"reasoning_content": thinking})),
+                _ => None,
+            },
+        };
+        if let Some(delta) = delta {
+            if tx
+                .send(chunk(&id, &model, created, delta, None))
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+
+    let _ = tx
+        .send(chunk(&id, &model, created, json!({}), Some(finish)))
+        .await;
+    if include_usage {
+        let chunk = json!({
+            "id": id,
+            "object": "chat.completion.chunk",
+            "created": created,
+            "model": model,
+            "choices": [],
+            "usage": usage_json(&usage),
+        });
+        let _ = tx.send(Event::new(chunk.to_string())).await;
+    }
+    let _ = tx.send(Event::new("[DONE]")).await;
+}
+
+/// The status and OpenAI style error body for a failed request.
+fn error_json(error: &ConduitError) -> (StatusCode, Value) {
+    let (status, kind) = match error {
+        ConduitError::AuthenticationFailed { .. } => {
+            (StatusCode::UNAUTHORIZED, "authentication_error")
+        }
+        ConduitError::RateLimited { .. } => (StatusCode::TOO_MANY_REQUESTS, "rate_limit_error"),
+        ConduitError::Overloaded { .. } => (StatusCode::SERVICE_UNAVAILABLE, "overloaded_error"),
+        ConduitError::ContextTooLong { .. } | ConduitError::InvalidRequest { .. } => {
+            (StatusCode::BAD_REQUEST, "invalid_request_error")
+        }
+        ConduitError::Api {
+            status: Some(status),
+            ..
+        } => (*status, "api_error"),
+        ConduitError::Network(_) => (StatusCode::BAD_GATEWAY, "api_error"),
+        ConduitError::Timeout => (StatusCode::GATEWAY_TIMEOUT, "timeout"),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, "api_error"),
+    };
+    let body = json!({"error": {"message": error.to_string(), "type": kind, "code": null}});
+    (status, body)
+}
+
+fn error_response(error: &ConduitError) -> Response<ResponseBody> {
+    let (status, body) = error_json(error);
+    let mut response = ServerResponse::new(status);
+    if let Some(after) = error.retry_after() {
+        response = response.header("retry-after", after.as_secs().max(1).to_string());
+    }
+    response.json(&body)
+}
+
+/// An OpenAI style error for a request turned away before reaching the API.
+fn reject(status: StatusCode, message: &str) -> Response<ResponseBody> {
+    let body =
+        json!({"error": {"message": message, "type": "invalid_request_error", "code": null}});
+    ServerResponse::new(status).json(&body)
+}
+
+/// Whether a `Host` or `Origin` authority names this machine: `localhost`, a
+/// loopback address or the address being served on.
+fn is_local(authority: &str, addr: &SocketAddr) -> bool {
+    let host = match authority.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => authority.split(':').next().unwrap_or_default(),
+    };
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback() || ip == addr.ip())
+}
+
+/// Whether `req` comes from a local client rather than a browser: a `Host`
+/// that is not this machine means DNS rebinding, and a foreign `Origin` a
+/// page on another site.
+fn from_local_client(req: &Request<RequestBody>, addr: &SocketAddr) -> bool {
+    let header = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+    let host = header("host").is_some_and(|host| is_local(host, addr));
+    let origin = header("origin").is_none_or(|origin| {
+        origin
+            .strip_prefix("http://")
+            .or_else(|| origin.strip_prefix("https://"))
+            .is_some_and(|origin| is_local(origin, addr))
+    });
+    host && origin
+}
+
+async fn local_only(
+    req: Request<RequestBody>,
+    next: Next,
+    addr: SocketAddr,
+) -> Response<ResponseBody> {
+    if !from_local_client(&req, &addr) {
+        return reject(StatusCode::FORBIDDEN, "only local clients are allowed");
+    }
+    next.run(req).await
+}
+
+struct Proxy {
+    config: Config,
+    conduit: Conduit,
+}
+
+impl Proxy {
+    /// Runs `send` again after rate limits and overloads, the way the chat
+    /// window does.
+    async fn retry<T, F, Fut>(&self, mut send: F) -> Result<T, ConduitError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, ConduitError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match send().await {
+                Err(e) => match self.conduit.retry_delay(&e, attempt) {
+                    Some(delay) => {
+                        eprintln!("Retrying in {:?}: {}", delay, e);
+                        tokio::time::sleep(delay).await;
+                        attempt += 1;
+                    }
+                    None => return Err(e),
+                },
+                result => return result,
+            }
+        }
+    }
+
+    async fn chat_completions(
+        self: Arc<Self>,
+        req: Request<RequestBody>,
+    ) -> Response<ResponseBody> {
+        let invalid = |message: String| {
+            error_response(&ConduitError::InvalidRequest {
+                field: None,
+                message,
+            })
+        };
+        let json = req
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .is_some_and(|media| media.trim().eq_ignore_ascii_case("application/json"));
+        if !json {
+            return reject(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Content-Type must be application/json",
+            );
+        }
+        let body = match req.into_body().collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(e) => return invalid(e.to_string()),
+        };
+        let chat: ChatCompletionRequest = match serde_json::from_slice(&body) {
+            Ok(chat) => chat,
+            Err(e) => return invalid(e.to_string()),
+        };
+        let stream = chat.stream;
+        let include_usage = chat
+            .stream_options
+            .as_ref()
+            .is_some_and(|o| o.include_usage);
+        let request = match chat.into_request(&self.config) {
+            Ok(request) => request,
+            Err(e) => return error_response(&e),
+        };
+
+        if !stream {
+            return match self.retry(|| self.conduit.create_message(&request)).await {
+                Ok(response) => ServerResponse::ok().json(&completion(response)),
+                Err(e) => error_response(&e),
+            };
+        }
+
+        // Open the stream before answering, so failures get a real status
+        let (opened_tx, opened) = oneshot::channel();
+        let (tx, sse) = Sse::channel();
+        let proxy = Arc::clone(&self);
+        tokio::spawn(async move {
+            match proxy.retry(|| proxy.conduit.stream(&request)).await {
+                Ok(events) => {
+                    let _ = opened_tx.send(Ok(()));
+                    relay(events, tx, include_usage).await;
+                }
+                Err(e) => {
+                    let _ = opened_tx.send(Err(e));
+                }
+            }
+        });
+        match opened.await {
+            Ok(Ok(())) => sse.keep_alive(Duration::from_secs(15)).into_response(),
+            Ok(Err(e)) => error_response(&e),
+            Err(_) => error_response(&ConduitError::EmptyResponse),
+        }
+    }
+
+    fn models(&self) -> Response<ResponseBody> {
+        let configured: ClaudeModel = self
+            .config
+            .anthropic
+            .model
+            .parse()
+            .unwrap_or_else(|never| match never {});
+        let mut ids = vec![configured.id()];
+        ids.extend(
+            ClaudeModel::KNOWN
+                .iter()
+                .map(ClaudeModel::id)
+                .filter(|id| *id != configured.id()),
+        );
+        let data: Vec<Value> = ids
+            .into_iter()
+            .map(|id| json!({"id": id, "object": "model", "created": 0, "owned_by": "anthropic"}))
+            .collect();
+        ServerResponse::ok().json(&json!({"object": "list", "data": data}))
+    }
+}
+
+/// Serves `/v1/chat/completions` and `/v1/models` on `addr` until Ctrl-C.
+pub async fn run(config: Config, addr: SocketAddr) -> Result<(), Box<dyn Error>> {
+    let proxy = Arc::new(Proxy {
+        conduit: Conduit::new(config.anthropic.api_key.clone()),
+        config,
+    });
+    let completions = Arc::clone(&proxy);
+    let router = Router::new()
+        .post("/v1/chat/completions", move |req| {
+            Arc::clone(&completions).chat_completions(req)
+        })
+        .get("/v1/models", move |_| {
+            let response = proxy.models();
+            async move { response }
+        });
+
+    let token = std::env::var(TOKEN_VAR)
+        .ok()
+        .filter(|token| !token.is_empty())
+        .unwrap_or_else(hyperax::random::hex::<16>);
+    let mut server = Server::new(addr)
+        .layer(Logger)
+        .layer(move |req, next| local_only(req, next, addr))
+        .layer(BearerAuth::new(token.clone()))
+        .layer(BodyLimit::new(BODY_LIMIT));
+    let local = server.bind().await?;
+    eprintln!("Serving an OpenAI-compatible API at http://{}/v1", local);
+    eprintln!("API key: {} (set {} to keep it)", token, TOKEN_VAR);
+
+    let (stop, shutdown) = watch::channel(false);
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            let _ = stop.send(true);
+        }
+    });
+    server.run(router.into_handler(), shutdown).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+
+    fn chat(body: Value) -> ChatCompletionRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn test_into_request() {
+        let config = Config::default();
+        let request = chat(json!({
+            "model": "gpt-4o",
+            "max_tokens": 100,
+            "messages": [
+                {"role": "system", "content": "Be brief."},
+                {"role": "developer", "content": "Use metric units."},
+                {"role": "user", "content": "Weather in Oslo"},
+                {"role": "user", "content": "and Bergen?"},
+                {"role": "assistant", "content": null, "tool_calls": [
+                    {"id": "call_1", "type": "function",
+                     "function": {"name": "weather", "arguments": "{\"city\":\"Oslo\"}"}},
+                    {"id": "call_2", "type": "function",
+                     "function": {"name": "weather", "arguments": "not json"}},
+                ]},
+                {"role": "tool", "tool_call_id": "call_1", "content": "4°C"},
+                {"role": "tool", "tool_call_id": "call_2", "content": "7°C"},
+            ],
+        }))
+        .into_request(&config)
+        .unwrap();
+
+        // Clients preconfigured for OpenAI get the configured model
+        assert_eq!(request.model, config.anthropic.request(Vec::new()).model);
+        assert_eq!(request.max_tokens, 100);
+        assert_eq!(
+            request.system.as_deref(),
+            Some("Be brief.\n\nUse metric units.")
+        );
+        assert_eq!(request.messages.len(), 3);
+        assert_eq!(request.messages[0].role, Role::User);
+        assert_eq!(request.messages[0].content.len(), 2);
+        assert_eq!(
+            request.messages[1].content,
+            [
+                ContentBlock::ToolUse {
+                    id: "call_1".into(),
+                    name: "weather".into(),
+                    input: json!({"city": "Oslo"}),
+                },
+                ContentBlock::ToolUse {
+                    id: "call_2".into(),
+                    name: "weather".into(),
+                    input: json!({}),
+                },
+            ]
+        );
+        // Both results go back in a single user turn
+        assert_eq!(request.messages[2].role, Role::User);
+        assert!(matches!(
+            &request.messages[2].content[1],
+            ContentBlock::ToolResult { tool_use_id, .. } if tool_use_id == "call_2"
+        ));
+
+        let error = chat(json!({"messages": [{"role": "narrator", "content": "Once"}]}))
+            .into_request(&config)
+            .unwrap_err();
+        assert!(matches!(error, ConduitError::InvalidRequest { .. }));
+    }
+
+    #[test]
+    fn test_into_request_limits() {
+        let mut config = Config::default();
+        config.anthropic.thinking_budget = Some(2048);
+        let request = chat(json!({
+            "model": "claude-3-opus-latest",
+            "max_completion_tokens": u32::MAX,
+            "temperature": 1.5,
+            "stop": "END",
+            "messages": [{"role": "user", "content": "Hi"}],
+        }))
+        .into_request(&config)
+        .unwrap();
+
+        assert_eq!(request.model.id(), "claude-3-opus-latest");
+        assert_eq!(request.max_tokens, u32::MAX);
+        assert_eq!(request.sampling.temperature, Some(1.0));
+        assert_eq!(request.sampling.stop_sequences, ["END"]);
+    }
+
+    #[test]
+    fn test_tool_choice() {
+        assert_eq!(tool_choice(&json!("auto")), Some(ToolChoice::Auto));
+        assert_eq!(tool_choice(&json!("required")), Some(ToolChoice::Any));
+        assert_eq!(tool_choice(&json!("none")), Some(ToolChoice::None));
+        assert_eq!(tool_choice(&json!("sometimes")), None);
+        assert_eq!(
+            tool_choice(&json!({"type": "function", "function": {"name": "weather"}})),
+            Some(ToolChoice::Tool {
+                name: "weather".into()
+            })
+        );
+    }
+
+    #[test]
+    fn test_image_source() {
+        assert_eq!(
+            image_source("data:image/png;base64,iVBORw0".into()),
+            Source::Base64 {
+                media_type: "image/png".into(),
+                data: "iVBORw0".into(),
+            }
+        );
+        assert_eq!(
+            image_source("https://example.com/cat.png".into()),
+            Source::Url {
+                url: "https://example.com/cat.png".into()
+            }
+        );
+    }
+
+    #[test]
+    fn test_completion() {
+        let response: MessageResponse = serde_json::from_value(json!({
+            "id": "msg_1",
+            "model": "claude-3-5-sonnet-latest",
+            "role": "assistant",
+            "content": [
+                {"type": "thinking", "thinking": "Need the weather.", "signature": "sig"},
+                {"type": "tool_use", "id": "toolu_1", "name": "weather", "input": {"city": "Oslo"}},
+            ],
+            "stop_reason": "tool_use",
+            "usage": {"input_tokens": 10, "output_tokens": 5, "cache_read_input_tokens": 20},
+        }))
+        .unwrap();
+        let completion = completion(response);
+
+        assert_eq!(completion["id"], "chatcmpl-msg_1");
+        let choice = &completion["choices"][0];
+        assert_eq!(choice["finish_reason"], "tool_calls");
+        assert_eq!(choice["message"]["content"], Value::Null);
+        assert_eq!(choice["message"]["reasoning_content"], "Need the weather.");
+        assert_eq!(
+            choice["message"]["tool_calls"][0]["function"],
+            json!({"name": "weather", "arguments": "{\"city\":\"Oslo\"}"})
+        );
+        assert_eq!(completion["usage"]["prompt_tokens"], 30);
+        assert_eq!(completion["usage"]["total_tokens"], 35);
+    }
+
+    #[test]
+    fn test_local_clients() {
+        let addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        assert!(is_local("localhost:8080", &addr));
+        assert!(is_local("127.0.0.1", &addr));
+        assert!(is_local("[::1]:8080", &addr));
+        assert!(!is_local("example.com:8080", &addr));
+        assert!(!is_local("127.0.0.1.example.com", &addr));
+
+        let lan: SocketAddr = "192.168.1.5:8080".parse().unwrap();
+        assert!(is_local("192.168.1.5:8080", &lan));
+        assert!(!is_local("192.168.1.6:8080", &lan));
+    }
+
+    #[tokio::test]
+    async fn test_relay() {
+        let events = [
+            json!({"type": "message_start", "message": {"id": "msg_1", "role": "assistant",
+                "model": "claude-3-5-sonnet-latest", "content": [], "stop_reason": null,
+                "usage": {"input_tokens": 10, "output_tokens": 1}}}),
+            json!({"type": "content_block_start", "index": 0,
+                "content_block": {"type": "text", "text": ""}}),
+            json!({"type": "content_block_delta", "index": 0,
+                "delta": {"type": "text_delta", "text": "Checking"}}),
+            json!({"type": "content_block_start", "index": 1,
+                "content_block": {"type": "tool_use", "id": "toolu_1", "name": "weather", "input": {}}}),
+            json!({"type": "content_block_delta", "index": 1,
+                "delta": {"type": "input_json_delta", "partial_json": "{\"city\":"}}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "tool_use"},
+                "usage": {"output_tokens": 12}}),
+            json!({"type": "message_stop"}),
+        ];
+        let events =
+            stream::iter(events.map(|event| {
+                Ok::<StreamEvent, ConduitError>(serde_json::from_value(event).unwrap())
+            }));
+        let (tx, mut rx) = mpsc::channel(16);
+        relay(events, tx, true).await;
+
+        let mut chunks = Vec::new();
+        while let Some(event) = rx.recv().await {
+            let bytes = event.to_bytes();
+            let data = std::str::from_utf8(&bytes).unwrap();
+            chunks.push(data.trim_end().strip_prefix("data: ").unwrap().to_string());
+        }
+        assert_eq!(chunks.pop().as_deref(), Some("[DONE]"));
+        let chunks: Vec<Value> = chunks
+            .iter()
+            .map(|chunk| serde_json::from_str(chunk).unwrap())
+            .collect();
+        let deltas: Vec<&Value> = chunks
+            .iter()
+            .filter_map(|chunk| chunk["choices"].get(0))
+            .map(|choice| &choice["delta"])
+            .collect();
+
+        assert!(chunks.iter().all(|chunk| chunk["id"] == "chatcmpl-msg_1"));
+        assert_eq!(deltas[0]["role"], "assistant");
+        assert_eq!(deltas[1]["content"], "Checking");
+        assert_eq!(deltas[2]["tool_calls"][0]["id"], "toolu_1");
+        assert_eq!(
+            deltas[3]["tool_calls"][0],
+            json!({"index": 0, "function": {"arguments": "{\"city\":"}})
+        );
+        assert_eq!(*deltas[4], json!({}));
+        assert_eq!(chunks[4]["choices"][0]["finish_reason"], "tool_calls");
+        // The usage chunk comes last, without choices
+        assert_eq!(chunks[5]["choices"], json!([]));
+        assert_eq!(chunks[5]["usage"]["completion_tokens"], 12);
+        assert_eq!(chunks.len(), 6);
+    }
+}
@@ -0,0 +1,135 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Conversations saved as one JSON file each under the user's data
+//! directory.
+
+use conduit::{Sampling, Usage};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Conversation {
+    /// Creation time in milliseconds since the epoch, which keeps file
+    /// names unique and sortable.
+    pub id: String,
+    pub messages: Vec<StoredMessage>,
+    /// Overrides of the configured sampling parameters.
+    #[serde(default)]
+    pub sampling: Sampling,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredMessage {
+    pub is_user: bool,
+    pub content: String,
+    /// Kept when older history is trimmed to fit the context window.
+    #[serde(default)]
+    pub pinned: bool,
+    /// Extended thinking that preceded an assistant message.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub reasoning: String,
+    /// Model that wrote an assistant message.
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub usage: Option<Usage>,
+    /// Cost in US dollars at the prices in effect when it was sent.
+    #[serde(default)]
+    pub cost: Option<f64>,
+}
+
+impl Conversation {
+    pub fn new() -> Self {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        Self {
+            id: millis.to_string(),
+            messages: Vec::new(),
+            sampling: Sampling::default(),
+        }
+    }
+}
+
+impl StoredMessage {
+    pub fn new(content: impl Into<String>, is_user: bool) -> Self {
+        Self {
+            is_user,
+            content: content.into(),
+            pinned: false,
+            reasoning: String::new(),
+            model: None,
+            usage: None,
+            cost: None,
+        }
+    }
+}
+
+pub struct ConversationStore {
+    dir: PathBuf,
+}
+
+impl ConversationStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// `$XDG_DATA_HOME/llming/conversations`, falling back to
+    /// `~/.local/share`.
+    pub fn open_default() -> Self {
+        let data = std::env::var("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(utils::get_home_dir()).join(".local/share"));
+        Self::new(data.join("llming").join("conversations"))
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    /// Writes `conversation`, replacing any earlier save atomically.
+    pub fn save(&self, conversation: &Conversation) -> io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let json = serde_json::to_vec_pretty(conversation)?;
+        let path = self.path(&conversation.id);
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)?;
+        std::fs::rename(tmp, path)
+    }
+
+    pub fn load(&self, id: &str) -> io::Result<Conversation> {
+        let json = std::fs::read(self.path(id))?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    /// Saved conversation ids, newest first.
+    pub fn list(&self) -> io::Result<Vec<String>> {
+        let mut ids = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| {
+                    let path = entry.ok()?.path();
+                    if path.extension()? != "json" {
+                        return None;
+                    }
+                    Some(path.file_stem()?.to_str()?.to_string())
+                })
+                .collect::<Vec<_>>(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        // Ids are millisecond timestamps
+        ids.sort_by_key(|id| std::cmp::Reverse(id.parse::<u128>().unwrap_or(0)));
+        Ok(ids)
+    }
+
+    /// The most recently started conversation, if any.
+    pub fn latest(&self) -> io::Result<Option<Conversation>> {
+        match self.list()?.first() {
+            Some(id) => self.load(id).map(Some),
+            None => Ok(None),
+        }
+    }
+}